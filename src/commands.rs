@@ -0,0 +1,300 @@
+//! What each subcommand does, and how its outcome is printed.
+//!
+//! Exit codes: 0 on success, 1 when the command ran but the answer is "no"
+//! (a refused transaction, an invalid chain, an unknown block), 2 for usage
+//! errors, and 3 when a file or the node could not be reached.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+use decentralized_ledger::models::topology::TopologyError;
+use decentralized_ledger::models::wallet::{Wallet, WalletError};
+use decentralized_ledger::models::{Block, Blockchain, Network, hex};
+use decentralized_ledger::rpc::{self, ClientError, RpcClient, RpcServer};
+use decentralized_ledger::scenario::{Scenario, ScenarioError};
+
+pub struct Output {
+    pub text: String,
+    pub json: Value,
+}
+
+pub enum Failure {
+    /// The command ran but the answer is negative.
+    Failed(String),
+    /// A file or the node could not be reached.
+    Io(String),
+    /// The command ran and its full output explains what failed.
+    Report(Output),
+}
+
+impl From<ClientError> for Failure {
+    fn from(error: ClientError) -> Self {
+        match error {
+            ClientError::Rpc(error) => Failure::Failed(error.message),
+            other => Failure::Io(other.to_string()),
+        }
+    }
+}
+
+pub type CommandResult = Result<Output, Failure>;
+
+pub fn report(result: CommandResult, json: bool) -> ExitCode {
+    let (output, code) = match result {
+        Ok(output) => (output, 0),
+        Err(Failure::Report(output)) => (output, 1),
+        Err(Failure::Failed(message)) => (error_output(message), 1),
+        Err(Failure::Io(message)) => (error_output(message), 3),
+    };
+    if json {
+        println!("{}", output.json);
+    } else if code == 0 {
+        println!("{}", output.text);
+    } else {
+        eprintln!("{}", output.text);
+    }
+    ExitCode::from(code)
+}
+
+fn error_output(message: String) -> Output {
+    Output {
+        text: format!("error: {}", message),
+        json: json!({ "error": message }),
+    }
+}
+
+/// Settings for `node run`, read from a TOML file. Command-line flags win.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct NodeConfig {
+    id: String,
+    difficulty: usize,
+    rpc: String,
+    mine: bool,
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        NodeConfig {
+            id: "node".to_string(),
+            difficulty: 2,
+            rpc: rpc::DEFAULT_ADDRESS.to_string(),
+            mine: false,
+        }
+    }
+}
+
+pub async fn run_node(
+    config_file: Option<PathBuf>,
+    id: Option<String>,
+    difficulty: Option<usize>,
+    mine: bool,
+    rpc_address: Option<String>,
+    json: bool,
+) -> CommandResult {
+    let mut config = match config_file {
+        Some(path) => {
+            let text = read(&path)?;
+            toml::from_str(&text).map_err(|e| Failure::Failed(format!("{}: {}", path.display(), e)))?
+        }
+        None => NodeConfig::default(),
+    };
+    config.id = id.unwrap_or(config.id);
+    config.difficulty = difficulty.unwrap_or(config.difficulty);
+    config.rpc = rpc_address.unwrap_or(config.rpc);
+    config.mine |= mine;
+
+    let network = Network::new();
+    network.add_node(config.id.clone(), config.difficulty).await;
+    let node = network.handle(&config.id).expect("node was just added");
+    let server = RpcServer::bind(&config.rpc, node.clone())
+        .await
+        .map_err(|e| Failure::Io(format!("could not listen on {}: {}", config.rpc, e)))?;
+    if config.mine {
+        node.set_mining(true).map_err(|e| Failure::Failed(e.to_string()))?;
+    }
+
+    let address = server.address();
+    if json {
+        println!("{}", json!({ "id": config.id, "rpc": address.to_string(), "mining": config.mine }));
+    } else {
+        println!("Node {} serving JSON-RPC on {}", config.id, address);
+    }
+    let _ = std::io::stdout().flush();
+
+    tokio::signal::ctrl_c()
+        .await
+        .map_err(|e| Failure::Io(format!("could not wait for interrupt: {}", e)))?;
+    drop(server);
+    let relay = node.status().await.map(|status| status.relay).unwrap_or_default();
+    Ok(Output {
+        text: format!(
+            "Node {} stopped after accepting {} blocks and {} chains from peers, banning {}",
+            config.id, relay.blocks_accepted, relay.chains_adopted, relay.peers_banned
+        ),
+        json: json!({
+            "id": config.id,
+            "stopped": true,
+            "blocks_accepted": relay.blocks_accepted,
+            "chains_adopted": relay.chains_adopted,
+            "peers_banned": relay.peers_banned,
+        }),
+    })
+}
+
+/// Submits a transfer, signed with the sender's key if a keystore is given
+/// and unsigned otherwise, which only named placeholder accounts may send.
+pub async fn send_transaction(
+    client: &RpcClient,
+    sender: String,
+    receiver: String,
+    amount: f64,
+    keystore: Option<PathBuf>,
+) -> CommandResult {
+    let result = match keystore {
+        Some(path) => {
+            let wallet = Wallet::load(&path, &password()?).map_err(|e| match e {
+                WalletError::Io(_) => Failure::Io(format!("{}: {}", path.display(), e)),
+                _ => Failure::Failed(format!("{}: {}", path.display(), e)),
+            })?;
+            let transaction = wallet
+                .build_transaction(&sender, &receiver, amount)
+                .map_err(|e| Failure::Failed(format!("{}: {}", sender, e)))?;
+            client.call("submit_raw_transaction", json!({ "transaction": transaction })).await?
+        }
+        None => {
+            let params = json!({ "sender": sender, "receiver": receiver, "amount": amount });
+            client.call("submit_transaction", params).await?
+        }
+    };
+    Ok(Output {
+        text: format!("Submitted {} -> {} : {} ({})", sender, receiver, amount, result["hash"].as_str().unwrap_or("")),
+        json: result,
+    })
+}
+
+pub async fn balance(client: &RpcClient, address: String) -> CommandResult {
+    let result = client.call("get_balance", json!({ "address": address })).await?;
+    Ok(Output {
+        text: format!("{}: {}", address, result["balance"]),
+        json: result,
+    })
+}
+
+pub async fn show_block(client: &RpcClient, block: &str) -> CommandResult {
+    let params = if let Ok(height) = block.parse::<u64>() {
+        json!({ "height": height })
+    } else if hex::decode_hash(block).is_some() {
+        json!({ "hash": block })
+    } else {
+        return Err(Failure::Failed(format!("expected a height or a 64-digit hash, got {}", block)));
+    };
+    let result = client.call("get_block", params).await?;
+    let block: Block = serde_json::from_value(result.clone()).map_err(|e| Failure::Io(e.to_string()))?;
+
+    let mut text = format!(
+        "Block #{}\n  hash:          {}\n  previous hash: {}\n  timestamp:     {}\n  nonce:         {}\n  transactions:  {}",
+        block.index,
+        hex::encode(&block.hash),
+        hex::encode(&block.previous_hash),
+        block.timestamp,
+        block.nonce,
+        block.transactions.len()
+    );
+    for transaction in &block.transactions {
+        text.push_str(&format!("\n    {}", transaction.digest_input()));
+    }
+    Ok(Output { text, json: result })
+}
+
+/// Checks a chain as a node mining at `difficulty` would before adopting it.
+pub async fn validate_chain(client: &RpcClient, file: Option<PathBuf>, difficulty: usize) -> CommandResult {
+    let blocks = match file {
+        Some(path) => read_chain(&path)?,
+        None => serde_json::from_value(client.call("get_chain", Value::Null).await?)
+            .map_err(|e| Failure::Io(e.to_string()))?,
+    };
+    let length = blocks.len();
+    let mut chain = Blockchain::new(difficulty);
+    chain.blocks = Arc::new(blocks);
+    chain
+        .validate_chain()
+        .map_err(|e| Failure::Failed(format!("chain is invalid: {}", e)))?;
+    Ok(Output {
+        text: format!("Chain is valid ({} blocks)", length),
+        json: json!({ "valid": true, "length": length }),
+    })
+}
+
+pub async fn export_chain(client: &RpcClient, file: PathBuf) -> CommandResult {
+    let chain = client.call("get_chain", Value::Null).await?;
+    let length = chain.as_array().map_or(0, Vec::len);
+    let text = serde_json::to_string_pretty(&chain).expect("JSON values always serialize");
+    std::fs::write(&file, text).map_err(|e| Failure::Io(format!("{}: {}", file.display(), e)))?;
+    Ok(Output {
+        text: format!("Exported {} blocks to {}", length, file.display()),
+        json: json!({ "file": file, "length": length }),
+    })
+}
+
+pub async fn import_chain(client: &RpcClient, file: PathBuf) -> CommandResult {
+    let blocks = read_chain(&file)?;
+    let length = blocks.len();
+    let result = client.call("submit_chain", json!({ "blocks": blocks })).await?;
+    if result["adopted"] != json!(true) {
+        return Err(Failure::Failed(
+            "node kept its own chain: the imported one is invalid or not longer".to_string(),
+        ));
+    }
+    Ok(Output {
+        text: format!("Node adopted the {} blocks in {}", length, file.display()),
+        json: json!({ "adopted": true, "length": length }),
+    })
+}
+
+/// Runs a scenario file; fails unless every assertion holds.
+pub async fn simulate(file: PathBuf) -> CommandResult {
+    let scenario = Scenario::load(&file).map_err(|e| match e {
+        ScenarioError::Io(_) | ScenarioError::Topology(TopologyError::Io(_)) => {
+            Failure::Io(format!("{}: {}", file.display(), e))
+        }
+        _ => Failure::Failed(format!("{}: {}", file.display(), e)),
+    })?;
+    let report = scenario
+        .run()
+        .await
+        .map_err(|e| Failure::Failed(format!("{}: {}", file.display(), e)))?;
+    let output = Output {
+        text: report.to_string(),
+        json: json!(report),
+    };
+    if report.passed() {
+        Ok(output)
+    } else {
+        Err(Failure::Report(output))
+    }
+}
+
+/// The keystore password, from `LEDGER_PASSWORD` or else the first line of stdin.
+fn password() -> Result<String, Failure> {
+    if let Ok(password) = std::env::var("LEDGER_PASSWORD") {
+        return Ok(password);
+    }
+    let mut line = String::new();
+    std::io::stdin()
+        .read_line(&mut line)
+        .map_err(|e| Failure::Io(format!("could not read the keystore password: {}", e)))?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+fn read(path: &Path) -> Result<String, Failure> {
+    std::fs::read_to_string(path).map_err(|e| Failure::Io(format!("{}: {}", path.display(), e)))
+}
+
+fn read_chain(path: &Path) -> Result<Vec<Block>, Failure> {
+    serde_json::from_str(&read(path)?).map_err(|e| Failure::Failed(format!("{}: {}", path.display(), e)))
+}
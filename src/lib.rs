@@ -0,0 +1,3 @@
+pub mod models;
+pub mod rpc;
+pub mod scenario;
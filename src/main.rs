@@ -1,136 +1,130 @@
-mod models;
+use std::path::PathBuf;
+use std::process::ExitCode;
 
-use std::sync::Arc;
-use std::time::Duration;
+use clap::{Parser, Subcommand};
 
-use tokio::time::sleep;
+use decentralized_ledger::rpc;
 
-use crate::models::Network;
-use crate::models::Transaction;
-use crate::models::network::DisplayAsync;
+mod commands;
 
-#[tokio::main]
-async fn main() {
-    println!("🚀 Starting Async Blockchain Network Simulation\n");
+/// Run ledger nodes, talk to them over JSON-RPC, and simulate networks.
+#[derive(Parser)]
+#[command(name = "ledger", version)]
+struct Cli {
+    /// Print machine-readable JSON instead of text.
+    #[arg(long, global = true)]
+    json: bool,
 
-    let network = Arc::new(Network::new());
-    let difficulty = 3;
+    /// Address of the node's JSON-RPC API.
+    #[arg(long, global = true, default_value = rpc::DEFAULT_ADDRESS)]
+    rpc: String,
 
-    // Add 4 nodes
-    println!("⚙️  Initializing nodes...");
-    network.add_node("Node_A".to_string(), difficulty).await;
-    network.add_node("Node_B".to_string(), difficulty).await;
-    network.add_node("Node_C".to_string(), difficulty).await;
-    network.add_node("Node_D".to_string(), difficulty).await;
+    #[command(subcommand)]
+    command: Command,
+}
 
-    // Connect nodes in mesh topology
-    network.connect_nodes("Node_A", "Node_B").await;
-    network.connect_nodes("Node_A", "Node_C").await;
-    network.connect_nodes("Node_B", "Node_C").await;
-    network.connect_nodes("Node_B", "Node_D").await;
-    network.connect_nodes("Node_C", "Node_D").await;
+#[derive(Subcommand)]
+enum Command {
+    /// Manage a node.
+    #[command(subcommand)]
+    Node(NodeCommand),
+    /// Submit transactions.
+    #[command(subcommand)]
+    Tx(TxCommand),
+    /// Show an address's balance.
+    Balance { address: String },
+    /// Inspect blocks.
+    #[command(subcommand)]
+    Block(BlockCommand),
+    /// Validate, export and import chains.
+    #[command(subcommand)]
+    Chain(ChainCommand),
+    /// Run a TOML or JSON scenario against an in-process network and check its assertions.
+    Simulate { file: PathBuf },
+}
 
-    println!("✅ Network initialized with 4 nodes\n");
+#[derive(Subcommand)]
+enum NodeCommand {
+    /// Start a node and serve its API until interrupted.
+    Run {
+        /// TOML file with `id`, `difficulty`, `rpc` and `mine` settings.
+        #[arg(long)]
+        config: Option<PathBuf>,
+        #[arg(long)]
+        id: Option<String>,
+        #[arg(long)]
+        difficulty: Option<usize>,
+        /// Mine pending transactions continuously.
+        #[arg(long)]
+        mine: bool,
+    },
+}
 
-    // Add transactions
-    println!("📝 Adding transactions...\n");
-    match network.add_transaction_to_node(
-        "Node_A",
-        Transaction::new("Alice".to_string(), "Bob".to_string(), 10.0),
-    ).await {
-        Ok(()) => println!("✅ Transaction added to Node_A successfully"),
-        Err(e) => println!("❌ Failed to add transaction to Node_A: {:?}", e),
-    }
-    match network.add_transaction_to_node(
-        "Node_B",
-        Transaction::new("Bob".to_string(), "Charlie".to_string(), 5.0),
-    ).await {
-        Ok(()) => println!("✅ Transaction added to Node_B successfully"),
-        Err(e) => println!("❌ Failed to add transaction to Node_B: {:?}", e),
-    }
+#[derive(Subcommand)]
+enum TxCommand {
+    /// Send `amount` from `sender` to `receiver`.
+    Send {
+        sender: String,
+        receiver: String,
+        amount: f64,
+        /// Sign with the sender's key from this wallet keystore. The password is
+        /// read from LEDGER_PASSWORD, or else from the first line of stdin.
+        #[arg(long)]
+        keystore: Option<PathBuf>,
+    },
+}
 
-    // Simulate concurrent mining with async tasks
-    println!("⛏️  Starting concurrent mining simulation...\n");
+#[derive(Subcommand)]
+enum BlockCommand {
+    /// Show a block by height or by hash.
+    Show { block: String },
+}
 
-    let net_a = Arc::clone(&network);
-    let task_a = tokio::spawn(async move {
-        sleep(Duration::from_millis(100)).await;
-        let node_arc_clone = {
-            let nodes = net_a.nodes.read().unwrap();
-            let Some(node_arc) = nodes.get("Node_A") else { return };
-            Arc::clone(&node_arc)
-        };
-        let block = tokio::task::spawn_blocking(move || {
-            let mut node = node_arc_clone.lock().unwrap();
-            node.mine_block()
-        }).await.ok().and_then(|r| r.ok());
-        if let Some(block) = block {
-            println!("[Node_A] ✅ Mined block #{}", block.index);
-            net_a.broadcast_block("Node_A", block).await;
-        }
-    });
+#[derive(Subcommand)]
+enum ChainCommand {
+    /// Check every block's link and proof of work.
+    Validate {
+        /// Validate a chain file instead of the node's chain.
+        #[arg(long)]
+        file: Option<PathBuf>,
+        /// Leading zero bytes each block's hash must have, as in `node run`.
+        #[arg(long, default_value_t = 2)]
+        difficulty: usize,
+    },
+    /// Write the node's chain to a JSON file.
+    Export { file: PathBuf },
+    /// Offer the chain in a JSON file to the node, which adopts it if it is valid and longer.
+    Import { file: PathBuf },
+}
 
-    let net_b = Arc::clone(&network);
-    let task_b = tokio::spawn(async move {
-        sleep(Duration::from_millis(150)).await;
-        let node_arc_clone = {
-            let nodes = net_b.nodes.read().unwrap();
-            let Some(node_arc) = nodes.get("Node_B") else { return };
-            Arc::clone(&node_arc)
-        };
-        let block = tokio::task::spawn_blocking(move || {
-            let mut node = node_arc_clone.lock().unwrap();
-            node.mine_block()
-        }).await.ok().and_then(|r| r.ok());
-        if let Some(block) = block {
-            println!("[Node_B] ✅ Mined block #{}", block.index);
-            net_b.broadcast_block("Node_B", block).await;
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let client = rpc::RpcClient::new(cli.rpc.clone());
+    let rpc_override = cli.rpc_override();
+    let result = match cli.command {
+        Command::Node(NodeCommand::Run { config, id, difficulty, mine }) => {
+            commands::run_node(config, id, difficulty, mine, rpc_override, cli.json).await
         }
-    });
-
-    // Wait for mining tasks
-    let _ = tokio::join!(task_a, task_b);
-
-    sleep(Duration::from_millis(500)).await;
-    println!("{}", network.fmt_async().await);
-
-    // Add more transactions and mine
-    println!("📝 Adding more transactions...\n");
-    match network.add_transaction_to_node(
-        "Node_C",
-        Transaction::new("Charlie".to_string(), "Dave".to_string(), 15.0),
-    ).await {
-        Ok(()) => println!("✅ Transaction added to Node_C successfully"),
-        Err(e) => println!("❌ Failed to add transaction to Node_C: {:?}", e),
-    }
-
-    println!("⛏️  Node C mining...\n");
-    let node_arc_clone = {
-        let nodes = network.nodes.read().unwrap();
-        nodes.get("Node_C").map(|node_arc| Arc::clone(&node_arc))
-    };
-    let block = if let Some(node_arc_clone) = node_arc_clone {
-        tokio::task::spawn_blocking(move || {
-            let mut node = node_arc_clone.lock().unwrap();
-            node.mine_block()
-        }).await.ok().and_then(|r| r.ok())
-    } else {
-        None
+        Command::Tx(TxCommand::Send { sender, receiver, amount, keystore }) => {
+            commands::send_transaction(&client, sender, receiver, amount, keystore).await
+        }
+        Command::Balance { address } => commands::balance(&client, address).await,
+        Command::Block(BlockCommand::Show { block }) => commands::show_block(&client, &block).await,
+        Command::Chain(ChainCommand::Validate { file, difficulty }) => {
+            commands::validate_chain(&client, file, difficulty).await
+        }
+        Command::Chain(ChainCommand::Export { file }) => commands::export_chain(&client, file).await,
+        Command::Chain(ChainCommand::Import { file }) => commands::import_chain(&client, file).await,
+        Command::Simulate { file } => commands::simulate(file).await,
     };
-    if let Some(block) = block {
-        println!("[Node_C] ✅ Mined block #{}", block.index);
-        network.broadcast_block("Node_C", block).await;
-    }
-
-    sleep(Duration::from_millis(500)).await;
-    println!("{}", network.fmt_async().await);
-
-    // Demonstrate longest chain wins
-    println!("🔄 Demonstrating longest-chain-wins...\n");
-    network.broadcast_chain("Node_D").await;
-
-    sleep(Duration::from_millis(200)).await;
-    println!("{}", network.fmt_async().await);
+    commands::report(result, cli.json)
+}
 
-    println!("✅ Simulation complete!");
+impl Cli {
+    /// The `--rpc` address if it was changed from the default, so that a
+    /// node's config file can choose its own.
+    fn rpc_override(&self) -> Option<String> {
+        (self.rpc != rpc::DEFAULT_ADDRESS).then(|| self.rpc.clone())
+    }
 }
@@ -0,0 +1,261 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use chrono::Utc;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::MissedTickBehavior;
+
+use crate::models::blockchain::BlockchainError;
+use crate::models::message::{Envelope, Message};
+use crate::models::node::{GossipMetrics, NodeError, RelayMetrics};
+use crate::models::peer::{Direction, PeerError};
+use crate::models::{Block, Blockchain, Node, Transaction};
+
+/// How often a node task runs its peer housekeeping.
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A request handled by a node's task, one at a time, in arrival order.
+pub enum NodeCommand {
+    Deliver(Envelope),
+    Connect(String, Direction),
+    Disconnect(String),
+    ForgetPeer(String),
+    /// Redials known peers after the node has been restarted.
+    Rejoin,
+    /// Closes every connection and ends the task, handing the node back.
+    Stop(oneshot::Sender<Node>),
+    BroadcastBlock(Block),
+    BroadcastChain,
+    /// Adopts a chain handed in from outside, e.g. imported from a file, if it
+    /// is valid and heavier than ours. Replies whether it was adopted.
+    ImportChain(Arc<Vec<Block>>, oneshot::Sender<bool>),
+    SubmitTransaction(Box<Transaction>, oneshot::Sender<Result<(), NodeError>>),
+    Mine(oneshot::Sender<Result<Block, BlockchainError>>),
+    /// Turns continuous mining on or off. While on, the node mines its
+    /// mempool on every tick and relays each block it finds.
+    SetMining(bool),
+    Status(oneshot::Sender<NodeStatus>),
+}
+
+/// A point-in-time copy of a node's state.
+#[derive(Debug, Clone)]
+pub struct NodeStatus {
+    pub id: String,
+    pub blockchain: Blockchain,
+    pub peers: Vec<String>,
+    pub banned: Vec<String>,
+    pub gossip: GossipMetrics,
+    pub relay: RelayMetrics,
+    pub mining: bool,
+}
+
+/// The sending side of a running node's inbox.
+#[derive(Clone)]
+pub struct NodeHandle {
+    inbox: mpsc::UnboundedSender<NodeCommand>,
+    in_flight: Arc<AtomicUsize>,
+}
+
+impl NodeHandle {
+    /// Queues a command without waiting for it to be processed.
+    pub fn send(&self, command: NodeCommand) -> Result<(), NodeError> {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        self.inbox.send(command).map_err(|_| {
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            NodeError::NodeNotFound
+        })
+    }
+
+    /// Hands a routed envelope to the node; it was already counted when it was sent.
+    pub(crate) fn forward(&self, envelope: Envelope) -> bool {
+        self.inbox.send(NodeCommand::Deliver(envelope)).is_ok()
+    }
+
+    async fn request<T>(
+        &self,
+        command: impl FnOnce(oneshot::Sender<T>) -> NodeCommand,
+    ) -> Result<T, NodeError> {
+        let (reply, response) = oneshot::channel();
+        self.send(command(reply))?;
+        response.await.map_err(|_| NodeError::NodeNotFound)
+    }
+
+    pub async fn submit_transaction(&self, transaction: Transaction) -> Result<(), NodeError> {
+        self.request(|reply| NodeCommand::SubmitTransaction(Box::new(transaction), reply)).await?
+    }
+
+    pub async fn mine_block(&self) -> Result<Block, NodeError> {
+        self.request(NodeCommand::Mine).await?.map_err(NodeError::Mining)
+    }
+
+    pub async fn import_chain(&self, chain: Arc<Vec<Block>>) -> Result<bool, NodeError> {
+        self.request(|reply| NodeCommand::ImportChain(chain, reply)).await
+    }
+
+    pub fn set_mining(&self, enabled: bool) -> Result<(), NodeError> {
+        self.send(NodeCommand::SetMining(enabled))
+    }
+
+    pub async fn status(&self) -> Result<NodeStatus, NodeError> {
+        self.request(NodeCommand::Status).await
+    }
+
+    /// Stops the task after it has handled everything queued before this call.
+    pub async fn stop(&self) -> Result<Node, NodeError> {
+        self.request(NodeCommand::Stop).await
+    }
+}
+
+/// Moves `node` onto its own task and returns a handle to its inbox.
+///
+/// Outgoing messages are passed to `router`; `in_flight` counts commands and
+/// envelopes that have been sent but not yet processed.
+pub fn spawn_node(
+    node: Node,
+    router: mpsc::UnboundedSender<Envelope>,
+    in_flight: Arc<AtomicUsize>,
+) -> NodeHandle {
+    let (inbox, commands) = mpsc::unbounded_channel();
+    let handle = NodeHandle {
+        inbox,
+        in_flight: Arc::clone(&in_flight),
+    };
+    tokio::spawn(run(node, commands, router, in_flight));
+    handle
+}
+
+async fn run(
+    mut node: Node,
+    mut commands: mpsc::UnboundedReceiver<NodeCommand>,
+    router: mpsc::UnboundedSender<Envelope>,
+    in_flight: Arc<AtomicUsize>,
+) {
+    let mut mining = false;
+    let mut ticker = tokio::time::interval(TICK_INTERVAL);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    let dispatch = |outgoing: Vec<Envelope>| {
+        for envelope in outgoing {
+            in_flight.fetch_add(1, Ordering::SeqCst);
+            if router.send(envelope).is_err() {
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+            }
+        }
+    };
+
+    loop {
+        tokio::select! {
+            command = commands.recv() => {
+                let Some(command) = command else { break };
+                if let NodeCommand::Stop(reply) = command {
+                    dispatch(node.stop());
+                    in_flight.fetch_sub(1, Ordering::SeqCst);
+                    // Anything still queued will never be handled.
+                    commands.close();
+                    while commands.try_recv().is_ok() {
+                        in_flight.fetch_sub(1, Ordering::SeqCst);
+                    }
+                    let _ = reply.send(node);
+                    return;
+                }
+                let (handled, outgoing) = handle_command(node, command, &mut mining).await;
+                node = handled;
+                dispatch(outgoing);
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+            }
+            _ = ticker.tick() => {
+                dispatch(node.tick(unix_now()));
+                if mining && !node.blockchain.pending_transactions.is_empty() {
+                    let (mined, result) = mine(node).await;
+                    node = mined;
+                    if let Ok(block) = result {
+                        dispatch(node.relay_block(block, None));
+                    }
+                }
+            }
+        }
+    }
+}
+
+async fn handle_command(mut node: Node, command: NodeCommand, mining: &mut bool) -> (Node, Vec<Envelope>) {
+    let outgoing = match command {
+        NodeCommand::Deliver(envelope) => node.handle_message(unix_now(), &envelope.from, envelope.message),
+        NodeCommand::Connect(peer_id, direction) => match node.add_peer(peer_id.clone(), direction, unix_now()) {
+            Ok(()) if direction == Direction::Outbound => vec![node.hello(&peer_id)],
+            Ok(()) | Err(PeerError::AlreadyConnected) => Vec::new(),
+            // Tell the other side, which may already count us as connected.
+            Err(_) => vec![Envelope {
+                from: node.id.clone(),
+                to: peer_id,
+                message: Message::Disconnect,
+            }],
+        },
+        NodeCommand::Disconnect(peer_id) => node.disconnect_peer(&peer_id),
+        NodeCommand::ForgetPeer(peer_id) => {
+            node.forget_peer(&peer_id);
+            Vec::new()
+        }
+        NodeCommand::Rejoin => node.restart(unix_now()),
+        NodeCommand::Stop(_) => unreachable!("stop is handled by the task loop"),
+        NodeCommand::BroadcastBlock(block) => node.relay_block(block, None),
+        NodeCommand::BroadcastChain => node.broadcast(Message::Chain(node.blockchain.blocks.clone())),
+        NodeCommand::ImportChain(chain, reply) => {
+            let adopted = node.receive_chain(chain).unwrap_or(false);
+            let _ = reply.send(adopted);
+            if adopted {
+                let tip = node.blockchain.blocks.last().unwrap().clone();
+                node.relay_block(tip, None)
+            } else {
+                Vec::new()
+            }
+        }
+        NodeCommand::SubmitTransaction(transaction, reply) => match node.submit_transaction(*transaction) {
+            Ok(announcements) => {
+                let _ = reply.send(Ok(()));
+                announcements
+            }
+            Err(e) => {
+                let _ = reply.send(Err(e));
+                Vec::new()
+            }
+        },
+        NodeCommand::Mine(reply) => {
+            let (mined, result) = mine(node).await;
+            node = mined;
+            let _ = reply.send(result);
+            Vec::new()
+        }
+        NodeCommand::SetMining(enabled) => {
+            *mining = enabled;
+            Vec::new()
+        }
+        NodeCommand::Status(reply) => {
+            let _ = reply.send(NodeStatus {
+                id: node.id.clone(),
+                blockchain: node.blockchain.clone(),
+                peers: node.peers.ids(),
+                banned: node.peers.banned(),
+                gossip: node.gossip,
+                relay: node.relay,
+                mining: *mining,
+            });
+            Vec::new()
+        }
+    };
+    (node, outgoing)
+}
+
+/// Grinds the nonce off the runtime; the node handles nothing else meanwhile.
+async fn mine(mut node: Node) -> (Node, Result<Block, BlockchainError>) {
+    tokio::task::spawn_blocking(move || {
+        let result = node.mine_block();
+        (node, result)
+    })
+    .await
+    .expect("mining task panicked")
+}
+
+fn unix_now() -> u64 {
+    Utc::now().timestamp() as u64
+}
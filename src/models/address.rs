@@ -0,0 +1,174 @@
+//! Account addresses.
+//!
+//! An address is Base58Check text: a version byte, the 20-byte hash of what
+//! controls the account, and the first four bytes of the double SHA-256 of
+//! both as a checksum. A mistyped character almost always breaks the checksum,
+//! so typos are caught instead of sending funds to an account nobody owns.
+
+use std::fmt;
+
+use sha2::{Digest, Sha256};
+
+/// Version byte of addresses controlled by a single public key.
+pub const KEY_HASH_VERSION: u8 = 0x00;
+
+/// Version byte of shared accounts controlled by M of N keys; see `multisig`.
+pub const MULTISIG_VERSION: u8 = 0x32;
+
+/// Version byte of accounts controlled by a locking script; see `script`.
+pub const SCRIPT_HASH_VERSION: u8 = 0x05;
+
+/// Version byte of WASM contract accounts, which no key controls; see `wasm`.
+pub const CONTRACT_VERSION: u8 = 0x1c;
+
+/// Version byte of the named placeholder accounts of simulations and tests;
+/// see `from_name`. They are kept apart from key-hash addresses because
+/// anyone may spend from them.
+pub const NAME_VERSION: u8 = 0x6f;
+
+const ALPHABET: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+const CHECKSUM_LENGTH: usize = 4;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AddressError {
+    InvalidCharacter(char),
+    InvalidLength,
+    BadChecksum,
+    UnknownVersion(u8),
+}
+
+impl fmt::Display for AddressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddressError::InvalidCharacter(c) => write!(f, "invalid character {:?} in address", c),
+            AddressError::InvalidLength => write!(f, "address has the wrong length"),
+            AddressError::BadChecksum => write!(f, "address checksum does not match"),
+            AddressError::UnknownVersion(version) => write!(f, "unknown address version {}", version),
+        }
+    }
+}
+
+/// Encodes a version byte and a 20-byte hash as an address.
+pub fn encode(version: u8, hash: &[u8; 20]) -> String {
+    let mut payload = Vec::with_capacity(1 + 20 + CHECKSUM_LENGTH);
+    payload.push(version);
+    payload.extend_from_slice(hash);
+    let checksum = checksum(&payload);
+    payload.extend_from_slice(&checksum);
+    base58_encode(&payload)
+}
+
+/// Checks an address's alphabet, length, checksum and version, and returns
+/// the version and hash it carries.
+pub fn decode(address: &str) -> Result<(u8, [u8; 20]), AddressError> {
+    let payload = base58_decode(address)?;
+    if payload.len() != 1 + 20 + CHECKSUM_LENGTH {
+        return Err(AddressError::InvalidLength);
+    }
+    let (body, check) = payload.split_at(1 + 20);
+    if checksum(body) != check {
+        return Err(AddressError::BadChecksum);
+    }
+    if ![KEY_HASH_VERSION, MULTISIG_VERSION, SCRIPT_HASH_VERSION, CONTRACT_VERSION, NAME_VERSION].contains(&body[0]) {
+        return Err(AddressError::UnknownVersion(body[0]));
+    }
+    Ok((body[0], body[1..].try_into().unwrap()))
+}
+
+pub fn is_valid(address: &str) -> bool {
+    decode(address).is_ok()
+}
+
+/// The address that owns funds sent to `public_key`.
+pub fn from_public_key(public_key: &[u8; 32]) -> String {
+    let hash = Sha256::digest(public_key);
+    encode(KEY_HASH_VERSION, hash[..20].try_into().unwrap())
+}
+
+/// A well-formed address standing in for a named account, such as "Alice",
+/// in simulations and tests that do not hold keys. No key controls it, so
+/// unsigned transactions spend from it; it is not for holding real funds.
+pub fn from_name(name: &str) -> String {
+    let hash = Sha256::digest(name.as_bytes());
+    encode(NAME_VERSION, hash[..20].try_into().unwrap())
+}
+
+fn checksum(payload: &[u8]) -> [u8; CHECKSUM_LENGTH] {
+    let hash = Sha256::digest(Sha256::digest(payload));
+    hash[..CHECKSUM_LENGTH].try_into().unwrap()
+}
+
+fn base58_encode(bytes: &[u8]) -> String {
+    let zeros = bytes.iter().take_while(|&&byte| byte == 0).count();
+    // Little-endian base-58 digits of the big-endian number in `bytes`.
+    let mut digits: Vec<u8> = Vec::new();
+    for &byte in &bytes[zeros..] {
+        let mut carry = byte as u32;
+        for digit in digits.iter_mut() {
+            carry += (*digit as u32) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+    let mut text = "1".repeat(zeros);
+    text.extend(digits.iter().rev().map(|&digit| ALPHABET[digit as usize] as char));
+    text
+}
+
+fn base58_decode(text: &str) -> Result<Vec<u8>, AddressError> {
+    let zeros = text.chars().take_while(|&c| c == '1').count();
+    // Little-endian bytes of the number.
+    let mut bytes: Vec<u8> = Vec::new();
+    for c in text.chars().skip(zeros) {
+        let value = ALPHABET
+            .iter()
+            .position(|&letter| letter as char == c)
+            .ok_or(AddressError::InvalidCharacter(c))? as u32;
+        let mut carry = value;
+        for byte in bytes.iter_mut() {
+            carry += (*byte as u32) * 58;
+            *byte = (carry & 0xff) as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.push((carry & 0xff) as u8);
+            carry >>= 8;
+        }
+    }
+    let mut decoded = vec![0u8; zeros];
+    decoded.extend(bytes.iter().rev());
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::hex;
+
+    #[test]
+    fn test_known_encoding() {
+        // The hash of the first Bitcoin address, which uses the same scheme.
+        let hash: [u8; 20] = hex::decode("62e907b15cbf27d5425399ebf6f0fb50ebb88f18").unwrap().try_into().unwrap();
+        assert_eq!(encode(KEY_HASH_VERSION, &hash), "1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa");
+        assert_eq!(decode("1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa"), Ok((KEY_HASH_VERSION, hash)));
+    }
+
+    #[test]
+    fn test_rejects_typos_and_garbage() {
+        let address = from_name("Alice");
+        assert_eq!(decode(&address).map(|(version, _)| version), Ok(NAME_VERSION));
+        assert_ne!(address, from_name("Bob"));
+
+        let mut typo: Vec<char> = address.chars().collect();
+        typo[5] = if typo[5] == 'x' { 'y' } else { 'x' };
+        let typo: String = typo.into_iter().collect();
+        assert_eq!(decode(&typo), Err(AddressError::BadChecksum));
+        assert_eq!(decode("Alice"), Err(AddressError::InvalidCharacter('l')));
+        assert_eq!(decode("abc"), Err(AddressError::InvalidLength));
+        assert_eq!(decode(&encode(0x07, &[7; 20])), Err(AddressError::UnknownVersion(0x07)));
+    }
+}
@@ -0,0 +1,174 @@
+//! Anchoring document hashes on chain, to prove they existed by a given time.
+//!
+//! An anchor commits a document's SHA-256 hash and a short memo. The document
+//! itself stays off chain; whoever holds it can later show that its hash was
+//! included in a block, and the block's timestamp bounds when it existed.
+//! Anchors pay a fee for every byte they add to the chain.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::models::{Block, Transaction, hex};
+
+/// The longest memo an anchor may carry, in bytes.
+pub const MAX_MEMO_LENGTH: usize = 80;
+/// What an anchor pays for each byte of hash and memo it stores.
+pub const FEE_PER_BYTE: f64 = 0.001;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Anchor {
+    #[serde(with = "hex")]
+    pub document_hash: [u8; 32],
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub memo: String,
+}
+
+impl Anchor {
+    pub fn new(document: &[u8], memo: String) -> Self {
+        Anchor {
+            document_hash: Sha256::digest(document).into(),
+            memo,
+        }
+    }
+
+    /// The fee the anchor's size requires.
+    pub fn fee(&self) -> f64 {
+        (self.document_hash.len() + self.memo.len()) as f64 * FEE_PER_BYTE
+    }
+}
+
+impl fmt::Display for Anchor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "anchoring {} {:?}", hex::encode(&self.document_hash), self.memo)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AnchorError {
+    MemoTooLong,
+    /// The fee does not pay for the anchor's bytes.
+    FeeBelowSize { required: f64 },
+}
+
+impl fmt::Display for AnchorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AnchorError::MemoTooLong => write!(f, "anchor memos are at most {} bytes", MAX_MEMO_LENGTH),
+            AnchorError::FeeBelowSize { required } => write!(f, "fee must be at least {} for the anchor's size", required),
+        }
+    }
+}
+
+/// Whether the transaction's anchor, if any, is small enough and paid for.
+pub fn check(transaction: &Transaction) -> Result<(), AnchorError> {
+    let Some(anchor) = &transaction.anchor else {
+        return Ok(());
+    };
+    if anchor.memo.len() > MAX_MEMO_LENGTH {
+        return Err(AnchorError::MemoTooLong);
+    }
+    if transaction.fee < anchor.fee() {
+        return Err(AnchorError::FeeBelowSize { required: anchor.fee() });
+    }
+    Ok(())
+}
+
+/// A transaction by `sender` anchoring `anchor`, with the fee its size requires.
+pub fn anchor_at(sender: String, anchor: Anchor, timestamp: u64) -> Transaction {
+    let mut transaction = Transaction::new_at(sender.clone(), sender, 0.0, timestamp).with_fee(anchor.fee());
+    transaction.anchor = Some(anchor);
+    transaction
+}
+
+/// Where a document hash was first anchored. Anyone holding the block can
+/// check it with `verify`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AnchorProof {
+    pub height: u64,
+    #[serde(with = "hex")]
+    pub block_hash: [u8; 32],
+    pub timestamp: u64,
+    /// The anchoring transaction's position in the block.
+    pub position: usize,
+    pub transaction: Transaction,
+}
+
+impl AnchorProof {
+    /// Finds the earliest anchor of `document_hash` in `blocks`.
+    pub fn find(blocks: &[Block], document_hash: &[u8; 32]) -> Option<Self> {
+        blocks.iter().find_map(|block| {
+            let position = block.transactions.iter().position(|transaction| {
+                transaction.anchor.as_ref().is_some_and(|anchor| anchor.document_hash == *document_hash)
+            })?;
+            Some(AnchorProof {
+                height: block.index,
+                block_hash: block.hash,
+                timestamp: block.timestamp,
+                position,
+                transaction: block.transactions[position].clone(),
+            })
+        })
+    }
+
+    /// Whether `block` is the one the proof points to and includes the anchor.
+    pub fn verify(&self, block: &Block) -> bool {
+        block.hash == self.block_hash
+            && block.hash() == block.hash
+            && block.index == self.height
+            && block.timestamp == self.timestamp
+            && block.transactions.get(self.position) == Some(&self.transaction)
+            && self.transaction.anchor.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::address;
+    use crate::models::blockchain::{Blockchain, BlockchainError, GENESIS_TIMESTAMP};
+
+    #[test]
+    fn test_lookup_proves_the_first_anchor() {
+        let (alice, bob) = (address::from_name("Alice"), address::from_name("Bob"));
+        let contract = Anchor::new(b"lease agreement, signed copy", "lease #12".to_string());
+        let mut bc = Blockchain::new(0);
+        assert_eq!(bc.find_anchor(&contract.document_hash), None);
+
+        bc.add_transaction(Transaction::new_at(bob.clone(), alice.clone(), 1.0, 1)).unwrap();
+        let first = anchor_at(alice.clone(), contract.clone(), 2);
+        bc.add_transaction(first.clone()).unwrap();
+        bc.mine_block_at(GENESIS_TIMESTAMP + 100).unwrap();
+        bc.add_transaction(anchor_at(bob, contract.clone(), 3)).unwrap();
+        bc.mine_block_at(GENESIS_TIMESTAMP + 200).unwrap();
+        assert_eq!(bc.get_balance(&alice), 101.0 - contract.fee());
+
+        let proof = bc.find_anchor(&contract.document_hash).unwrap();
+        assert_eq!((proof.height, proof.timestamp, proof.position), (1, GENESIS_TIMESTAMP + 100, 1));
+        assert_eq!(proof.transaction, first);
+        assert!(proof.verify(&bc.blocks[1]));
+        assert!(!proof.verify(&bc.blocks[2]));
+        let mut tampered = bc.blocks[1].clone();
+        tampered.timestamp = 50;
+        assert!(!AnchorProof { timestamp: 50, ..proof }.verify(&tampered));
+    }
+
+    #[test]
+    fn test_anchors_pay_per_byte_within_limits() {
+        let alice = address::from_name("Alice");
+        let mut bc = Blockchain::new(0);
+        let anchor = Anchor::new(b"minutes", "board meeting".to_string());
+        assert_eq!(anchor.fee(), 45.0 * FEE_PER_BYTE);
+        let underpaid = anchor_at(alice.clone(), anchor.clone(), 1).with_fee(0.01);
+        assert_eq!(
+            bc.add_transaction(underpaid),
+            Err(BlockchainError::Anchor(AnchorError::FeeBelowSize { required: anchor.fee() }))
+        );
+        let rambling = Anchor::new(b"minutes", "x".repeat(MAX_MEMO_LENGTH + 1));
+        assert_eq!(
+            bc.add_transaction(anchor_at(alice, rambling, 1)),
+            Err(BlockchainError::Anchor(AnchorError::MemoTooLong))
+        );
+    }
+}
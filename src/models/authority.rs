@@ -0,0 +1,429 @@
+//! Proof of authority, for permissioned deployments.
+//!
+//! A configured set of validators takes turns signing blocks: the block at
+//! height `h` belongs to validator `h mod n`, in the order they joined the
+//! set. Should that validator be offline, the ones after it in turn order
+//! may sign out of turn, each once the block is dated far enough past its
+//! parent (see `consensus`). Nobody grinds a nonce. The set starts as configured on the engine
+//! and changes through signed transactions from current validators, each a
+//! vote to add or remove the transaction's receiver. A change takes effect
+//! once more than half the validators have voted for it, so no single one
+//! can take the set over. Only key-hash addresses, which can sign blocks,
+//! are added, and the set is never left empty.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use ed25519_dalek::SigningKey;
+use serde::{Deserialize, Serialize};
+
+use crate::models::consensus::{ChainView, ConsensusEngine, ConsensusError, fallback_due, rank_weight};
+use crate::models::{Block, Transaction, address};
+
+/// What a transaction does to the validator set. It applies to the transaction's receiver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthorityOp {
+    Add,
+    Remove,
+}
+
+impl fmt::Display for AuthorityOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthorityOp::Add => write!(f, "adding the receiver as an authority"),
+            AuthorityOp::Remove => write!(f, "removing the receiver as an authority"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuthorityError {
+    /// Set changes must carry the sender's valid signature.
+    Unsigned,
+    /// Only a current validator may change the set.
+    NotAuthority,
+    AlreadyAuthority,
+    UnknownAuthority,
+    /// Only key-hash addresses can sign blocks, so only they may be added.
+    CannotSign,
+    /// The sender already voted for this change.
+    AlreadyVoted,
+    /// Removing the validator would leave nobody to sign blocks.
+    LastAuthority,
+    /// A set change names a single validator.
+    MultipleReceivers,
+}
+
+impl fmt::Display for AuthorityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthorityError::Unsigned => write!(f, "validator set changes must be signed by the sender"),
+            AuthorityError::NotAuthority => write!(f, "only a current validator may change the set"),
+            AuthorityError::AlreadyAuthority => write!(f, "already a validator"),
+            AuthorityError::UnknownAuthority => write!(f, "not a validator"),
+            AuthorityError::CannotSign => write!(f, "only a key-hash address can become a validator"),
+            AuthorityError::AlreadyVoted => write!(f, "already voted for this change"),
+            AuthorityError::LastAuthority => write!(f, "the last validator cannot be removed"),
+            AuthorityError::MultipleReceivers => write!(f, "a validator set change names a single validator"),
+        }
+    }
+}
+
+/// The validators of a proof-of-authority chain, in turn order.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Authorities {
+    validators: Vec<String>,
+    /// Who has voted for each change that no majority has approved yet.
+    votes: BTreeMap<(AuthorityOp, String), BTreeSet<String>>,
+}
+
+impl Authorities {
+    pub fn new(validators: Vec<String>) -> Self {
+        Authorities {
+            validators,
+            votes: BTreeMap::new(),
+        }
+    }
+
+    pub fn validators(&self) -> &[String] {
+        &self.validators
+    }
+
+    pub fn contains(&self, validator: &str) -> bool {
+        self.validators.iter().any(|known| known == validator)
+    }
+
+    /// The validator whose turn it is to sign the block at `height`, if there are any.
+    pub fn scheduled(&self, height: u64) -> Option<&str> {
+        if self.validators.is_empty() {
+            return None;
+        }
+        Some(&self.validators[(height % self.validators.len() as u64) as usize])
+    }
+
+    /// The validators in the order they may sign the block at `height`:
+    /// the scheduled one, then the others in turn order from it.
+    pub fn ranked(&self, height: u64) -> Vec<&str> {
+        let Some(scheduled) = self.scheduled(height) else {
+            return Vec::new();
+        };
+        let start = self.validators.iter().position(|validator| validator == scheduled).unwrap_or(0);
+        let (before, after) = self.validators.split_at(start);
+        after.iter().chain(before).map(String::as_str).collect()
+    }
+
+    /// How many validators have voted to apply `op` to `validator` so far.
+    pub fn votes(&self, op: AuthorityOp, validator: &str) -> usize {
+        self.votes.get(&(op, validator.to_string())).map_or(0, BTreeSet::len)
+    }
+
+    /// Counts the sender's vote to add or remove the validator the
+    /// transaction names, and makes the change once a majority agrees.
+    pub fn apply(&mut self, transaction: &Transaction) -> Result<(), AuthorityError> {
+        self.check(transaction)?;
+        let Some(op) = transaction.authority else {
+            return Ok(());
+        };
+        let votes = self.votes.entry((op, transaction.receiver.clone())).or_default();
+        votes.insert(transaction.sender.clone());
+        if votes.len() * 2 <= self.validators.len() {
+            return Ok(());
+        }
+        // Votes were cast by the old set, so every other change is voted on afresh.
+        self.votes.clear();
+        match op {
+            AuthorityOp::Add => self.validators.push(transaction.receiver.clone()),
+            AuthorityOp::Remove => self.validators.retain(|validator| *validator != transaction.receiver),
+        }
+        Ok(())
+    }
+
+    /// Whether `apply` would accept the transaction, without applying it.
+    pub fn check(&self, transaction: &Transaction) -> Result<(), AuthorityError> {
+        let Some(op) = transaction.authority else {
+            return Ok(());
+        };
+        if !transaction.outputs.is_empty() {
+            return Err(AuthorityError::MultipleReceivers);
+        }
+        if !transaction.verify_signature() {
+            return Err(AuthorityError::Unsigned);
+        }
+        if !self.contains(&transaction.sender) {
+            return Err(AuthorityError::NotAuthority);
+        }
+        let voters = self.votes.get(&(op, transaction.receiver.clone()));
+        if voters.is_some_and(|voters| voters.contains(&transaction.sender)) {
+            return Err(AuthorityError::AlreadyVoted);
+        }
+        let can_sign = matches!(address::decode(&transaction.receiver), Ok((address::KEY_HASH_VERSION, _)));
+        match op {
+            AuthorityOp::Add if self.contains(&transaction.receiver) => Err(AuthorityError::AlreadyAuthority),
+            AuthorityOp::Add if !can_sign => Err(AuthorityError::CannotSign),
+            AuthorityOp::Remove if !self.contains(&transaction.receiver) => Err(AuthorityError::UnknownAuthority),
+            AuthorityOp::Remove if self.validators.len() == 1 => Err(AuthorityError::LastAuthority),
+            AuthorityOp::Add | AuthorityOp::Remove => Ok(()),
+        }
+    }
+}
+
+/// An unsigned transaction by `sender` applying `op` to `validator`, moving no coins.
+pub fn authority_at(sender: String, validator: String, op: AuthorityOp, timestamp: u64) -> Transaction {
+    let mut transaction = Transaction::new_at(sender, validator, 0.0, timestamp);
+    transaction.authority = Some(op);
+    transaction
+}
+
+/// Signs blocks when it is this node's turn, or when it is due to step in
+/// for the validators scheduled ahead of it, and accepts blocks signed likewise.
+#[derive(Debug, Clone)]
+pub struct ProofOfAuthority {
+    /// The set the chain starts with, before any change transactions.
+    validators: Vec<String>,
+    key: Option<SigningKey>,
+}
+
+impl ProofOfAuthority {
+    pub fn new(validators: Vec<String>, key: SigningKey) -> Self {
+        ProofOfAuthority {
+            validators,
+            key: Some(key),
+        }
+    }
+
+    /// An engine that verifies blocks but never signs any.
+    pub fn observer(validators: Vec<String>) -> Self {
+        ProofOfAuthority { validators, key: None }
+    }
+
+    /// Where the validator `address` ranks for `block`, failing if nobody may sign it.
+    fn rank(view: &ChainView, block: &Block, address: &str) -> Result<Option<usize>, ConsensusError> {
+        let ranked = view.authorities.ranked(block.index);
+        if ranked.is_empty() {
+            return Err(ConsensusError::NoAuthorities);
+        }
+        Ok(ranked.iter().position(|validator| *validator == address))
+    }
+}
+
+impl ConsensusEngine for ProofOfAuthority {
+    fn genesis_authorities(&self) -> Authorities {
+        Authorities::new(self.validators.clone())
+    }
+
+    fn prepare(&self, view: &ChainView, block: &mut Block) -> Result<(), ConsensusError> {
+        let key = self.key.as_ref().ok_or(ConsensusError::NoValidatorKey)?;
+        match Self::rank(view, block, &address::from_public_key(&key.verifying_key().to_bytes()))? {
+            Some(rank) if fallback_due(rank, view, block) => Ok(()),
+            _ => Err(ConsensusError::NotProposer),
+        }
+    }
+
+    fn seal(&self, _view: &ChainView, block: &mut Block) -> Result<(), ConsensusError> {
+        block.sign(self.key.as_ref().ok_or(ConsensusError::NoValidatorKey)?);
+        Ok(())
+    }
+
+    fn verify(&self, view: &ChainView, block: &Block) -> Result<(), ConsensusError> {
+        let signer = block.signer().ok_or(ConsensusError::Unsigned)?;
+        match Self::rank(view, block, &signer)? {
+            Some(rank) if fallback_due(rank, view, block) => Ok(()),
+            _ => Err(ConsensusError::WrongProposer {
+                expected: view.authorities.scheduled(block.index).unwrap_or_default().to_string(),
+            }),
+        }
+    }
+
+    fn weight(&self, view: &ChainView, block: &Block) -> u64 {
+        let signer = block.signer().unwrap_or_default();
+        rank_weight(Self::rank(view, block, &signer).ok().flatten().unwrap_or(0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::models::blockchain::{BlockchainError, GENESIS_TIMESTAMP};
+    use crate::models::node::NodeError;
+    use crate::models::{Blockchain, Node};
+    use crate::models::consensus::{FALLBACK_SECONDS, validator_address, validator_key};
+
+    fn chain(node_id: &str, validators: &[&str]) -> Blockchain {
+        let validators = validators.iter().map(|id| validator_address(id)).collect();
+        Blockchain::new(0).with_consensus(Arc::new(ProofOfAuthority::new(validators, validator_key(node_id))))
+    }
+
+    fn transfer(timestamp: u64) -> Transaction {
+        Transaction::new_at(address::from_name("Alice"), address::from_name("Bob"), 1.0, timestamp)
+    }
+
+    #[test]
+    fn test_authorities_sign_in_turn() {
+        let (mut a, mut b) = (chain("A", &["A", "B"]), chain("B", &["A", "B"]));
+        // Block 1 is B's turn, and A may not step in for it yet.
+        a.add_transaction(transfer(1)).unwrap();
+        assert_eq!(
+            a.mine_block_at(GENESIS_TIMESTAMP + 1),
+            Err(BlockchainError::Consensus(ConsensusError::NotProposer))
+        );
+        b.add_transaction(transfer(1)).unwrap();
+        let first = b.mine_block_at(GENESIS_TIMESTAMP + 100).unwrap();
+        assert_eq!((first.nonce, first.signer()), (0, Some(validator_address("B"))));
+        a.append_block(first).unwrap();
+        a.prune_pending();
+        a.add_transaction(transfer(2)).unwrap();
+        let second = a.mine_block_at(GENESIS_TIMESTAMP + 200).unwrap();
+        assert_eq!(second.signer(), Some(validator_address("A")));
+        b.append_block(second).unwrap();
+        assert!(b.validate_chain().is_ok());
+
+        let mut observer = Blockchain::new(0)
+            .with_consensus(Arc::new(ProofOfAuthority::observer(vec![validator_address("A"), validator_address("B")])));
+        assert!(observer.replace_chain(a.blocks.clone()));
+        observer.add_transaction(transfer(3)).unwrap();
+        assert_eq!(
+            observer.mine_block_at(GENESIS_TIMESTAMP + 300),
+            Err(BlockchainError::Consensus(ConsensusError::NoValidatorKey))
+        );
+        let mut outsider = chain("A", &["B"]);
+        assert!(!outsider.replace_chain(a.blocks.clone()));
+    }
+
+    #[test]
+    fn test_an_authority_steps_in_once_the_scheduled_one_is_overdue() {
+        let [mut a, mut b, mut c] = ["A", "B", "C"].map(|id| chain(id, &["A", "B", "C"]));
+        // Block 1 is B's turn; C is next in line, then A.
+        for bc in [&mut a, &mut b, &mut c] {
+            bc.add_transaction(transfer(1)).unwrap();
+        }
+        assert_eq!(
+            a.mine_block_at(GENESIS_TIMESTAMP + FALLBACK_SECONDS),
+            Err(BlockchainError::Consensus(ConsensusError::NotProposer))
+        );
+        let backup = c.mine_block_at(GENESIS_TIMESTAMP + FALLBACK_SECONDS).unwrap();
+        assert_eq!(backup.signer(), Some(validator_address("C")));
+        a.mine_block_at(GENESIS_TIMESTAMP + 2 * FALLBACK_SECONDS).unwrap();
+
+        // A block signed in turn outweighs either backup's.
+        let in_turn = b.mine_block_at(GENESIS_TIMESTAMP + 100).unwrap();
+        assert!(!b.clone().replace_chain(c.blocks.clone()));
+        assert!(c.replace_chain(b.blocks.clone()));
+        assert!(a.replace_chain(b.blocks.clone()));
+        assert_eq!(a.blocks.last(), Some(&in_turn));
+    }
+
+    #[test]
+    fn test_validators_change_the_set() {
+        let (a, c) = (validator_address("A"), validator_address("C"));
+        let mut bc = chain("A", &["A"]);
+        let mut add = authority_at(a.clone(), c.clone(), AuthorityOp::Add, 1);
+        let refused = |bc: &Blockchain, transaction: Transaction| bc.clone().add_transaction(transaction);
+        assert_eq!(refused(&bc, add.clone()), Err(BlockchainError::InvalidSignature));
+        let named = authority_at(address::from_name("Alice"), c.clone(), AuthorityOp::Add, 1);
+        assert_eq!(refused(&bc, named), Err(BlockchainError::Authority(AuthorityError::Unsigned)));
+        add.sign(&validator_key("A"));
+        let mut outsider = authority_at(c.clone(), c.clone(), AuthorityOp::Add, 1);
+        outsider.sign(&validator_key("C"));
+        assert_eq!(refused(&bc, outsider), Err(BlockchainError::Authority(AuthorityError::NotAuthority)));
+        let mut last = authority_at(a.clone(), a.clone(), AuthorityOp::Remove, 1);
+        last.sign(&validator_key("A"));
+        assert_eq!(refused(&bc, last), Err(BlockchainError::Authority(AuthorityError::LastAuthority)));
+
+        bc.add_transaction(add.clone()).unwrap();
+        assert_eq!(refused(&bc, add), Err(BlockchainError::Replayed));
+        let mut again = authority_at(a.clone(), c.clone(), AuthorityOp::Add, 2);
+        again.sign(&validator_key("A"));
+        assert_eq!(refused(&bc, again), Err(BlockchainError::Authority(AuthorityError::AlreadyAuthority)));
+        bc.mine_block_at(GENESIS_TIMESTAMP + 100).unwrap();
+        assert_eq!(bc.authorities.validators(), [a.clone(), c.clone()]);
+
+        // A keeps block 2, but block 3 is now C's turn.
+        bc.add_transaction(transfer(2)).unwrap();
+        bc.mine_block_at(GENESIS_TIMESTAMP + 200).unwrap();
+        bc.add_transaction(transfer(3)).unwrap();
+        assert_eq!(
+            bc.mine_block_at(GENESIS_TIMESTAMP + 201),
+            Err(BlockchainError::Consensus(ConsensusError::NotProposer))
+        );
+        let mut by_c = chain("C", &["A"]);
+        assert!(by_c.replace_chain(bc.blocks.clone()));
+        by_c.add_transaction(transfer(3)).unwrap();
+        by_c.mine_block_at(GENESIS_TIMESTAMP + 300).unwrap();
+        assert_eq!(by_c.blocks[3].signer(), Some(c));
+        assert!(by_c.validate_chain().is_ok());
+    }
+
+    #[test]
+    fn test_changes_need_a_majority_and_a_validator_that_can_sign() {
+        let [a, b, c] = ["A", "B", "C"].map(validator_address);
+        let mut authorities = Authorities::new(vec![a.clone(), b.clone(), c.clone()]);
+        let vote = |voter: &str, validator: &str, op: AuthorityOp, timestamp: u64| {
+            let mut transaction = authority_at(validator_address(voter), validator.to_string(), op, timestamp);
+            transaction.sign(&validator_key(voter));
+            transaction
+        };
+
+        // One validator alone can neither strip the others nor add a signer that cannot sign.
+        authorities.apply(&vote("A", &b, AuthorityOp::Remove, 1)).unwrap();
+        authorities.apply(&vote("A", &c, AuthorityOp::Remove, 1)).unwrap();
+        assert_eq!(authorities.validators(), [a.clone(), b.clone(), c.clone()]);
+        assert_eq!(authorities.check(&vote("A", &c, AuthorityOp::Remove, 2)), Err(AuthorityError::AlreadyVoted));
+        let named = address::from_name("Mallory");
+        assert_eq!(authorities.check(&vote("A", &named, AuthorityOp::Add, 2)), Err(AuthorityError::CannotSign));
+
+        authorities.apply(&vote("B", &c, AuthorityOp::Remove, 3)).unwrap();
+        assert_eq!(authorities.validators(), [a.clone(), b.clone()]);
+        assert_eq!(authorities.votes(AuthorityOp::Remove, &b), 0);
+    }
+
+    #[test]
+    fn test_signed_blocks_must_still_link_to_the_tip() {
+        let (mut a, mut b) = (chain("A", &["A", "B"]), chain("B", &["A", "B"]));
+        let genesis = a.blocks[0].hash;
+        b.add_transaction(transfer(1)).unwrap();
+        let block = b.mine_block_at(GENESIS_TIMESTAMP + 100).unwrap();
+
+        // The signature covers the header hash, so the body must still match it.
+        let mut tampered = block.clone();
+        tampered.transactions[0].amount = 90.0;
+        assert_eq!(a.append_block(tampered), Err(BlockchainError::IncorrectProof));
+        let mut skipped = Block::new_at(2, genesis, vec![transfer(1)], GENESIS_TIMESTAMP + 100);
+        skipped.sign(&validator_key("A"));
+        assert_eq!(a.append_block(skipped), Err(BlockchainError::IncorrectIndex));
+        let mut orphan = Block::new_at(1, [1; 32], vec![transfer(1)], 100);
+        orphan.sign(&validator_key("B"));
+        assert_eq!(a.append_block(orphan), Err(BlockchainError::PreviousHashDoesNotMatch));
+        a.append_block(block).unwrap();
+    }
+
+    #[test]
+    fn test_nodes_refuse_blocks_from_off_turn_authorities() {
+        let validators = vec![validator_address("A"), validator_address("B")];
+        let node = |node_id: &str| {
+            let engine = ProofOfAuthority::new(validators.clone(), validator_key(node_id));
+            Node::new(node_id.to_string(), 0).with_consensus(Arc::new(engine))
+        };
+        let (mut a, mut b) = (node("A"), node("B"));
+        let genesis = a.blockchain.blocks[0].hash;
+        let mut early = Block::new_at(1, genesis, vec![transfer(1)], GENESIS_TIMESTAMP + 1);
+        early.sign(&validator_key("A"));
+        assert!(matches!(
+            b.receive_block(early, GENESIS_TIMESTAMP + 100),
+            Err(NodeError::InvalidBlock(BlockchainError::Consensus(ConsensusError::WrongProposer { .. })))
+        ));
+        let mut unsigned = Block::new_at(1, genesis, vec![transfer(1)], GENESIS_TIMESTAMP + 100);
+        unsigned.hash = unsigned.hash();
+        assert!(matches!(
+            a.receive_block(unsigned, GENESIS_TIMESTAMP + 100),
+            Err(NodeError::InvalidBlock(BlockchainError::Consensus(ConsensusError::Unsigned)))
+        ));
+
+        b.blockchain.add_transaction(transfer(1)).unwrap();
+        let scheduled = b.mine_block_at(GENESIS_TIMESTAMP + 100).unwrap();
+        a.receive_block(scheduled, GENESIS_TIMESTAMP + 100).unwrap();
+        assert_eq!(a.blockchain.blocks.len(), 2);
+    }
+}
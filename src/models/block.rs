@@ -1,52 +1,128 @@
+use std::fmt;
+
 use chrono::Utc;
+use ed25519_dalek::SigningKey;
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 
-use crate::models::Transaction;
+use crate::models::{Signature, Transaction, address, hex};
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Block {
     pub index: u64,
-    // pub data: String,
+    #[serde(with = "hex")]
     pub previous_hash: [u8; 32],
+    #[serde(with = "hex")]
     pub hash: [u8; 32],
     pub nonce: u64,
     pub transactions: Vec<Transaction>,
     pub timestamp: u64,
+    /// The proposer's signature over the block's header, under consensus
+    /// engines that take turns instead of grinding a nonce.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<Signature>,
 }
 
 impl Block {
     pub fn new(index: u64, previous_hash: [u8; 32], transactions: Vec<Transaction>) -> Self {
+        Self::new_at(index, previous_hash, transactions, Utc::now().timestamp() as u64)
+    }
+
+    /// Creates a block stamped with `timestamp` instead of the wall clock.
+    pub fn new_at(index: u64, previous_hash: [u8; 32], transactions: Vec<Transaction>, timestamp: u64) -> Self {
         Block {
             index,
             previous_hash,
             hash: [0u8; 32],
             nonce: 0,
             transactions,
-            timestamp: Utc::now().timestamp() as u64,
+            timestamp,
+            signature: None,
         }
     }
 
     pub fn hash(&self) -> [u8; 32] {
         let mut hasher = Sha256::new();
-        hasher.update(&self.previous_hash);
+        hasher.update(self.previous_hash);
         hasher.update(self.nonce.to_string().as_bytes());
         hasher.update(self.timestamp.to_string().as_bytes());
         for transaction in &self.transactions {
-            let tx = format!(
-                "{} -> {} : {} on {}",
-                transaction.sender,
-                transaction.receiver,
-                transaction.amount,
-                transaction.timestamp
-            );
-            hasher.update(tx.as_bytes());
+            hasher.update(transaction.digest_input().as_bytes());
         }
         hasher.finalize().into()
     }
+
+    /// What the block's transactions pay in fees.
+    pub fn fees(&self) -> f64 {
+        self.transactions.iter().map(|transaction| transaction.fee).sum()
+    }
+
+    /// Sets the block's hash and signs its header with `key`.
+    pub fn sign(&mut self, key: &SigningKey) {
+        self.hash = self.hash();
+        let input = SignedHeader::signing_input(self.index, &self.previous_hash, &self.hash);
+        self.signature = Some(Signature::new(key, input.as_bytes()));
+    }
+
+    /// The signed header, if the block carries a signature.
+    pub fn header(&self) -> Option<SignedHeader> {
+        Some(SignedHeader {
+            index: self.index,
+            previous_hash: self.previous_hash,
+            hash: self.hash,
+            signature: self.signature.clone()?,
+        })
+    }
+
+    /// The address of whoever validly signed the block, if anyone did.
+    pub fn signer(&self) -> Option<String> {
+        self.header()?.signer()
+    }
+}
+
+/// What a proposer signs for a block. Two of these at one height, signed by
+/// the same key, prove that it backed competing blocks.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SignedHeader {
+    pub index: u64,
+    #[serde(with = "hex")]
+    pub previous_hash: [u8; 32],
+    #[serde(with = "hex")]
+    pub hash: [u8; 32],
+    pub signature: Signature,
+}
+
+impl SignedHeader {
+    fn signing_input(index: u64, previous_hash: &[u8; 32], hash: &[u8; 32]) -> String {
+        format!("block {} at height {} on {}", hex::encode(hash), index, hex::encode(previous_hash))
+    }
+
+    /// The address of the key that signed the header, if the signature is valid.
+    pub fn signer(&self) -> Option<String> {
+        let input = Self::signing_input(self.index, &self.previous_hash, &self.hash);
+        self.signature
+            .verifies(input.as_bytes())
+            .then(|| address::from_public_key(&self.signature.public_key))
+    }
 }
+
+impl fmt::Display for SignedHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "block {} at height {} on {} signed by {}",
+            hex::encode(&self.hash),
+            self.index,
+            hex::encode(&self.previous_hash),
+            self.signature
+        )
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::models::utxo::TxOutput;
 
     #[test]
     fn test_new_block() {
@@ -76,7 +152,7 @@ mod tests {
         let hash = block.hash();
         // Compute expected hash
         let mut hasher = Sha256::new();
-        hasher.update(&prev_hash);
+        hasher.update(prev_hash);
         hasher.update("42".as_bytes());
         hasher.update("1609459200".as_bytes());
         let tx_str = "Alice -> Bob : 10 on 1234567890";
@@ -84,4 +160,22 @@ mod tests {
         let expected: [u8; 32] = hasher.finalize().into();
         assert_eq!(hash, expected);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_hash_commits_to_every_output() {
+        let pay = |address: &str, amount: f64| TxOutput { address: address.to_string(), amount };
+        let payroll = Transaction::batch_at("Acme".to_string(), vec![pay("Bob", 10.0), pay("Carol", 20.0)], 7)
+            .unwrap()
+            .with_fee(0.5);
+        assert_eq!(payroll.signing_input(), "Acme -> Bob : 10 on 7 and Carol : 20 with fee 0.5");
+        let reordered = Transaction::batch_at("Acme".to_string(), vec![pay("Carol", 20.0), pay("Bob", 10.0)], 7)
+            .unwrap()
+            .with_fee(0.5);
+
+        let block = |transaction: Transaction| Block::new_at(1, [b'p'; 32], vec![transaction], 1609459200);
+        assert_ne!(block(payroll.clone()).hash(), block(reordered).hash());
+        let decoded: Transaction = serde_json::from_str(&serde_json::to_string(&payroll).unwrap()).unwrap();
+        assert_eq!(block(decoded).hash(), block(payroll.clone()).hash());
+        assert_eq!(block(payroll).fees(), 0.5);
+    }
+}
@@ -1,137 +1,633 @@
 use chrono::Utc;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
 use std::sync::Arc;
 
+use crate::models::address::{self, AddressError};
+use crate::models::anchor::{self, AnchorError, AnchorProof};
+use crate::models::authority::{Authorities, AuthorityError};
+use crate::models::consensus::{ChainView, ConsensusEngine, ConsensusError, ProofOfWork};
+use crate::models::htlc::{Contracts, HtlcError};
+use crate::models::multisig::{self, MultisigError};
+use crate::models::nft::{AssetError, Assets};
+use crate::models::script::{self, ScriptError};
+use crate::models::stake::{StakeError, Stakes};
+use crate::models::token::{TokenError, Tokens};
+use crate::models::utxo::{OutPoint, UtxoSet};
+use crate::models::wasm::{Programs, WasmError};
 use crate::models::{Block, Transaction};
 
-#[derive(Debug, Clone, PartialEq)]
+/// Every node starts from the same genesis block, so its timestamp is fixed.
+pub const GENESIS_TIMESTAMP: u64 = 1_735_689_600;
+
+/// How far ahead of our clock, in seconds, a block may be dated. Lock times
+/// and contract timeouts are judged by block timestamps, so without a bound a
+/// miner could date a block past them.
+pub const MAX_DRIFT: u64 = 2 * 60 * 60;
+
+/// What every address holds before it has sent or received anything.
+pub const STARTING_BALANCE: f64 = 100.0;
+
+/// How balances are accounted for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LedgerModel {
+    /// Balances are the sum of every transfer in and out of an address.
+    #[default]
+    Account,
+    /// Transactions consume unspent outputs and create new ones; see `utxo`.
+    Utxo,
+}
+
+#[derive(Debug, Clone)]
 pub struct Blockchain {
     pub blocks: Arc<Vec<Block>>,
+    /// Transactions waiting to be mined. Add them with `add_transaction`,
+    /// which keeps the pending state in step.
     pub pending_transactions: Vec<Transaction>,
     pub difficulty: usize,
+    pub ledger: LedgerModel,
+    /// Decides who may propose the next block and checks blocks from others.
+    pub consensus: Arc<dyn ConsensusEngine>,
+    /// Outputs spendable at the tip. Only kept up to date in the UTXO model.
+    pub utxos: UtxoSet,
+    /// Balances at the tip of the addresses that have sent or received
+    /// anything. Only kept up to date in the account model.
+    pub balances: BTreeMap<String, f64>,
+    /// Hash time-locked contracts opened on the chain, and whether they are settled.
+    pub contracts: Contracts,
+    /// WASM contracts deployed on the chain, with their storage.
+    pub programs: Programs,
+    /// Fungible tokens issued on the chain, with their holders.
+    pub tokens: Tokens,
+    /// Unique assets minted on the chain, with their ownership history.
+    pub assets: Assets,
+    /// Coins bonded by proof-of-stake validators.
+    pub stakes: Stakes,
+    /// The proof-of-authority validators, in turn order.
+    pub authorities: Authorities,
+    /// Signing hashes of the transactions on the chain; none may be included again.
+    pub confirmed: HashSet<[u8; 32]>,
+    /// The tip state with the pending transactions applied, so that each new
+    /// one is checked without replaying the whole mempool.
+    pending: LedgerState,
+    /// What the blocks past genesis weigh to the consensus engine; only a
+    /// heavier chain replaces them.
+    weight: u64,
+}
+
+/// Chains are equal when their blocks, mempools and ledger state are, whichever
+/// engine each runs: engines are not comparable, and carry no chain state. The
+/// pending state and the weight follow from the others.
+impl PartialEq for Blockchain {
+    fn eq(&self, other: &Self) -> bool {
+        let Blockchain {
+            blocks,
+            pending_transactions,
+            difficulty,
+            ledger,
+            consensus: _,
+            utxos,
+            balances,
+            contracts,
+            programs,
+            tokens,
+            assets,
+            stakes,
+            authorities,
+            confirmed,
+            pending: _,
+            weight: _,
+        } = self;
+        *blocks == other.blocks
+            && *pending_transactions == other.pending_transactions
+            && *difficulty == other.difficulty
+            && *ledger == other.ledger
+            && *utxos == other.utxos
+            && *balances == other.balances
+            && *contracts == other.contracts
+            && *programs == other.programs
+            && *tokens == other.tokens
+            && *assets == other.assets
+            && *stakes == other.stakes
+            && *authorities == other.authorities
+            && *confirmed == other.confirmed
+    }
 }
 
 impl Blockchain {
     pub fn new(difficulty: usize) -> Self {
+        Self::with_ledger(difficulty, LedgerModel::Account)
+    }
+
+    pub fn with_ledger(difficulty: usize, ledger: LedgerModel) -> Self {
+        Blockchain {
+            blocks: Arc::new(vec![Self::genesis()]),
+            pending_transactions: vec![],
+            difficulty,
+            ledger,
+            consensus: Arc::new(ProofOfWork),
+            utxos: UtxoSet::default(),
+            balances: BTreeMap::new(),
+            contracts: Contracts::default(),
+            programs: Programs::default(),
+            tokens: Tokens::default(),
+            assets: Assets::default(),
+            stakes: Stakes::default(),
+            authorities: Authorities::default(),
+            confirmed: HashSet::new(),
+            pending: LedgerState::default(),
+            weight: 0,
+        }
+    }
+
+    /// The block every chain starts from. Chains that start anywhere else are refused.
+    pub fn genesis() -> Block {
         let mut genesis_block = Block {
             index: 0,
             previous_hash: [0u8; 32],
             hash: [0u8; 32],
             nonce: 0,
             transactions: vec![],
-            timestamp: Utc::now().timestamp() as u64,
+            timestamp: GENESIS_TIMESTAMP,
+            signature: None,
         };
         genesis_block.hash = genesis_block.hash();
+        genesis_block
+    }
 
-        Blockchain {
-            blocks: Arc::new(vec![genesis_block]),
-            pending_transactions: vec![],
-            difficulty,
-
-        }
+    /// Runs `consensus` instead of proof of work, starting from its validator
+    /// set. Meant for a chain that only holds its genesis block, since blocks
+    /// already on it are not rechecked.
+    pub fn with_consensus(mut self, consensus: Arc<dyn ConsensusEngine>) -> Self {
+        self.authorities = consensus.genesis_authorities();
+        self.consensus = consensus;
+        self.refresh_pending();
+        self
     }
 
     pub fn add_transaction(&mut self, transaction: Transaction) -> Result<(), BlockchainError> {
-        let sender_balance = self.get_balance(&transaction.sender);
-
-        if sender_balance < transaction.amount {
-            return Err(BlockchainError::InsufficientBalance);
+        address::decode(&transaction.sender).map_err(BlockchainError::InvalidAddress)?;
+        for payment in transaction.payments() {
+            address::decode(&payment.address).map_err(BlockchainError::InvalidAddress)?;
+            if !is_valid_amount(payment.amount) {
+                return Err(BlockchainError::InvalidAmount);
+            }
+        }
+        if !is_valid_amount(transaction.fee) {
+            return Err(BlockchainError::InvalidAmount);
         }
+        check_authorization(&transaction)?;
+        // Checked against the pending transactions too, so the mempool never
+        // holds a double spend, an overdraft or two settlements of one contract.
+        self.pending.apply(self.ledger, &transaction, None)?;
         self.pending_transactions.push(transaction);
         Ok(())
     }
 
-    pub fn get_balance(&self, address: &str) -> f64 {
-        let mut balance = 100.0;
+    /// Drops pending transactions that the chain already includes.
+    pub fn prune_pending(&mut self) {
+        let confirmed: HashSet<[u8; 32]> = self
+            .blocks
+            .iter()
+            .flat_map(|block| block.transactions.iter().map(Transaction::hash))
+            .collect();
+        self.pending_transactions.retain(|transaction| !confirmed.contains(&transaction.hash()));
+        self.refresh_pending();
+    }
 
-        for block in &*self.blocks {
-            for transaction in &block.transactions {
-                if transaction.sender == address {
-                    balance -= transaction.amount;
-                }
-                if transaction.receiver == address {
-                    balance += transaction.amount;
-                }
+    /// Rebuilds the pending state on a new tip, dropping pending transactions
+    /// that no longer apply: the tip may have spent or settled what they rely on.
+    fn refresh_pending(&mut self) {
+        let mut state = self.tip_state();
+        let ledger = self.ledger;
+        self.pending_transactions.retain(|transaction| state.apply(ledger, transaction, None).is_ok());
+        self.pending = state;
+    }
+
+    fn tip_state(&self) -> LedgerState {
+        LedgerState {
+            utxos: self.utxos.clone(),
+            balances: self.balances.clone(),
+            contracts: self.contracts.clone(),
+            programs: self.programs.clone(),
+            tokens: self.tokens.clone(),
+            assets: self.assets.clone(),
+            stakes: self.stakes.clone(),
+            authorities: self.authorities.clone(),
+            confirmed: self.confirmed.clone(),
+        }
+    }
+
+    fn set_tip_state(&mut self, state: LedgerState) {
+        self.utxos = state.utxos;
+        self.balances = state.balances;
+        self.contracts = state.contracts;
+        self.programs = state.programs;
+        self.tokens = state.tokens;
+        self.assets = state.assets;
+        self.stakes = state.stakes;
+        self.authorities = state.authorities;
+        self.confirmed = state.confirmed;
+    }
+
+    /// What the consensus engine sees of the chain when a block extends the tip.
+    fn view(&self) -> ChainView<'_> {
+        ChainView {
+            parent: self.blocks.last().unwrap(),
+            difficulty: self.difficulty,
+            stakes: &self.stakes,
+            authorities: &self.authorities,
+        }
+    }
+
+    /// Picks unspent outputs of `address` worth at least `amount`, skipping
+    /// those pending transactions already spend.
+    pub fn select_inputs(&self, address: &str, amount: f64) -> Result<Vec<OutPoint>, BlockchainError> {
+        let mut inputs = Vec::new();
+        let mut total = 0.0;
+        for (outpoint, output) in self.pending.utxos.outputs_of(address) {
+            if total >= amount && !inputs.is_empty() {
+                break;
             }
+            inputs.push(outpoint);
+            total += output.amount;
+        }
+        if total < amount {
+            return Err(BlockchainError::InsufficientBalance);
         }
+        Ok(inputs)
+    }
+
+    /// What `address` holds at the tip, as the ledger checks it when spending.
+    pub fn get_balance(&self, address: &str) -> f64 {
+        match self.ledger {
+            LedgerModel::Utxo => self.utxos.balance(address),
+            LedgerModel::Account => account_balance(&self.balances, address),
+        }
+    }
+
+    /// Units of the token `symbol` that `address` holds at the tip.
+    pub fn token_balance(&self, symbol: &str, address: &str) -> u64 {
+        self.tokens.get(symbol).map_or(0, |token| token.balance(address))
+    }
+
+    /// Units of the token `symbol` in existence at the tip, if it was issued.
+    pub fn token_supply(&self, symbol: &str) -> Option<u64> {
+        self.tokens.get(symbol).map(|token| token.supply)
+    }
 
-        balance
+    /// Who holds the asset `id` at the tip, if it was minted.
+    pub fn asset_owner(&self, id: &str) -> Option<&str> {
+        self.assets.get(id).map(|asset| asset.owner())
+    }
+
+    /// Where `document_hash` was first anchored on the chain, if it was.
+    pub fn find_anchor(&self, document_hash: &[u8; 32]) -> Option<AnchorProof> {
+        AnchorProof::find(&self.blocks, document_hash)
     }
 
     pub fn mine_block(&mut self) -> Result<Block, BlockchainError> {
-        if self.pending_transactions.is_empty() {
+        self.mine_block_at(Utc::now().timestamp() as u64)
+    }
+
+    /// Mines the pending transactions into a block stamped with `timestamp`.
+    /// Those still locked, and those relying on them, stay in the mempool for
+    /// a later block; claims that missed their contract's timeout are dropped.
+    pub fn mine_block_at(&mut self, timestamp: u64) -> Result<Block, BlockchainError> {
+        let height = self.blocks.len() as u64;
+        let mut state = self.tip_state();
+        let mut ready = Vec::new();
+        let mut waiting = Vec::new();
+        for transaction in &self.pending_transactions {
+            if !transaction.is_final(height, timestamp) {
+                waiting.push(transaction.clone());
+                continue;
+            }
+            match state.apply(self.ledger, transaction, Some(timestamp)) {
+                Ok(()) => ready.push(transaction.clone()),
+                Err(BlockchainError::Htlc(HtlcError::Expired)) => {}
+                Err(_) => waiting.push(transaction.clone()),
+            }
+        }
+        if ready.is_empty() {
+            self.pending_transactions = waiting;
+            self.refresh_pending();
             return Err(BlockchainError::EmptyTransactions);
         }
 
         let last_block = self.blocks.last().unwrap();
-        let mut new_block = Block::new(self.blocks.len() as u64, last_block.hash.clone(), self.pending_transactions.clone());
-
-        // mine the block (proof of work)
-        let mut hash = new_block.hash();
-        while !hash.iter().take(self.difficulty).all(|&b| b == 0) {
-            new_block.nonce += 1;
-            hash = new_block.hash();
-        }
+        let mut new_block = Block::new_at(height, last_block.hash, ready, timestamp);
+        let view = self.view();
+        self.consensus.prepare(&view, &mut new_block).map_err(BlockchainError::Consensus)?;
+        self.consensus.seal(&view, &mut new_block).map_err(BlockchainError::Consensus)?;
 
         // adds block to chain
-        new_block.hash = hash;
-        let mut new_blocks = (*self.blocks).clone();
-        new_blocks.push(new_block.clone());
-        self.blocks = Arc::new(new_blocks);
-        // clear pending transactions
-        self.pending_transactions.clear();
+        self.append_block(new_block.clone())?;
+        self.pending_transactions = waiting;
+        self.refresh_pending();
 
         Ok(new_block)
     }
 
+    /// Appends a block that extends the tip. It must link to the tip, be
+    /// dated no earlier than it and at most `MAX_DRIFT` past our clock, the
+    /// consensus engine must accept it, and its transactions must be past
+    /// their locks, settle contracts as their terms allow and spend only what
+    /// their senders hold.
+    pub fn append_block(&mut self, block: Block) -> Result<(), BlockchainError> {
+        check_link(self.blocks.last().unwrap(), &block)?;
+        check_timestamp(self.blocks.last().unwrap(), &block, Utc::now().timestamp() as u64)?;
+        self.consensus.verify(&self.view(), &block).map_err(BlockchainError::Consensus)?;
+        for transaction in &block.transactions {
+            check_authorization(transaction)?;
+        }
+        check_locks(&block)?;
+        let weight = self.consensus.weight(&self.view(), &block);
+        let mut state = self.tip_state();
+        state.stakes.reseed(&block);
+        for transaction in &block.transactions {
+            state.apply(self.ledger, transaction, Some(block.timestamp))?;
+        }
+        self.set_tip_state(state);
+        self.weight += weight;
+        let mut new_blocks = (*self.blocks).clone();
+        new_blocks.push(block);
+        self.blocks = Arc::new(new_blocks);
+        self.refresh_pending();
+        Ok(())
+    }
+
     pub fn validate_chain(&self) -> Result<(), BlockchainError> {
-        for (i, current_block) in self.blocks.iter().enumerate().skip(1) {
-            let previous_block = &self.blocks[i - 1];
+        self.replay(&self.blocks).map(|_| ())
+    }
+
+    pub fn replace_chain(&mut self, new_chain: Arc<Vec<Block>>) -> bool {
+        self.try_replace_chain(new_chain).unwrap_or(false)
+    }
 
-            // Check if the current block's previous hash matches the previous block's hash
-            if current_block.previous_hash != previous_block.hash {
-                return Err(BlockchainError::PreviousHashDoesNotMatch);
+    /// Like `replace_chain`, but says why an invalid chain was refused.
+    /// `Ok(false)` means the chain is valid but no heavier than ours, which
+    /// under proof of work means no longer.
+    pub fn try_replace_chain(&mut self, new_chain: Arc<Vec<Block>>) -> Result<bool, BlockchainError> {
+        let (state, weight) = self.replay(&new_chain)?;
+        if weight <= self.weight {
+            return Ok(false);
+        }
+        self.set_tip_state(state);
+        self.weight = weight;
+        self.blocks = new_chain;
+        self.refresh_pending();
+        Ok(true)
+    }
+
+    /// The ledger state at the tip of `blocks` and what they weigh, failing
+    /// unless they start from our genesis block, or at the first block that
+    /// does not link to its parent, is misdated, is refused by the consensus
+    /// engine or holds a bad or unauthorized transaction.
+    fn replay(&self, blocks: &[Block]) -> Result<(LedgerState, u64), BlockchainError> {
+        if blocks.first() != Some(&Self::genesis()) {
+            return Err(BlockchainError::WrongGenesis);
+        }
+        let mut state = LedgerState {
+            authorities: self.consensus.genesis_authorities(),
+            ..LedgerState::default()
+        };
+        let mut weight = 0;
+        let now = Utc::now().timestamp() as u64;
+        for (parent, block) in blocks.iter().zip(&blocks[1..]) {
+            check_link(parent, block)?;
+            check_timestamp(parent, block, now)?;
+            for transaction in &block.transactions {
+                check_authorization(transaction)?;
+            }
+            check_locks(block)?;
+            let view = ChainView {
+                parent,
+                difficulty: self.difficulty,
+                stakes: &state.stakes,
+                authorities: &state.authorities,
+            };
+            self.consensus.verify(&view, block).map_err(BlockchainError::Consensus)?;
+            weight += self.consensus.weight(&view, block);
+            state.stakes.reseed(block);
+            for transaction in &block.transactions {
+                state.apply(self.ledger, transaction, Some(block.timestamp))?;
             }
+        }
+        Ok((state, weight))
+    }
+}
 
-            // Recalculate the hash of the current block and compare
-            if current_block.hash != current_block.hash() {
-                return Err(BlockchainError::IncorrectProof);
+/// Checks that whoever controls the sender approved the transaction: enough
+/// keyholders for a multisig account, a satisfied locking script for a
+/// script address, the key itself for a key-hash address. Contract accounts
+/// have nobody to approve anything, so they never send. Named placeholder
+/// accounts have no key either, and spend unsigned.
+fn check_authorization(transaction: &Transaction) -> Result<(), BlockchainError> {
+    match address::decode(&transaction.sender) {
+        Ok((address::MULTISIG_VERSION, _)) => multisig::verify(transaction).map_err(BlockchainError::Multisig),
+        Ok((address::SCRIPT_HASH_VERSION, _)) => script::verify(transaction).map_err(BlockchainError::Script),
+        Ok((address::CONTRACT_VERSION, _)) => Err(BlockchainError::Wasm(WasmError::SentByContract)),
+        Ok((address::NAME_VERSION, _)) if transaction.signature.is_none() => Ok(()),
+        _ if transaction.verify_signature() => Ok(()),
+        _ => Err(BlockchainError::InvalidSignature),
+    }
+}
+
+/// What the transactions on a chain build up, native balances included.
+#[derive(Debug, Clone, Default)]
+struct LedgerState {
+    utxos: UtxoSet,
+    balances: BTreeMap<String, f64>,
+    contracts: Contracts,
+    programs: Programs,
+    tokens: Tokens,
+    assets: Assets,
+    stakes: Stakes,
+    authorities: Authorities,
+    confirmed: HashSet<[u8; 32]>,
+}
+
+impl LedgerState {
+    /// Applies one transaction, leaving the state untouched if it is refused.
+    /// `timestamp` is that of the including block, if any.
+    fn apply(
+        &mut self,
+        ledger: LedgerModel,
+        transaction: &Transaction,
+        timestamp: Option<u64>,
+    ) -> Result<(), BlockchainError> {
+        let signing_hash = transaction.signing_hash();
+        if self.confirmed.contains(&signing_hash) {
+            return Err(BlockchainError::Replayed);
+        }
+        if transaction.operation_count() > 1 {
+            return Err(BlockchainError::MultipleOperations);
+        }
+        anchor::check(transaction).map_err(BlockchainError::Anchor)?;
+        self.contracts.check(transaction, timestamp).map_err(BlockchainError::Htlc)?;
+        self.tokens.check(transaction).map_err(BlockchainError::Token)?;
+        self.assets.check(transaction).map_err(BlockchainError::Asset)?;
+        self.stakes.check(transaction).map_err(BlockchainError::Stake)?;
+        self.authorities.check(transaction).map_err(BlockchainError::Authority)?;
+        if ledger == LedgerModel::Account && self.balance(&transaction.sender) < transaction.debit() {
+            return Err(BlockchainError::InsufficientBalance);
+        }
+        let changes = self.programs.run(transaction, timestamp).map_err(BlockchainError::Wasm)?;
+        if ledger == LedgerModel::Utxo {
+            self.utxos.apply(transaction)?;
+        } else {
+            *self.balances.entry(transaction.sender.clone()).or_insert(STARTING_BALANCE) -= transaction.debit();
+            for payment in transaction.credits() {
+                *self.balances.entry(payment.address).or_insert(STARTING_BALANCE) += payment.amount;
             }
         }
+        self.contracts.apply(transaction, timestamp).map_err(BlockchainError::Htlc)?;
+        self.tokens.apply(transaction).map_err(BlockchainError::Token)?;
+        self.assets.apply(transaction, timestamp).map_err(BlockchainError::Asset)?;
+        self.stakes.apply(transaction).map_err(BlockchainError::Stake)?;
+        self.authorities.apply(transaction).map_err(BlockchainError::Authority)?;
+        self.programs.commit(changes);
+        self.confirmed.insert(signing_hash);
         Ok(())
     }
 
-    pub fn replace_chain(&mut self, new_chain: Arc<Vec<Block>>) -> bool {
-        if new_chain.len() <= self.blocks.len() {
-            return false;
-        }
+    fn balance(&self, address: &str) -> f64 {
+        account_balance(&self.balances, address)
+    }
+}
 
-        let temp_blockchain = Blockchain {
-            blocks: new_chain.clone(),
-            pending_transactions: vec![],
-            difficulty: self.difficulty,
-        };
-        if let Err(_) = temp_blockchain.validate_chain() {
-            return false;
-        }
+/// An account's balance; accounts never touched hold the starting balance.
+fn account_balance(balances: &BTreeMap<String, f64>, address: &str) -> f64 {
+    balances.get(address).copied().unwrap_or(STARTING_BALANCE)
+}
 
-        self.blocks = new_chain;
-        true
+/// Checks that `block` follows `parent` and is stamped with its own hash.
+fn check_link(parent: &Block, block: &Block) -> Result<(), BlockchainError> {
+    if block.previous_hash != parent.hash {
+        return Err(BlockchainError::PreviousHashDoesNotMatch);
     }
+    if block.index != parent.index + 1 {
+        return Err(BlockchainError::IncorrectIndex);
+    }
+    if block.hash != block.hash() {
+        return Err(BlockchainError::IncorrectProof);
+    }
+    Ok(())
 }
 
+/// Checks that `block` is dated no earlier than its parent and at most
+/// `MAX_DRIFT` seconds past `now`.
+pub fn check_timestamp(parent: &Block, block: &Block, now: u64) -> Result<(), BlockchainError> {
+    if block.timestamp < parent.timestamp {
+        return Err(BlockchainError::EarlierThanParent);
+    }
+    if block.timestamp > now.saturating_add(MAX_DRIFT) {
+        return Err(BlockchainError::TooFarInFuture);
+    }
+    Ok(())
+}
+
+fn check_locks(block: &Block) -> Result<(), BlockchainError> {
+    if block.transactions.iter().all(|transaction| transaction.is_final(block.index, block.timestamp)) {
+        Ok(())
+    } else {
+        Err(BlockchainError::PrematureTransaction)
+    }
+}
+
+fn is_valid_amount(amount: f64) -> bool {
+    amount.is_finite() && amount >= 0.0
+}
 
 #[derive(Debug, PartialEq)]
 pub enum BlockchainError {
     IncorrectProof,
     PreviousHashDoesNotMatch,
+    /// The block's index is not one past its parent's.
+    IncorrectIndex,
+    /// The chain does not start from our genesis block.
+    WrongGenesis,
+    /// The block is dated before its parent.
+    EarlierThanParent,
+    /// The block is dated more than `MAX_DRIFT` past our clock.
+    TooFarInFuture,
     EmptyTransactions,
     InsufficientBalance,
+    InvalidSignature,
+    InvalidAddress(AddressError),
+    /// A payment or fee is negative, infinite or not a number.
+    InvalidAmount,
+    /// The block includes a transaction before its lock height or time.
+    PrematureTransaction,
+    /// A hash time-locked contract refuses the lock, claim or refund.
+    Htlc(HtlcError),
+    /// A spend from a script address does not satisfy its locking script.
+    Script(ScriptError),
+    /// A spend from a multisig account lacks the approvals it needs.
+    Multisig(MultisigError),
+    /// A WASM contract refuses the deploy or call.
+    Wasm(WasmError),
+    /// The token operation is not allowed or not covered.
+    Token(TokenError),
+    /// The asset cannot be minted or transferred.
+    Asset(AssetError),
+    /// The anchor is too large or underpaid.
+    Anchor(AnchorError),
+    /// The bond, unbond or slash is not allowed.
+    Stake(StakeError),
+    /// The validator set change is not allowed.
+    Authority(AuthorityError),
+    /// The consensus engine refuses to propose or accept the block.
+    Consensus(ConsensusError),
+    /// The same transaction is already on the chain or in the mempool.
+    Replayed,
+    /// The transaction carries more than one contract, token, asset, anchor,
+    /// stake or authority operation.
+    MultipleOperations,
+    /// The input is not an unspent output of the sender.
+    UnknownInput(OutPoint),
+    /// The input was already spent on the chain, in the mempool or by the same transaction.
+    DoubleSpend(OutPoint),
+}
 
+impl fmt::Display for BlockchainError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlockchainError::IncorrectProof => write!(f, "incorrect proof of work"),
+            BlockchainError::PreviousHashDoesNotMatch => write!(f, "previous hash does not match"),
+            BlockchainError::IncorrectIndex => write!(f, "block index does not follow its parent"),
+            BlockchainError::WrongGenesis => write!(f, "chain does not start from the genesis block"),
+            BlockchainError::EarlierThanParent => write!(f, "block is dated before its parent"),
+            BlockchainError::TooFarInFuture => write!(f, "block is dated too far in the future"),
+            BlockchainError::EmptyTransactions => write!(f, "no pending transactions are ready to mine"),
+            BlockchainError::InsufficientBalance => write!(f, "insufficient balance"),
+            BlockchainError::InvalidSignature => write!(f, "invalid signature"),
+            BlockchainError::InvalidAddress(e) => write!(f, "{}", e),
+            BlockchainError::InvalidAmount => write!(f, "amounts and fees must be finite and not negative"),
+            BlockchainError::PrematureTransaction => write!(f, "transaction is still locked"),
+            BlockchainError::Htlc(e) => write!(f, "{}", e),
+            BlockchainError::Multisig(e) => write!(f, "{}", e),
+            BlockchainError::Script(e) => write!(f, "{}", e),
+            BlockchainError::Wasm(e) => write!(f, "{}", e),
+            BlockchainError::Token(e) => write!(f, "{}", e),
+            BlockchainError::Asset(e) => write!(f, "{}", e),
+            BlockchainError::Anchor(e) => write!(f, "{}", e),
+            BlockchainError::Stake(e) => write!(f, "{}", e),
+            BlockchainError::Authority(e) => write!(f, "{}", e),
+            BlockchainError::Consensus(e) => write!(f, "{}", e),
+            BlockchainError::Replayed => write!(f, "transaction was already submitted"),
+            BlockchainError::MultipleOperations => write!(f, "a transaction carries at most one operation"),
+            BlockchainError::UnknownInput(outpoint) => write!(f, "{} is not an unspent output of the sender", outpoint),
+            BlockchainError::DoubleSpend(outpoint) => write!(f, "{} is already spent", outpoint),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::models::utxo::TxOutput;
 
     #[test]
     fn test_new_blockchain() {
@@ -144,12 +640,14 @@ mod tests {
         assert!((*bc.blocks)[0].transactions.is_empty());
         assert!(bc.pending_transactions.is_empty());
         assert_eq!(bc.difficulty, 2);
+        assert_eq!(bc, Blockchain::new(2).with_consensus(Arc::new(ProofOfWork)));
+        assert_ne!(bc, Blockchain::new(1));
     }
 
     #[test]
     fn test_add_transaction() {
         let mut bc = Blockchain::new(1);
-        let tx = Transaction::new("Alice".to_string(), "Bob".to_string(), 0.0); // amount 0 to bypass balance check
+        let tx = Transaction::new(address::from_name("Alice"), address::from_name("Bob"), 0.0); // amount 0 to bypass balance check
         let result = bc.add_transaction(tx.clone());
         assert!(result.is_ok());
         assert_eq!(bc.pending_transactions.len(), 1);
@@ -160,15 +658,15 @@ mod tests {
     fn test_mine_block() {
         let mut bc = Blockchain::new(1); // low difficulty
         // Add a transaction to give Alice balance
-        let tx_genesis = Transaction::new("Genesis".to_string(), "Alice".to_string(), 100.0);
-        let mut genesis_block = Block::new(1, (*bc.blocks)[0].hash.clone(), vec![tx_genesis]);
+        let tx_genesis = Transaction::new(address::from_name("Genesis"), address::from_name("Alice"), 100.0);
+        let mut genesis_block = Block::new(1, (*bc.blocks)[0].hash, vec![tx_genesis]);
         genesis_block.nonce = 1;
         genesis_block.hash = genesis_block.hash();
         let mut v = (*bc.blocks).clone();
         v.push(genesis_block);
         bc.blocks = Arc::new(v);
         // Now add transaction from Alice
-        let tx = Transaction::new("Alice".to_string(), "Bob".to_string(), 10.0);
+        let tx = Transaction::new(address::from_name("Alice"), address::from_name("Bob"), 10.0);
         let result = bc.add_transaction(tx.clone());
         assert!(result.is_ok());
         let result = bc.mine_block();
@@ -184,15 +682,19 @@ mod tests {
     fn test_validate_chain_valid() {
         let mut bc = Blockchain::new(1);
         // Add balance
-        let tx_genesis = Transaction::new("Genesis".to_string(), "Alice".to_string(), 100.0);
-        let mut genesis_block = Block::new(1, (*bc.blocks)[0].hash.clone(), vec![tx_genesis]);
-        genesis_block.nonce = 1;
+        let tx_genesis = Transaction::new(address::from_name("Genesis"), address::from_name("Alice"), 100.0);
+        let mut genesis_block = Block::new(1, (*bc.blocks)[0].hash, vec![tx_genesis]);
         genesis_block.hash = genesis_block.hash();
+        // Replaying the chain checks the proof of work too.
+        while genesis_block.hash[0] != 0 {
+            genesis_block.nonce += 1;
+            genesis_block.hash = genesis_block.hash();
+        }
         let mut v = (*bc.blocks).clone();
         v.push(genesis_block);
         bc.blocks = Arc::new(v);
         // Add transaction
-        let tx = Transaction::new("Alice".to_string(), "Bob".to_string(), 10.0);
+        let tx = Transaction::new(address::from_name("Alice"), address::from_name("Bob"), 10.0);
         let result = bc.add_transaction(tx);
         assert!(result.is_ok());
         let result = bc.mine_block();
@@ -204,15 +706,15 @@ mod tests {
     fn test_validate_chain_invalid() {
         let mut bc = Blockchain::new(1);
         // Add balance
-        let tx_genesis = Transaction::new("Genesis".to_string(), "Alice".to_string(), 100.0);
-        let mut genesis_block = Block::new(1, (*bc.blocks)[0].hash.clone(), vec![tx_genesis]);
+        let tx_genesis = Transaction::new(address::from_name("Genesis"), address::from_name("Alice"), 100.0);
+        let mut genesis_block = Block::new(1, (*bc.blocks)[0].hash, vec![tx_genesis]);
         genesis_block.nonce = 1;
         genesis_block.hash = genesis_block.hash();
         let mut v = (*bc.blocks).clone();
         v.push(genesis_block);
         bc.blocks = Arc::new(v);
         // Add transaction
-        let tx = Transaction::new("Alice".to_string(), "Bob".to_string(), 10.0);
+        let tx = Transaction::new(address::from_name("Alice"), address::from_name("Bob"), 10.0);
         let result = bc.add_transaction(tx);
         assert!(result.is_ok());
         let result = bc.mine_block();
@@ -227,19 +729,325 @@ mod tests {
     #[test]
     fn test_get_balance() {
         let mut bc = Blockchain::new(1);
-        // Manually add a block with transactions
-        let tx1 = Transaction::new("Alice".to_string(), "Bob".to_string(), 50.0);
-        let tx2 = Transaction::new("Bob".to_string(), "Charlie".to_string(), 20.0);
-        let mut block = Block::new(1, (*bc.blocks)[0].hash.clone(), vec![tx1, tx2]);
-        block.nonce = 1;
-        block.hash = block.hash(); // compute hash
-        let mut v = (*bc.blocks).clone();
-        v.push(block);
-        bc.blocks = Arc::new(v);
+        bc.add_transaction(Transaction::new(address::from_name("Alice"), address::from_name("Bob"), 50.0)).unwrap();
+        bc.add_transaction(Transaction::new(address::from_name("Bob"), address::from_name("Charlie"), 20.0)).unwrap();
+        assert_eq!(bc.get_balance(&address::from_name("Alice")), 100.0, "pending transfers are not spent yet");
+        bc.mine_block().unwrap();
 
-        assert_eq!(bc.get_balance("Alice"), 50.0); // 100 - 50
-        assert_eq!(bc.get_balance("Bob"), 130.0); // 100 + 50 - 20
-        assert_eq!(bc.get_balance("Charlie"), 120.0); // 100 + 20
-        assert_eq!(bc.get_balance("Dave"), 100.0); // 100 (no transactions)
+        assert_eq!(bc.get_balance(&address::from_name("Alice")), 50.0); // 100 - 50
+        assert_eq!(bc.get_balance(&address::from_name("Bob")), 130.0); // 100 + 50 - 20
+        assert_eq!(bc.get_balance(&address::from_name("Charlie")), 120.0); // 100 + 20
+        assert_eq!(bc.get_balance(&address::from_name("Dave")), 100.0); // 100 (no transactions)
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_account_ledger_refuses_overdrafts_in_the_mempool_and_in_blocks() {
+        let (alice, bob) = (address::from_name("Alice"), address::from_name("Bob"));
+        let mut bc = Blockchain::new(0);
+        bc.add_transaction(Transaction::new_at(alice.clone(), bob.clone(), 60.0, 1)).unwrap();
+        let second = Transaction::new_at(alice.clone(), bob.clone(), 60.0, 2);
+        assert_eq!(bc.add_transaction(second.clone()), Err(BlockchainError::InsufficientBalance));
+        bc.mine_block_at(GENESIS_TIMESTAMP + 100).unwrap();
+        assert_eq!(bc.balances.get(&alice), Some(&40.0));
+        bc.add_transaction(Transaction::new_at(alice.clone(), bob.clone(), 40.0, 3)).unwrap();
+        let overdraft = Transaction::new_at(alice.clone(), bob.clone(), 1.0, 4);
+        assert_eq!(bc.add_transaction(overdraft), Err(BlockchainError::InsufficientBalance));
+
+        let mut block = Block::new_at(2, bc.blocks[1].hash, vec![second], GENESIS_TIMESTAMP + 200);
+        block.hash = block.hash();
+        assert_eq!(bc.append_block(block.clone()), Err(BlockchainError::InsufficientBalance));
+        let mut forged = (*bc.blocks).clone();
+        forged.push(block);
+        assert!(!Blockchain::new(0).replace_chain(Arc::new(forged)));
+        assert_eq!(bc.get_balance(&alice), 40.0);
+    }
+
+    #[test]
+    fn test_chains_must_start_from_our_genesis_block() {
+        let mallory = address::from_name("Mallory");
+        let mut genesis = Blockchain::genesis();
+        genesis.transactions.push(Transaction::new_at(address::from_name("Alice"), mallory.clone(), 100.0, 1));
+        genesis.hash = genesis.hash();
+        let mut block = Block::new_at(1, genesis.hash, vec![], GENESIS_TIMESTAMP + 100);
+        block.hash = block.hash();
+        let forged = Arc::new(vec![genesis, block]);
+
+        let mut bc = Blockchain::new(0);
+        assert_eq!(bc.try_replace_chain(forged.clone()), Err(BlockchainError::WrongGenesis));
+        assert_eq!(bc.get_balance(&mallory), STARTING_BALANCE);
+        bc.blocks = forged;
+        assert_eq!(bc.validate_chain(), Err(BlockchainError::WrongGenesis));
+    }
+
+    #[test]
+    fn test_add_transaction_rejects_malformed_addresses() {
+        let mut bc = Blockchain::new(1);
+        let typo = Transaction::new("Alice".to_string(), address::from_name("Bob"), 1.0);
+        assert_eq!(
+            bc.add_transaction(typo),
+            Err(BlockchainError::InvalidAddress(AddressError::InvalidCharacter('l')))
+        );
+        let mut receiver = address::from_name("Bob");
+        receiver.pop();
+        let truncated = Transaction::new(address::from_name("Alice"), receiver, 1.0);
+        assert!(matches!(bc.add_transaction(truncated), Err(BlockchainError::InvalidAddress(_))));
+        assert!(bc.pending_transactions.is_empty());
+    }
+
+    #[test]
+    fn test_utxo_ledger_detects_double_spends() {
+        let (alice, bob) = (address::from_name("Alice"), address::from_name("Bob"));
+        let mut bc = Blockchain::with_ledger(1, LedgerModel::Utxo);
+        let inputs = bc.select_inputs(&alice, 30.0).unwrap();
+        assert_eq!(inputs, vec![OutPoint::allowance(&alice)]);
+        bc.add_transaction(Transaction::new_at(alice.clone(), bob.clone(), 30.0, 1).spending(inputs.clone()))
+            .unwrap();
+
+        // The mempool already spends the allowance.
+        let rival = Transaction::new_at(alice.clone(), bob.clone(), 5.0, 2).spending(inputs.clone());
+        assert_eq!(bc.add_transaction(rival.clone()), Err(BlockchainError::DoubleSpend(inputs[0])));
+        assert_eq!(bc.select_inputs(&alice, 71.0), Err(BlockchainError::InsufficientBalance));
+        let without_inputs = Transaction::new_at(alice.clone(), bob.clone(), 5.0, 3);
+        assert_eq!(bc.add_transaction(without_inputs), Err(BlockchainError::InsufficientBalance));
+
+        bc.mine_block_at(GENESIS_TIMESTAMP + 10).unwrap();
+        assert_eq!(bc.get_balance(&alice), 70.0);
+        assert_eq!(bc.get_balance(&bob), 130.0);
+        // So does the chain, once mined.
+        assert_eq!(bc.add_transaction(rival), Err(BlockchainError::DoubleSpend(inputs[0])));
+
+        let change = bc.select_inputs(&alice, 70.0).unwrap();
+        bc.add_transaction(Transaction::new_at(alice.clone(), bob.clone(), 70.0, 4).spending(change))
+            .unwrap();
+        bc.mine_block_at(GENESIS_TIMESTAMP + 20).unwrap();
+        assert_eq!(bc.get_balance(&alice), 0.0);
+        assert_eq!(bc.get_balance(&bob), 200.0);
+        assert!(bc.validate_chain().is_ok());
+    }
+
+    #[test]
+    fn test_utxo_ledger_refuses_blocks_that_double_spend() {
+        let (alice, bob) = (address::from_name("Alice"), address::from_name("Bob"));
+        let mut bc = Blockchain::with_ledger(0, LedgerModel::Utxo);
+        let allowance = vec![OutPoint::allowance(&alice)];
+        let first = Transaction::new_at(alice.clone(), bob.clone(), 60.0, 1).spending(allowance.clone());
+        let second = Transaction::new_at(alice.clone(), bob.clone(), 60.0, 2).spending(allowance);
+        let mut block = Block::new_at(1, bc.blocks[0].hash, vec![first, second], GENESIS_TIMESTAMP + 10);
+        block.hash = block.hash();
+
+        assert!(matches!(bc.append_block(block.clone()), Err(BlockchainError::DoubleSpend(_))));
+        assert_eq!(bc.blocks.len(), 1);
+        assert_eq!(bc.get_balance(&bob), 100.0);
+
+        let forged = Arc::new(vec![bc.blocks[0].clone(), block]);
+        assert!(!bc.replace_chain(forged.clone()));
+        let mut account = Blockchain::new(0);
+        assert!(!account.replace_chain(forged), "the account model overdraws Alice instead");
+    }
+
+    #[test]
+    fn test_confirmed_transactions_cannot_be_replayed() {
+        use ed25519_dalek::SigningKey;
+
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let alice = address::from_public_key(&key.verifying_key().to_bytes());
+        let bob = address::from_name("Bob");
+        let mut bc = Blockchain::new(0);
+        let mut payment = Transaction::new_at(alice.clone(), bob.clone(), 30.0, 1);
+        payment.sign(&key);
+        bc.add_transaction(payment.clone()).unwrap();
+        assert_eq!(bc.add_transaction(payment.clone()), Err(BlockchainError::Replayed));
+        bc.mine_block_at(GENESIS_TIMESTAMP + 100).unwrap();
+        assert_eq!(bc.add_transaction(payment.clone()), Err(BlockchainError::Replayed));
+
+        // Nor may a block carry it again, whoever mines it.
+        let mut replay = Block::new_at(2, bc.blocks[1].hash, vec![payment], GENESIS_TIMESTAMP + 200);
+        replay.hash = replay.hash();
+        assert_eq!(bc.append_block(replay.clone()), Err(BlockchainError::Replayed));
+        let mut forged = (*bc.blocks).clone();
+        forged.push(replay);
+        assert!(!Blockchain::new(0).replace_chain(Arc::new(forged)));
+        assert_eq!(bc.get_balance(&alice), 70.0);
+    }
+
+    #[test]
+    fn test_transactions_carry_at_most_one_operation() {
+        use crate::models::anchor::Anchor;
+        use crate::models::stake::{StakeOp, stake_at};
+
+        let alice = address::from_name("Alice");
+        let mut bc = Blockchain::new(0);
+        let mut both = stake_at(alice.clone(), StakeOp::Bond, 10.0, 1);
+        both.anchor = Some(Anchor::new(b"contract", "bonded and anchored".to_string()));
+        assert_eq!(both.operation_count(), 2);
+        assert_eq!(bc.add_transaction(both.clone()), Err(BlockchainError::MultipleOperations));
+
+        let mut block = Block::new_at(1, bc.blocks[0].hash, vec![both], GENESIS_TIMESTAMP + 100);
+        block.hash = block.hash();
+        assert_eq!(bc.append_block(block), Err(BlockchainError::MultipleOperations));
+        bc.add_transaction(stake_at(alice, StakeOp::Bond, 10.0, 1)).unwrap();
+    }
+
+    #[test]
+    fn test_batch_transfers_are_checked_as_a_whole() {
+        let pay = |name: &str, amount: f64| TxOutput { address: address::from_name(name), amount };
+        let (acme, bob) = (address::from_name("Acme"), address::from_name("Bob"));
+        let mut bc = Blockchain::new(1);
+
+        let too_much = Transaction::batch_at(acme.clone(), vec![pay("Bob", 60.0), pay("Carol", 40.0)], 1)
+            .unwrap()
+            .with_fee(1.0);
+        assert_eq!(bc.add_transaction(too_much), Err(BlockchainError::InsufficientBalance));
+        let negative = Transaction::batch_at(acme.clone(), vec![pay("Bob", 60.0), pay("Acme", -40.0)], 1).unwrap();
+        assert_eq!(bc.add_transaction(negative), Err(BlockchainError::InvalidAmount));
+        let mut typo = Transaction::batch_at(acme.clone(), vec![pay("Bob", 1.0), pay("Carol", 1.0)], 1).unwrap();
+        typo.outputs[0].address.pop();
+        assert!(matches!(bc.add_transaction(typo), Err(BlockchainError::InvalidAddress(_))));
+        assert!(bc.pending_transactions.is_empty());
+
+        let payroll = Transaction::batch_at(acme.clone(), vec![pay("Bob", 50.0), pay("Carol", 40.0)], 1)
+            .unwrap()
+            .with_fee(2.0);
+        bc.add_transaction(payroll).unwrap();
+        let block = bc.mine_block_at(GENESIS_TIMESTAMP + 10).unwrap();
+        assert_eq!(block.fees(), 2.0);
+        assert_eq!(bc.get_balance(&acme), 8.0);
+        assert_eq!(bc.get_balance(&bob), 150.0);
+        assert_eq!(bc.get_balance(&address::from_name("Carol")), 140.0);
+
+        // The UTXO model pays every output and returns the change after the fee.
+        let mut utxo = Blockchain::with_ledger(1, LedgerModel::Utxo);
+        let inputs = utxo.select_inputs(&acme, 92.0).unwrap();
+        let payroll = Transaction::batch_at(acme.clone(), vec![pay("Bob", 50.0), pay("Carol", 40.0)], 1)
+            .unwrap()
+            .with_fee(2.0)
+            .spending(inputs);
+        utxo.add_transaction(payroll.clone()).unwrap();
+        utxo.mine_block_at(GENESIS_TIMESTAMP + 10).unwrap();
+        assert_eq!(utxo.get_balance(&acme), 8.0);
+        assert_eq!(utxo.get_balance(&bob), 150.0);
+        assert_eq!(utxo.select_inputs(&acme, 8.0), Ok(vec![OutPoint { txid: payroll.hash(), index: 2 }]));
+    }
+
+    #[test]
+    fn test_multisig_spends_need_the_threshold() {
+        use crate::models::multisig::{MultisigAccount, PartiallySignedTransaction};
+        use ed25519_dalek::SigningKey;
+
+        let keys: Vec<SigningKey> = (1..=3).map(|seed| SigningKey::from_bytes(&[seed; 32])).collect();
+        let account = MultisigAccount::new(2, keys.iter().map(|key| key.verifying_key().to_bytes()).collect()).unwrap();
+        let treasury = account.address();
+        let mut bc = Blockchain::new(1);
+
+        let spend = Transaction::new_at(treasury.clone(), address::from_name("Bob"), 40.0, 1);
+        assert_eq!(
+            bc.add_transaction(spend.clone()),
+            Err(BlockchainError::Multisig(MultisigError::NotEnoughSignatures { required: 1, valid: 0 }))
+        );
+        let mut partial = PartiallySignedTransaction::new(spend, account).unwrap();
+        partial.sign(&keys[1]).unwrap();
+        let mut short = partial.transaction.clone();
+        short.multisig = Some(multisig::MultisigSpend {
+            account: partial.account.clone(),
+            signatures: partial.signatures.clone(),
+        });
+        assert_eq!(
+            bc.add_transaction(short.clone()),
+            Err(BlockchainError::Multisig(MultisigError::NotEnoughSignatures { required: 2, valid: 1 }))
+        );
+
+        partial.sign(&keys[2]).unwrap();
+        bc.add_transaction(partial.finalize().unwrap()).unwrap();
+        bc.mine_block_at(GENESIS_TIMESTAMP + 10).unwrap();
+        assert_eq!(bc.get_balance(&treasury), 60.0);
+        assert!(bc.validate_chain().is_ok());
+
+        // A block that sneaks in an under-signed spend does not validate.
+        let mut block = Block::new_at(2, bc.blocks[1].hash, vec![short], GENESIS_TIMESTAMP + 20);
+        block.hash = block.hash();
+        let mut blocks = (*bc.blocks).clone();
+        blocks.push(block);
+        bc.blocks = Arc::new(blocks);
+        assert!(matches!(bc.validate_chain(), Err(BlockchainError::Multisig(_))));
+    }
+
+    #[test]
+    fn test_locked_transactions_wait_in_the_mempool() {
+        let (alice, bob) = (address::from_name("Alice"), address::from_name("Bob"));
+        let mut bc = Blockchain::new(1);
+        let vesting = Transaction::new_at(alice.clone(), bob.clone(), 10.0, 1).locked_until_height(2);
+        let escrow = Transaction::new_at(alice.clone(), bob.clone(), 20.0, 1).locked_until(GENESIS_TIMESTAMP + 1_000);
+        let now = Transaction::new_at(alice.clone(), bob.clone(), 1.0, 1);
+        for transaction in [vesting.clone(), escrow.clone(), now.clone()] {
+            bc.add_transaction(transaction).unwrap();
+        }
+
+        let first = bc.mine_block_at(GENESIS_TIMESTAMP + 500).unwrap();
+        assert_eq!(first.transactions, vec![now]);
+        assert_eq!(bc.pending_transactions, vec![vesting.clone(), escrow.clone()]);
+        let second = bc.mine_block_at(GENESIS_TIMESTAMP + 600).unwrap();
+        assert_eq!(second.transactions, vec![vesting]);
+        assert_eq!(bc.mine_block_at(GENESIS_TIMESTAMP + 700), Err(BlockchainError::EmptyTransactions));
+        assert_eq!(bc.mine_block_at(GENESIS_TIMESTAMP + 1_000).unwrap().transactions, vec![escrow.clone()]);
+        assert_eq!(bc.get_balance(&bob), 131.0);
+        assert!(bc.validate_chain().is_ok());
+
+        // A block that includes the escrow too early is refused and does not validate.
+        let mut early = Blockchain::new(0);
+        let mut block = Block::new_at(1, early.blocks[0].hash, vec![escrow], GENESIS_TIMESTAMP + 999);
+        block.hash = block.hash();
+        assert_eq!(early.append_block(block.clone()), Err(BlockchainError::PrematureTransaction));
+        early.blocks = Arc::new(vec![early.blocks[0].clone(), block]);
+        assert_eq!(early.validate_chain(), Err(BlockchainError::PrematureTransaction));
+    }
+
+    #[test]
+    fn test_locked_transactions_cannot_be_mined_early_by_dating_the_block_ahead() {
+        let (alice, bob) = (address::from_name("Alice"), address::from_name("Bob"));
+        let now = Utc::now().timestamp() as u64;
+        let unlock = now + 24 * 60 * 60;
+        let escrow = Transaction::new_at(alice, bob.clone(), 20.0, 1).locked_until(unlock);
+        let mut bc = Blockchain::new(0);
+        bc.add_transaction(escrow.clone()).unwrap();
+        assert_eq!(bc.mine_block_at(now), Err(BlockchainError::EmptyTransactions));
+        assert_eq!(bc.mine_block_at(unlock), Err(BlockchainError::TooFarInFuture));
+        assert_eq!(bc.pending_transactions, vec![escrow.clone()]);
+
+        let mut block = Block::new_at(1, bc.blocks[0].hash, vec![escrow], unlock);
+        block.hash = block.hash();
+        assert_eq!(bc.append_block(block.clone()), Err(BlockchainError::TooFarInFuture));
+        assert!(!bc.replace_chain(Arc::new(vec![bc.blocks[0].clone(), block])));
+        assert_eq!(bc.get_balance(&bob), STARTING_BALANCE);
+    }
+
+    #[test]
+    fn test_script_addresses_need_their_script_satisfied() {
+        use crate::models::script::{Op, Script, ScriptSpend};
+        use sha2::{Digest, Sha256};
+
+        let locking = Script(vec![Op::Sha256, Op::Push(Sha256::digest(b"secret").to_vec()), Op::Equal]);
+        let spend = |preimage: &[u8]| {
+            let mut transaction = Transaction::new_at(locking.address(), address::from_name("Bob"), 25.0, 1);
+            transaction.script = Some(ScriptSpend {
+                locking: locking.clone(),
+                unlocking: Script(vec![Op::Push(preimage.to_vec())]),
+            });
+            transaction
+        };
+        let mut bc = Blockchain::new(0);
+        assert_eq!(
+            bc.add_transaction(Transaction::new_at(locking.address(), address::from_name("Bob"), 25.0, 1)),
+            Err(BlockchainError::Script(ScriptError::MissingScript))
+        );
+        assert_eq!(bc.add_transaction(spend(b"guess")), Err(BlockchainError::Script(ScriptError::EvaluatedFalse)));
+        bc.add_transaction(spend(b"secret")).unwrap();
+        bc.mine_block_at(GENESIS_TIMESTAMP + 10).unwrap();
+        assert_eq!(bc.get_balance(&locking.address()), 75.0);
+        assert!(bc.validate_chain().is_ok());
+
+        let mut block = Block::new_at(2, bc.blocks[1].hash, vec![spend(b"guess")], GENESIS_TIMESTAMP + 20);
+        block.hash = block.hash();
+        bc.blocks = Arc::new(vec![bc.blocks[0].clone(), bc.blocks[1].clone(), block]);
+        assert_eq!(bc.validate_chain(), Err(BlockchainError::Script(ScriptError::EvaluatedFalse)));
+    }
+}
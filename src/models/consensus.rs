@@ -0,0 +1,203 @@
+//! How nodes agree on who may extend the chain.
+//!
+//! A `ConsensusEngine` readies a block its node built for sealing, seals it,
+//! and verifies blocks from everyone else. The chain keeps no other rules
+//! about who proposes: proof of work lets anyone who finds a nonce extend it,
+//! proof of stake (see `stake`) only the validator its seed draws, and proof
+//! of authority (see `authority`) only the validator whose turn it is.
+//!
+//! A validator that is offline would stall the signing engines, so each also
+//! ranks backups behind the in-turn validator. The backup at rank `r` may
+//! sign once the block is dated `r * FALLBACK_SECONDS` past its parent. Blocks
+//! signed in turn weigh more than a backup's, and nodes keep the heaviest
+//! chain, so a backup's block loses to the in-turn one when both are made.
+
+use std::fmt;
+use std::sync::Arc;
+
+use ed25519_dalek::SigningKey;
+use sha2::{Digest, Sha256};
+
+use crate::models::Block;
+use crate::models::address;
+use crate::models::authority::{Authorities, ProofOfAuthority};
+use crate::models::stake::{ProofOfStake, Stakes};
+
+/// How long, in seconds past its parent, a block waits for each validator
+/// ranked ahead of its signer before that signer may step in.
+pub const FALLBACK_SECONDS: u64 = 2;
+
+/// What an engine may consult about the chain a block extends.
+pub struct ChainView<'a> {
+    /// The block being extended.
+    pub parent: &'a Block,
+    /// Leading zero bytes a proof-of-work hash needs.
+    pub difficulty: usize,
+    /// Coins bonded by validators as of `parent`.
+    pub stakes: &'a Stakes,
+    /// Validators taking turns as of `parent`.
+    pub authorities: &'a Authorities,
+}
+
+pub trait ConsensusEngine: fmt::Debug + Send + Sync {
+    /// The validator set a chain starts with, for engines that have one.
+    fn genesis_authorities(&self) -> Authorities {
+        Authorities::default()
+    }
+
+    /// Readies a block built on `view.parent`, failing if this node may not propose it.
+    fn prepare(&self, view: &ChainView, block: &mut Block) -> Result<(), ConsensusError>;
+
+    /// Finishes the block, hash included, so that `verify` accepts it.
+    fn seal(&self, view: &ChainView, block: &mut Block) -> Result<(), ConsensusError>;
+
+    /// Whether a block that extends `view.parent` was proposed and sealed by the rules.
+    fn verify(&self, view: &ChainView, block: &Block) -> Result<(), ConsensusError>;
+
+    /// What a block `verify` accepted adds to its chain's weight. Chains are
+    /// only replaced by heavier ones, so blocks that weigh the same make the
+    /// longest chain win.
+    fn weight(&self, _view: &ChainView, _block: &Block) -> u64 {
+        1
+    }
+}
+
+/// Whether the validator ranked `rank` places behind the in-turn one may
+/// sign `block`: it waits `FALLBACK_SECONDS` past the parent for each.
+pub fn fallback_due(rank: usize, view: &ChainView, block: &Block) -> bool {
+    block.timestamp >= view.parent.timestamp.saturating_add(rank as u64 * FALLBACK_SECONDS)
+}
+
+/// The weight of a block signed by the validator ranked `rank`: twice as
+/// much in turn as from a backup.
+pub fn rank_weight(rank: usize) -> u64 {
+    if rank == 0 { 2 } else { 1 }
+}
+
+/// Anyone may propose a block by grinding its nonce until the hash has
+/// `difficulty` leading zero bytes.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProofOfWork;
+
+impl ConsensusEngine for ProofOfWork {
+    fn prepare(&self, _view: &ChainView, _block: &mut Block) -> Result<(), ConsensusError> {
+        Ok(())
+    }
+
+    fn seal(&self, view: &ChainView, block: &mut Block) -> Result<(), ConsensusError> {
+        let mut hash = block.hash();
+        while !meets_difficulty(&hash, view.difficulty) {
+            block.nonce += 1;
+            hash = block.hash();
+        }
+        block.hash = hash;
+        Ok(())
+    }
+
+    fn verify(&self, view: &ChainView, block: &Block) -> Result<(), ConsensusError> {
+        if meets_difficulty(&block.hash, view.difficulty) {
+            Ok(())
+        } else {
+            Err(ConsensusError::InsufficientWork)
+        }
+    }
+}
+
+fn meets_difficulty(hash: &[u8; 32], difficulty: usize) -> bool {
+    hash.iter().take(difficulty).all(|&b| b == 0)
+}
+
+/// Which engine the nodes of a `Network` run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Consensus {
+    #[default]
+    ProofOfWork,
+    ProofOfStake,
+    /// The nodes with these ids start out as the validators, in turn order.
+    ProofOfAuthority(Vec<String>),
+}
+
+impl Consensus {
+    /// The engine node `node_id` runs, signing with `validator_key(node_id)`
+    /// where the engine signs at all.
+    pub fn engine(&self, node_id: &str) -> Arc<dyn ConsensusEngine> {
+        match self {
+            Consensus::ProofOfWork => Arc::new(ProofOfWork),
+            Consensus::ProofOfStake => Arc::new(ProofOfStake::new(validator_key(node_id))),
+            Consensus::ProofOfAuthority(validators) => {
+                let validators = validators.iter().map(|id| validator_address(id)).collect();
+                Arc::new(ProofOfAuthority::new(validators, validator_key(node_id)))
+            }
+        }
+    }
+}
+
+/// The key a simulated node signs blocks with. Derived from the node's id so
+/// that tests know every validator's address; real validators bring their own.
+pub fn validator_key(node_id: &str) -> SigningKey {
+    SigningKey::from_bytes(&Sha256::digest(format!("validator {}", node_id)).into())
+}
+
+/// The address of `validator_key(node_id)`.
+pub fn validator_address(node_id: &str) -> String {
+    address::from_public_key(&validator_key(node_id).verifying_key().to_bytes())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConsensusError {
+    /// The hash lacks the leading zeros the difficulty asks for.
+    InsufficientWork,
+    /// The node has no key to sign blocks with.
+    NoValidatorKey,
+    /// It is another validator's turn to propose, and too early to step in for it.
+    NotProposer,
+    /// The validator set is empty, so nobody may sign.
+    NoAuthorities,
+    /// The block carries no valid signature.
+    Unsigned,
+    /// The block is signed by someone other than the validator whose turn it
+    /// was, and who was not yet due to step in for it.
+    WrongProposer { expected: String },
+}
+
+impl fmt::Display for ConsensusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConsensusError::InsufficientWork => write!(f, "block hash does not meet the difficulty"),
+            ConsensusError::NoValidatorKey => write!(f, "this node has no validator key"),
+            ConsensusError::NotProposer => write!(f, "it is not this node's turn to propose"),
+            ConsensusError::NoAuthorities => write!(f, "there are no validators to sign blocks"),
+            ConsensusError::Unsigned => write!(f, "block is not validly signed"),
+            ConsensusError::WrongProposer { expected } => write!(f, "block should have been proposed by {}", expected),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Transaction;
+
+    #[test]
+    fn test_proof_of_work_seals_and_verifies() {
+        let parent = Block::new_at(0, [0; 32], vec![], 1);
+        let (stakes, authorities) = (Stakes::default(), Authorities::default());
+        let view = ChainView {
+            parent: &parent,
+            difficulty: 1,
+            stakes: &stakes,
+            authorities: &authorities,
+        };
+        let transaction = Transaction::new_at("Alice".to_string(), "Bob".to_string(), 1.0, 2);
+        let mut block = Block::new_at(1, parent.hash, vec![transaction], 3);
+        ProofOfWork.prepare(&view, &mut block).unwrap();
+        ProofOfWork.seal(&view, &mut block).unwrap();
+        assert_eq!((block.hash, block.hash[0]), (block.hash(), 0));
+        assert_eq!(ProofOfWork.verify(&view, &block), Ok(()));
+
+        block.nonce += 1;
+        block.hash = block.hash();
+        let harder = ChainView { difficulty: 32, ..view };
+        assert_eq!(ProofOfWork.verify(&harder, &block), Err(ConsensusError::InsufficientWork));
+    }
+}
@@ -0,0 +1,77 @@
+//! Lowercase hex encoding for hashes, used wherever they leave the process.
+
+use serde::{Deserialize, Deserializer, Serializer};
+
+pub fn encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn decode(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// Decodes a 32-byte hash, rejecting any other length.
+pub fn decode_hash(text: &str) -> Option<[u8; 32]> {
+    decode(text)?.try_into().ok()
+}
+
+/// Serializes a `[u8; 32]` field as a hex string: `#[serde(with = "hex")]`.
+pub fn serialize<S: Serializer>(hash: &[u8; 32], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&encode(hash))
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[u8; 32], D::Error> {
+    let text = String::deserialize(deserializer)?;
+    decode_hash(&text).ok_or_else(|| serde::de::Error::custom("expected a 32-byte hex hash"))
+}
+
+/// Serializes a byte vector field as a hex string: `#[serde(with = "hex::bytes")]`.
+pub mod bytes {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&super::encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        super::decode(&text).ok_or_else(|| serde::de::Error::custom("expected hex"))
+    }
+}
+
+/// Serializes a list of 32-byte keys as hex strings: `#[serde(with = "hex::list")]`.
+pub mod list {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(hashes: &[[u8; 32]], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(hashes.iter().map(|hash| super::encode(hash)))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<[u8; 32]>, D::Error> {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|text| super::decode_hash(text).ok_or_else(|| serde::de::Error::custom("expected a 32-byte hex hash")))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let hash = [0xab; 32];
+        assert_eq!(decode_hash(&encode(&hash)), Some(hash));
+        assert_eq!(encode(&[0, 15, 255]), "000fff");
+        assert_eq!(decode("0g"), None);
+        assert_eq!(decode("abc"), None);
+        assert_eq!(decode_hash("abcd"), None);
+    }
+}
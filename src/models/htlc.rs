@@ -0,0 +1,355 @@
+//! Hash time-locked contracts, for swapping coins across chains atomically.
+//!
+//! A lock transaction moves `amount` out of the sender's balance into a
+//! contract identified by the lock's hash. The receiver can claim it by
+//! revealing a preimage of the hash lock in a block stamped before the
+//! timeout; from the timeout on, only the sender can take it back. A claim
+//! publishes the secret, which is what lets the other side of a swap claim
+//! its own contract in turn.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::models::{Transaction, hex};
+
+/// What a transaction does with a hash time-locked contract.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Htlc {
+    /// Opens a contract holding the transaction's amount for its receiver.
+    Lock {
+        #[serde(with = "hex")]
+        hash_lock: [u8; 32],
+        timeout: u64,
+    },
+    /// Pays the contract out to its receiver, who reveals the secret.
+    Claim {
+        #[serde(with = "hex")]
+        contract: [u8; 32],
+        #[serde(with = "hex::bytes")]
+        preimage: Vec<u8>,
+    },
+    /// Pays the contract back to its sender once it has timed out.
+    Refund {
+        #[serde(with = "hex")]
+        contract: [u8; 32],
+    },
+}
+
+impl fmt::Display for Htlc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Htlc::Lock { hash_lock, timeout } => {
+                write!(f, "hash-locked to {} until {}", hex::encode(hash_lock), timeout)
+            }
+            Htlc::Claim { contract, preimage } => {
+                write!(f, "claiming {} with {}", hex::encode(contract), hex::encode(preimage))
+            }
+            Htlc::Refund { contract } => write!(f, "refunding {}", hex::encode(contract)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HtlcError {
+    /// A lock pays more than one receiver.
+    MultipleReceivers,
+    /// A lock holds nothing.
+    EmptyLock,
+    /// A lock times out no later than the block including it, or than its own timestamp while pending.
+    TimeoutPassed,
+    /// A lock would open a contract with the id of one already on the chain.
+    AlreadyLocked,
+    UnknownContract,
+    AlreadySettled,
+    /// The claim or refund does not pay the contract's amount to the party entitled to it.
+    WrongParty,
+    WrongPreimage,
+    /// The claim comes at or after the timeout.
+    Expired,
+    /// The refund is not locked until the timeout.
+    NotExpired,
+}
+
+impl fmt::Display for HtlcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HtlcError::MultipleReceivers => write!(f, "a hash-locked contract has a single receiver"),
+            HtlcError::EmptyLock => write!(f, "a hash-locked contract must hold a positive amount"),
+            HtlcError::TimeoutPassed => write!(f, "a hash-locked contract must time out in the future"),
+            HtlcError::AlreadyLocked => write!(f, "a hash-locked contract with that id already exists"),
+            HtlcError::UnknownContract => write!(f, "no such hash-locked contract"),
+            HtlcError::AlreadySettled => write!(f, "contract was already claimed or refunded"),
+            HtlcError::WrongParty => write!(f, "contract pays a different party or amount"),
+            HtlcError::WrongPreimage => write!(f, "preimage does not match the hash lock"),
+            HtlcError::Expired => write!(f, "contract has timed out and can only be refunded"),
+            HtlcError::NotExpired => write!(f, "a refund must be locked until the contract times out"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ContractState {
+    Open,
+    Claimed { preimage: Vec<u8> },
+    Refunded,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Contract {
+    pub sender: String,
+    pub receiver: String,
+    pub amount: f64,
+    pub hash_lock: [u8; 32],
+    pub timeout: u64,
+    pub state: ContractState,
+}
+
+/// Every contract opened on a chain, by the hash of the transaction that locked it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Contracts {
+    contracts: BTreeMap<[u8; 32], Contract>,
+}
+
+impl Contracts {
+    pub fn get(&self, id: &[u8; 32]) -> Option<&Contract> {
+        self.contracts.get(id)
+    }
+
+    /// Opens or settles the contract the transaction refers to. `timestamp`
+    /// is that of the block including it, or `None` while it is pending.
+    pub fn apply(&mut self, transaction: &Transaction, timestamp: Option<u64>) -> Result<(), HtlcError> {
+        self.check(transaction, timestamp)?;
+        match &transaction.htlc {
+            None => {}
+            Some(Htlc::Lock { hash_lock, timeout }) => {
+                let contract = Contract {
+                    sender: transaction.sender.clone(),
+                    receiver: transaction.receiver.clone(),
+                    amount: transaction.amount,
+                    hash_lock: *hash_lock,
+                    timeout: *timeout,
+                    state: ContractState::Open,
+                };
+                self.contracts.insert(transaction.hash(), contract);
+            }
+            Some(Htlc::Claim { contract, preimage }) => {
+                let preimage = preimage.clone();
+                self.contracts.get_mut(contract).unwrap().state = ContractState::Claimed { preimage };
+            }
+            Some(Htlc::Refund { contract }) => {
+                self.contracts.get_mut(contract).unwrap().state = ContractState::Refunded;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether `apply` would accept the transaction, without applying it.
+    pub fn check(&self, transaction: &Transaction, timestamp: Option<u64>) -> Result<(), HtlcError> {
+        let Some(htlc) = &transaction.htlc else {
+            return Ok(());
+        };
+        if !transaction.outputs.is_empty() {
+            return Err(HtlcError::MultipleReceivers);
+        }
+        let id = match htlc {
+            Htlc::Lock { timeout, .. } => {
+                if transaction.amount <= 0.0 {
+                    return Err(HtlcError::EmptyLock);
+                }
+                if *timeout <= timestamp.unwrap_or(transaction.timestamp) {
+                    return Err(HtlcError::TimeoutPassed);
+                }
+                if self.contracts.contains_key(&transaction.hash()) {
+                    return Err(HtlcError::AlreadyLocked);
+                }
+                return Ok(());
+            }
+            Htlc::Claim { contract, .. } | Htlc::Refund { contract } => contract,
+        };
+        let contract = self.contracts.get(id).ok_or(HtlcError::UnknownContract)?;
+        if contract.state != ContractState::Open {
+            return Err(HtlcError::AlreadySettled);
+        }
+        let payee = match htlc {
+            Htlc::Claim { .. } => &contract.receiver,
+            _ => &contract.sender,
+        };
+        if transaction.sender != *payee || transaction.receiver != *payee || transaction.amount != contract.amount {
+            return Err(HtlcError::WrongParty);
+        }
+        match htlc {
+            Htlc::Claim { preimage, .. } => {
+                if hash_lock(preimage) != contract.hash_lock {
+                    return Err(HtlcError::WrongPreimage);
+                }
+                if timestamp.is_some_and(|timestamp| timestamp >= contract.timeout) {
+                    return Err(HtlcError::Expired);
+                }
+            }
+            _ => {
+                // The lock time keeps the refund out of blocks until the timeout.
+                if transaction.lock_time.is_none_or(|lock| lock < contract.timeout) {
+                    return Err(HtlcError::NotExpired);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn hash_lock(secret: &[u8]) -> [u8; 32] {
+    Sha256::digest(secret).into()
+}
+
+/// Locks `amount` for `receiver`, who can claim it with the preimage of
+/// `hash_lock` until `timeout`.
+pub fn lock_at(sender: String, receiver: String, amount: f64, hash_lock: [u8; 32], timeout: u64, timestamp: u64) -> Transaction {
+    let mut transaction = Transaction::new_at(sender, receiver, amount, timestamp);
+    transaction.htlc = Some(Htlc::Lock { hash_lock, timeout });
+    transaction
+}
+
+/// The receiver's claim on the contract `lock` opened. `None` if `lock` is not a lock.
+pub fn claim_at(lock: &Transaction, preimage: Vec<u8>, timestamp: u64) -> Option<Transaction> {
+    let Some(Htlc::Lock { .. }) = lock.htlc else {
+        return None;
+    };
+    let mut transaction = Transaction::new_at(lock.receiver.clone(), lock.receiver.clone(), lock.amount, timestamp);
+    transaction.htlc = Some(Htlc::Claim {
+        contract: lock.hash(),
+        preimage,
+    });
+    Some(transaction)
+}
+
+/// The sender's refund of the contract `lock` opened, locked until its timeout.
+pub fn refund_at(lock: &Transaction, timestamp: u64) -> Option<Transaction> {
+    let Some(Htlc::Lock { timeout, .. }) = lock.htlc else {
+        return None;
+    };
+    let mut transaction = Transaction::new_at(lock.sender.clone(), lock.sender.clone(), lock.amount, timestamp);
+    transaction.htlc = Some(Htlc::Refund { contract: lock.hash() });
+    Some(transaction.locked_until(timeout))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::blockchain::{BlockchainError, GENESIS_TIMESTAMP};
+    use crate::models::{Block, Blockchain, address};
+
+    #[test]
+    fn test_claim_before_and_refund_after_the_timeout() {
+        let (alice, bob) = (address::from_name("Alice"), address::from_name("Bob"));
+        let secret = b"open sesame".to_vec();
+        let mut bc = Blockchain::new(0);
+        let lock = lock_at(alice.clone(), bob.clone(), 40.0, hash_lock(&secret), GENESIS_TIMESTAMP + 1_000, 1);
+        bc.add_transaction(lock.clone()).unwrap();
+        bc.mine_block_at(GENESIS_TIMESTAMP + 100).unwrap();
+        assert_eq!(bc.get_balance(&alice), 60.0);
+        assert_eq!(bc.get_balance(&bob), 100.0);
+
+        let wrong = claim_at(&lock, b"guess".to_vec(), 2).unwrap();
+        assert_eq!(bc.add_transaction(wrong), Err(BlockchainError::Htlc(HtlcError::WrongPreimage)));
+        let mut early = refund_at(&lock, 2).unwrap();
+        early.lock_time = Some(GENESIS_TIMESTAMP + 999);
+        assert_eq!(bc.add_transaction(early), Err(BlockchainError::Htlc(HtlcError::NotExpired)));
+
+        // A claim that misses the timeout is dropped, and the refund goes through.
+        let late = claim_at(&lock, secret.clone(), 3).unwrap();
+        bc.add_transaction(late.clone()).unwrap();
+        let refund = refund_at(&lock, 3).unwrap();
+        assert_eq!(bc.add_transaction(refund.clone()), Err(BlockchainError::Htlc(HtlcError::AlreadySettled)));
+        assert_eq!(bc.mine_block_at(GENESIS_TIMESTAMP + 1_000), Err(BlockchainError::EmptyTransactions));
+        assert!(bc.pending_transactions.is_empty());
+        bc.add_transaction(refund.clone()).unwrap();
+        assert_eq!(bc.mine_block_at(GENESIS_TIMESTAMP + 1_000).unwrap().transactions, vec![refund]);
+        assert_eq!(bc.get_balance(&alice), 100.0);
+        assert_eq!(bc.contracts.get(&lock.hash()).unwrap().state, ContractState::Refunded);
+        assert!(bc.validate_chain().is_ok());
+
+        let mut block = Block::new_at(3, bc.blocks[2].hash, vec![late], GENESIS_TIMESTAMP + 1_001);
+        block.hash = block.hash();
+        assert_eq!(bc.append_block(block), Err(BlockchainError::Htlc(HtlcError::AlreadySettled)));
+    }
+
+    #[test]
+    fn test_claims_are_checked_in_block_validation() {
+        let (alice, bob) = (address::from_name("Alice"), address::from_name("Bob"));
+        let secret = b"open sesame".to_vec();
+        let lock = lock_at(alice, bob.clone(), 40.0, hash_lock(&secret), GENESIS_TIMESTAMP + 1_000, 1);
+        let claim = claim_at(&lock, secret, 2).unwrap();
+
+        let chain = |claimed_at: u64| {
+            let mut bc = Blockchain::new(0);
+            let mut blocks = vec![bc.blocks[0].clone()];
+            for (transaction, timestamp) in [(lock.clone(), GENESIS_TIMESTAMP + 100), (claim.clone(), claimed_at)] {
+                let mut block = Block::new_at(blocks.len() as u64, blocks.last().unwrap().hash, vec![transaction], timestamp);
+                block.hash = block.hash();
+                blocks.push(block);
+            }
+            bc.blocks = std::sync::Arc::new(blocks);
+            bc
+        };
+        assert!(chain(GENESIS_TIMESTAMP + 999).validate_chain().is_ok());
+        assert_eq!(chain(GENESIS_TIMESTAMP + 1_000).validate_chain(), Err(BlockchainError::Htlc(HtlcError::Expired)));
+
+        let mut bc = Blockchain::new(0);
+        assert!(bc.replace_chain(chain(GENESIS_TIMESTAMP + 999).blocks));
+        assert_eq!(bc.get_balance(&bob), 140.0);
+    }
+
+    #[test]
+    fn test_blocks_cannot_be_dated_past_a_timeout_or_back_before_it() {
+        let (alice, bob) = (address::from_name("Alice"), address::from_name("Bob"));
+        let secret = b"open sesame".to_vec();
+        let now = chrono::Utc::now().timestamp() as u64;
+        let timeout = now + 24 * 60 * 60;
+        let lock = lock_at(alice.clone(), bob, 40.0, hash_lock(&secret), timeout, 1);
+        let mut bc = Blockchain::new(0);
+        bc.add_transaction(lock.clone()).unwrap();
+        bc.mine_block_at(now).unwrap();
+
+        // Alice cannot take her refund a day early by dating the block at the timeout.
+        let refund = refund_at(&lock, 2).unwrap();
+        bc.add_transaction(refund.clone()).unwrap();
+        assert_eq!(bc.mine_block_at(timeout), Err(BlockchainError::TooFarInFuture));
+        let mut early = Block::new_at(2, bc.blocks[1].hash, vec![refund], timeout);
+        early.hash = early.hash();
+        assert_eq!(bc.append_block(early.clone()), Err(BlockchainError::TooFarInFuture));
+        let forged = std::sync::Arc::new(vec![bc.blocks[0].clone(), bc.blocks[1].clone(), early]);
+        assert_eq!(Blockchain::new(0).try_replace_chain(forged), Err(BlockchainError::TooFarInFuture));
+        assert_eq!(bc.get_balance(&alice), 60.0);
+
+        // Nor can a block be dated back before its parent.
+        let claim = claim_at(&lock, secret, 3).unwrap();
+        let mut backdated = Block::new_at(2, bc.blocks[1].hash, vec![claim], now - 1);
+        backdated.hash = backdated.hash();
+        assert_eq!(bc.append_block(backdated), Err(BlockchainError::EarlierThanParent));
+    }
+
+    #[test]
+    fn test_locks_must_hold_something_until_a_future_timeout() {
+        let (alice, bob) = (address::from_name("Alice"), address::from_name("Bob"));
+        let lock = |amount: f64, timeout: u64| lock_at(alice.clone(), bob.clone(), amount, hash_lock(b"s"), timeout, 10);
+        let mut bc = Blockchain::new(0);
+        let refused = |transaction: Transaction| bc.clone().add_transaction(transaction);
+        assert_eq!(refused(lock(0.0, 1_000)), Err(BlockchainError::Htlc(HtlcError::EmptyLock)));
+        assert_eq!(refused(lock(5.0, 10)), Err(BlockchainError::Htlc(HtlcError::TimeoutPassed)));
+
+        // Mined after its timeout, a lock that was fine in the mempool is refused.
+        let expired = lock(5.0, GENESIS_TIMESTAMP + 100);
+        let mut stale = Block::new_at(1, bc.blocks[0].hash, vec![expired], GENESIS_TIMESTAMP + 100);
+        stale.hash = stale.hash();
+        assert_eq!(bc.append_block(stale), Err(BlockchainError::Htlc(HtlcError::TimeoutPassed)));
+
+        let mut contracts = Contracts::default();
+        contracts.apply(&lock(5.0, 1_000), None).unwrap();
+        assert_eq!(contracts.check(&lock(5.0, 1_000), None), Err(HtlcError::AlreadyLocked));
+        bc.add_transaction(lock(5.0, 1_000)).unwrap();
+    }
+}
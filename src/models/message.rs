@@ -0,0 +1,32 @@
+use std::sync::Arc;
+
+use crate::models::{Block, Transaction};
+
+/// A typed message exchanged between nodes.
+#[derive(Debug, Clone)]
+pub enum Message {
+    /// Asks to open a connection, carrying the sender's chain height. Answered
+    /// with `Welcome` if accepted or `Disconnect` if refused.
+    Hello { height: u64 },
+    Welcome { height: u64 },
+    Disconnect,
+    GetAddresses,
+    Addresses(Vec<String>),
+    Block(Block),
+    Chain(Arc<Vec<Block>>),
+    /// Asks a peer for its whole chain, sent when its block does not extend ours.
+    GetChain,
+    /// Announces transactions by hash so peers can ask for the ones they lack.
+    Inventory(Vec<[u8; 32]>),
+    GetTransactions(Vec<[u8; 32]>),
+    /// Boxed, as transactions are much larger than the other messages.
+    Transaction(Box<Transaction>),
+}
+
+/// A message in flight from one node to another, routed by the `Network`.
+#[derive(Debug, Clone)]
+pub struct Envelope {
+    pub from: String,
+    pub to: String,
+    pub message: Message,
+}
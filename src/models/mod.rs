@@ -1,10 +1,31 @@
 mod transaction;
 mod block;
-mod blockchain;
-mod node;
+pub mod address;
+pub mod anchor;
+pub mod authority;
+pub mod blockchain;
+pub mod consensus;
+pub mod hex;
+pub mod htlc;
+pub mod node;
+pub mod actor;
+pub mod message;
+pub mod multisig;
 pub mod network;
+pub mod nft;
+pub mod peer;
+pub mod script;
+pub mod seen_cache;
+pub mod simulation;
+pub mod simulator;
+pub mod stake;
+pub mod token;
+pub mod topology;
+pub mod utxo;
+pub mod wallet;
+pub mod wasm;
 
-pub use transaction::Transaction;
+pub use transaction::{Signature, Transaction};
 pub use block::Block;
 pub use blockchain::Blockchain;
 pub use node::Node;
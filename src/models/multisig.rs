@@ -0,0 +1,243 @@
+//! Shared accounts that need M of N keyholders to approve a spend.
+//!
+//! A multisig address commits to its threshold and keys, which stay private
+//! until the account first spends: the spending transaction reveals them in a
+//! `MultisigSpend` alongside the keyholders' signatures. Keyholders approve
+//! in turn, offline, by passing a `PartiallySignedTransaction` around as JSON.
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+use ed25519_dalek::SigningKey;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::models::{Signature, Transaction, address, hex};
+
+/// The most keyholders one account can have.
+pub const MAX_KEYHOLDERS: usize = 15;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MultisigError {
+    /// The threshold is zero or more than the number of keys.
+    InvalidThreshold,
+    TooManyKeys,
+    DuplicateKey,
+    /// The transaction does not spend from this account.
+    WrongAccount,
+    /// The key is not one of the account's keyholders.
+    UnknownKey,
+    AlreadySigned,
+    /// More valid signatures are needed before the spend can be submitted.
+    NotEnoughSignatures { required: usize, valid: usize },
+}
+
+impl fmt::Display for MultisigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MultisigError::InvalidThreshold => write!(f, "threshold must be between 1 and the number of keys"),
+            MultisigError::TooManyKeys => write!(f, "a multisig account has at most {} keys", MAX_KEYHOLDERS),
+            MultisigError::DuplicateKey => write!(f, "a key appears more than once"),
+            MultisigError::WrongAccount => write!(f, "transaction does not spend from the multisig account"),
+            MultisigError::UnknownKey => write!(f, "key is not a keyholder of the account"),
+            MultisigError::AlreadySigned => write!(f, "key has already signed"),
+            MultisigError::NotEnoughSignatures { required, valid } => {
+                write!(f, "{} of {} required signatures are valid", valid, required)
+            }
+        }
+    }
+}
+
+/// The threshold and keys behind a multisig address.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MultisigAccount {
+    pub threshold: usize,
+    /// Sorted, so the same keys give the same address in any order.
+    #[serde(with = "hex::list")]
+    pub public_keys: Vec<[u8; 32]>,
+}
+
+impl MultisigAccount {
+    pub fn new(threshold: usize, mut public_keys: Vec<[u8; 32]>) -> Result<Self, MultisigError> {
+        if public_keys.len() > MAX_KEYHOLDERS {
+            return Err(MultisigError::TooManyKeys);
+        }
+        if threshold == 0 || threshold > public_keys.len() {
+            return Err(MultisigError::InvalidThreshold);
+        }
+        public_keys.sort();
+        if public_keys.windows(2).any(|pair| pair[0] == pair[1]) {
+            return Err(MultisigError::DuplicateKey);
+        }
+        Ok(MultisigAccount { threshold, public_keys })
+    }
+
+    pub fn address(&self) -> String {
+        let hash = Sha256::digest(self.to_string().as_bytes());
+        address::encode(address::MULTISIG_VERSION, hash[..20].try_into().unwrap())
+    }
+
+    /// How many keyholders validly signed `message`; extra signatures by the
+    /// same key, or by strangers, count for nothing.
+    pub fn valid_signatures(&self, signatures: &[Signature], message: &[u8]) -> usize {
+        signatures
+            .iter()
+            .filter(|signature| self.public_keys.contains(&signature.public_key) && signature.verifies(message))
+            .map(|signature| signature.public_key)
+            .collect::<BTreeSet<_>>()
+            .len()
+    }
+}
+
+impl fmt::Display for MultisigAccount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let keys: Vec<String> = self.public_keys.iter().map(|key| hex::encode(key)).collect();
+        write!(f, "{} of {}", self.threshold, keys.join(", "))
+    }
+}
+
+/// What a transaction from a multisig account carries to prove it was approved.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MultisigSpend {
+    pub account: MultisigAccount,
+    pub signatures: Vec<Signature>,
+}
+
+impl fmt::Display for MultisigSpend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let signatures: Vec<String> = self.signatures.iter().map(Signature::to_string).collect();
+        write!(f, "under {} signed by {}", self.account, signatures.join(", "))
+    }
+}
+
+/// Whether a transaction from a multisig address reveals the account behind
+/// it and carries enough valid signatures of its signing input.
+pub fn verify(transaction: &Transaction) -> Result<(), MultisigError> {
+    let Some(spend) = &transaction.multisig else {
+        return Err(MultisigError::NotEnoughSignatures { required: 1, valid: 0 });
+    };
+    if spend.account.address() != transaction.sender {
+        return Err(MultisigError::WrongAccount);
+    }
+    let valid = spend
+        .account
+        .valid_signatures(&spend.signatures, transaction.signing_input().as_bytes());
+    if valid < spend.account.threshold {
+        return Err(MultisigError::NotEnoughSignatures { required: spend.account.threshold, valid });
+    }
+    Ok(())
+}
+
+/// A spend from a multisig account that keyholders sign one after another.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PartiallySignedTransaction {
+    pub transaction: Transaction,
+    pub account: MultisigAccount,
+    pub signatures: Vec<Signature>,
+}
+
+impl PartiallySignedTransaction {
+    pub fn new(transaction: Transaction, account: MultisigAccount) -> Result<Self, MultisigError> {
+        if transaction.sender != account.address() {
+            return Err(MultisigError::WrongAccount);
+        }
+        Ok(PartiallySignedTransaction {
+            transaction,
+            account,
+            signatures: vec![],
+        })
+    }
+
+    pub fn sign(&mut self, key: &SigningKey) -> Result<(), MultisigError> {
+        let public_key = key.verifying_key().to_bytes();
+        if !self.account.public_keys.contains(&public_key) {
+            return Err(MultisigError::UnknownKey);
+        }
+        if self.signatures.iter().any(|signature| signature.public_key == public_key) {
+            return Err(MultisigError::AlreadySigned);
+        }
+        let message = self.transaction.signing_input();
+        self.signatures.push(Signature::new(key, message.as_bytes()));
+        Ok(())
+    }
+
+    /// How many more keyholders need to sign.
+    pub fn missing_signatures(&self) -> usize {
+        let message = self.transaction.signing_input();
+        let valid = self.account.valid_signatures(&self.signatures, message.as_bytes());
+        self.account.threshold.saturating_sub(valid)
+    }
+
+    /// The transaction ready to submit, once enough keyholders have signed.
+    pub fn finalize(self) -> Result<Transaction, MultisigError> {
+        let mut transaction = self.transaction;
+        transaction.multisig = Some(MultisigSpend {
+            account: self.account,
+            signatures: self.signatures,
+        });
+        verify(&transaction)?;
+        Ok(transaction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(count: u8) -> Vec<SigningKey> {
+        (1..=count).map(|seed| SigningKey::from_bytes(&[seed; 32])).collect()
+    }
+
+    fn public_keys(keys: &[SigningKey]) -> Vec<[u8; 32]> {
+        keys.iter().map(|key| key.verifying_key().to_bytes()).collect()
+    }
+
+    #[test]
+    fn test_account_rules() {
+        let keys = keys(3);
+        let account = MultisigAccount::new(2, public_keys(&keys)).unwrap();
+        let mut reversed = public_keys(&keys);
+        reversed.reverse();
+        assert_eq!(MultisigAccount::new(2, reversed).unwrap().address(), account.address());
+        assert_eq!(address::decode(&account.address()).unwrap().0, address::MULTISIG_VERSION);
+        assert_ne!(MultisigAccount::new(3, public_keys(&keys)).unwrap().address(), account.address());
+
+        assert_eq!(MultisigAccount::new(0, public_keys(&keys)), Err(MultisigError::InvalidThreshold));
+        assert_eq!(MultisigAccount::new(4, public_keys(&keys)), Err(MultisigError::InvalidThreshold));
+        let twice = vec![public_keys(&keys)[0]; 2];
+        assert_eq!(MultisigAccount::new(1, twice), Err(MultisigError::DuplicateKey));
+        assert_eq!(MultisigAccount::new(1, vec![[0; 32]; 16]), Err(MultisigError::TooManyKeys));
+    }
+
+    #[test]
+    fn test_keyholders_sign_in_turn() {
+        let keys = keys(3);
+        let account = MultisigAccount::new(2, public_keys(&keys)).unwrap();
+        let spend = Transaction::new_at(account.address(), address::from_name("Bob"), 10.0, 1);
+        let mut partial = PartiallySignedTransaction::new(spend, account.clone()).unwrap();
+        assert_eq!(partial.missing_signatures(), 2);
+
+        partial.sign(&keys[0]).unwrap();
+        assert_eq!(partial.sign(&keys[0]), Err(MultisigError::AlreadySigned));
+        assert_eq!(partial.sign(&SigningKey::from_bytes(&[9; 32])), Err(MultisigError::UnknownKey));
+        assert_eq!(
+            partial.clone().finalize(),
+            Err(MultisigError::NotEnoughSignatures { required: 2, valid: 1 })
+        );
+
+        // The second keyholder signs a copy that travelled as JSON.
+        let mut received: PartiallySignedTransaction =
+            serde_json::from_str(&serde_json::to_string(&partial).unwrap()).unwrap();
+        received.sign(&keys[2]).unwrap();
+        assert_eq!(received.missing_signatures(), 0);
+        let transaction = received.finalize().unwrap();
+        assert_eq!(verify(&transaction), Ok(()));
+
+        let mut tampered = transaction.clone();
+        tampered.amount = 90.0;
+        assert_eq!(verify(&tampered), Err(MultisigError::NotEnoughSignatures { required: 2, valid: 0 }));
+        let mut unsigned = transaction;
+        unsigned.multisig = None;
+        assert!(verify(&unsigned).is_err());
+    }
+}
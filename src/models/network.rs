@@ -1,78 +1,344 @@
-use std::{collections::HashMap, sync::Arc};
-use std::sync::{Mutex, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::collections::{HashMap, hash_map::Entry};
+use std::time::{Duration, Instant};
+
 use async_trait::async_trait;
+use tokio::sync::mpsc;
 
-use crate::models::{Block, Node, Transaction, node::NodeError};
+use crate::models::actor::{NodeCommand, NodeHandle, NodeStatus, spawn_node};
+use crate::models::consensus::Consensus;
+use crate::models::message::Envelope;
+use crate::models::peer::{Direction, PeerConfig};
+use crate::models::simulator::{LinkModel, LinkStats};
+use crate::models::topology::{Topology, TopologyError};
+use crate::models::node::{GossipMetrics, NodeError};
+use crate::models::{Block, Node, Transaction};
 
+/// Routes messages between node tasks.
+///
+/// Each node runs on its own task and owns its state; the network only keeps
+/// handles to their inboxes, so no lock is ever held while a node is working.
 pub struct Network {
-    pub nodes: Arc<RwLock<HashMap<String, Arc<Mutex<Node>>>>>,
+    nodes: Arc<RwLock<HashMap<String, NodeHandle>>>,
+    /// Nodes that were stopped with their state intact, waiting to be restarted.
+    stopped: Mutex<HashMap<String, Node>>,
+    router: mpsc::UnboundedSender<Envelope>,
+    links: Arc<Mutex<LinkModel>>,
+    in_flight: Arc<AtomicUsize>,
+    /// The engine nodes added from now on run.
+    consensus: Consensus,
 }
 
 impl Network {
+    /// Creates an empty network with instant, lossless links. Must be called
+    /// from within a tokio runtime.
     pub fn new() -> Self {
+        Self::with_link_model(LinkModel::new(0))
+    }
+
+    /// Creates an empty network whose messages are delayed, dropped and
+    /// partitioned by `links`. Scheduled partition events are timed from now.
+    pub fn with_link_model(links: LinkModel) -> Self {
+        let nodes: Arc<RwLock<HashMap<String, NodeHandle>>> = Arc::new(RwLock::new(HashMap::new()));
+        let links = Arc::new(Mutex::new(links));
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let (router, envelopes) = mpsc::unbounded_channel();
+        tokio::spawn(route(
+            envelopes,
+            Arc::clone(&nodes),
+            Arc::clone(&links),
+            Arc::clone(&in_flight),
+        ));
+
         Network {
-            nodes: Arc::new(RwLock::new(HashMap::new())),
+            nodes,
+            stopped: Mutex::new(HashMap::new()),
+            router,
+            links,
+            in_flight,
+            consensus: Consensus::default(),
+        }
+    }
+
+    /// Runs `consensus` on every node added from now on, instead of proof of work.
+    pub fn with_consensus(mut self, consensus: Consensus) -> Self {
+        self.consensus = consensus;
+        self
+    }
+
+    /// Creates a network with a node for every node in `topology` and a
+    /// connection for every edge. Slot limits are raised to fit the busiest
+    /// node so that no edge is refused.
+    pub async fn from_topology(topology: &Topology, difficulty: usize) -> Self {
+        Self::from_topology_with_link_model(topology, difficulty, LinkModel::new(0)).await
+    }
+
+    /// Like `from_topology`, with messages delayed, dropped and partitioned by `links`.
+    pub async fn from_topology_with_link_model(topology: &Topology, difficulty: usize, links: LinkModel) -> Self {
+        let busiest = topology.nodes.iter().map(|node| topology.degree(node)).max().unwrap_or(0);
+        let defaults = PeerConfig::default();
+        let peer_config = PeerConfig {
+            max_inbound: defaults.max_inbound.max(busiest),
+            max_outbound: defaults.max_outbound.max(busiest),
+            ..defaults
+        };
+        let network = Network::with_link_model(links);
+        for node_id in &topology.nodes {
+            network.add_node_with_peer_config(node_id.clone(), difficulty, peer_config).await;
+        }
+        for (a, b) in &topology.edges {
+            network.connect_nodes(a, b).await;
+        }
+        network
+    }
+
+    pub async fn full_mesh(count: usize, difficulty: usize) -> Self {
+        Self::from_topology(&Topology::full_mesh(count), difficulty).await
+    }
+
+    pub async fn ring(count: usize, difficulty: usize) -> Self {
+        Self::from_topology(&Topology::ring(count), difficulty).await
+    }
+
+    pub async fn star(count: usize, difficulty: usize) -> Self {
+        Self::from_topology(&Topology::star(count), difficulty).await
+    }
+
+    pub async fn line(count: usize, difficulty: usize) -> Self {
+        Self::from_topology(&Topology::line(count), difficulty).await
+    }
+
+    pub async fn random_regular(count: usize, degree: usize, seed: u64, difficulty: usize) -> Result<Self, TopologyError> {
+        let topology = Topology::random_regular(count, degree, seed)?;
+        Ok(Self::from_topology(&topology, difficulty).await)
+    }
+
+    pub async fn erdos_renyi(count: usize, probability: f64, seed: u64, difficulty: usize) -> Self {
+        Self::from_topology(&Topology::erdos_renyi(count, probability, seed), difficulty).await
+    }
+
+    /// Builds a network from a JSON or edge-list file; see `Topology::load`.
+    pub async fn load_topology(path: &str, difficulty: usize) -> Result<Self, TopologyError> {
+        let topology = Topology::load(path)?;
+        Ok(Self::from_topology(&topology, difficulty).await)
+    }
+
+    /// The connections as they currently stand, which may have drifted from
+    /// the starting topology through peer discovery, churn and bans.
+    pub async fn topology(&self) -> Topology {
+        let statuses = self.statuses().await;
+        let mut topology = Topology::new(statuses.iter().map(|status| status.id.clone()).collect());
+        for status in &statuses {
+            for peer in &status.peers {
+                topology.connect(&status.id, peer);
+            }
         }
+        topology
+    }
+
+    /// A handle to a running node, for driving it directly, e.g. from an RPC server.
+    pub fn handle(&self, node_id: &str) -> Option<NodeHandle> {
+        self.nodes.read().unwrap().get(node_id).cloned()
     }
 
     pub async fn add_node(&self, node_id: String, difficulty: usize) {
+        self.add_node_with_peer_config(node_id, difficulty, PeerConfig::default()).await;
+    }
+
+    /// Adds a node with its own connection limits and ban policy.
+    pub async fn add_node_with_peer_config(&self, node_id: String, difficulty: usize, peer_config: PeerConfig) {
         let mut nodes = self.nodes.write().unwrap();
-        if !nodes.contains_key(&node_id) {
-            let node = Arc::new(Mutex::new(Node::new(node_id.clone(), difficulty)));
-            nodes.insert(node_id, node);
+        if let Entry::Vacant(entry) = nodes.entry(node_id.clone()) {
+            let engine = self.consensus.engine(&node_id);
+            let node = Node::new(node_id, difficulty).with_peer_config(peer_config).with_consensus(engine);
+            entry.insert(spawn_node(node, self.router.clone(), Arc::clone(&self.in_flight)));
         }
     }
 
+    /// Opens a connection from `node_id1` to `node_id2`. Either side may refuse
+    /// it, for example when its slots are full or it has banned the other.
     pub async fn connect_nodes(&self, node_id1: &str, node_id2: &str) {
-        let nodes = self.nodes.read().unwrap();
-        if let (Some(node1), Some(node2)) = (nodes.get(node_id1), nodes.get(node_id2)) {
-            node1.lock().unwrap().peers.push(node_id2.to_string());
-            node2.lock().unwrap().peers.push(node_id1.to_string());
+        if node_id1 == node_id2 {
+            return;
+        }
+        if let (Some(node1), Some(node2)) = (self.handle(node_id1), self.handle(node_id2)) {
+            let _ = node1.send(NodeCommand::Connect(node_id2.to_string(), Direction::Outbound));
+            let _ = node2.send(NodeCommand::Connect(node_id1.to_string(), Direction::Inbound));
         }
     }
 
-    pub async fn broadcast_block(&self, node_id: &str, block: Block) {
-        let nodes = self.nodes.read().unwrap();
-        if let Some(node) = nodes.get(node_id) {
-            let node = node.lock().unwrap();
-            for peer in &node.peers {
-                if let Some(peer_node) = nodes.get(peer) {
-                    let mut peer_node = peer_node.lock().unwrap();
-                    if peer_node.receive_block(block.clone()).is_ok(){
-                        println!("Node {} accepted block #{} from {}", peer_node.id, block.index, node_id);
-                    }
-                }
+    /// Closes the connection between two nodes on both sides. Peer discovery
+    /// may reconnect them later.
+    pub async fn disconnect_nodes(&self, node_id1: &str, node_id2: &str) {
+        if let Some(node1) = self.handle(node_id1) {
+            let _ = node1.send(NodeCommand::Disconnect(node_id2.to_string()));
+        }
+        if let Some(node2) = self.handle(node_id2) {
+            let _ = node2.send(NodeCommand::Disconnect(node_id1.to_string()));
+        }
+    }
+
+    /// Shuts a node down and removes it for good. Every other node forgets its address.
+    pub async fn remove_node(&self, node_id: &str) -> Result<(), NodeError> {
+        let handle = self.nodes.write().unwrap().remove(node_id);
+        match handle {
+            Some(handle) => {
+                handle.stop().await?;
+            }
+            None => {
+                self.stopped.lock().unwrap().remove(node_id).ok_or(NodeError::NodeNotFound)?;
             }
         }
+
+        let remaining: Vec<NodeHandle> = self.nodes.read().unwrap().values().cloned().collect();
+        for handle in remaining {
+            let _ = handle.send(NodeCommand::ForgetPeer(node_id.to_string()));
+        }
+        for node in self.stopped.lock().unwrap().values_mut() {
+            node.forget_peer(node_id);
+        }
+        Ok(())
+    }
+
+    /// Shuts a node down, closing its connections but keeping its chain,
+    /// mempool and known addresses for `restart_node`.
+    pub async fn stop_node(&self, node_id: &str) -> Result<(), NodeError> {
+        let handle = self.nodes.write().unwrap().remove(node_id).ok_or(NodeError::NodeNotFound)?;
+        let node = handle.stop().await?;
+        self.stopped.lock().unwrap().insert(node_id.to_string(), node);
+        Ok(())
+    }
+
+    /// Starts a stopped node again. It redials its known peers and catches up
+    /// with any that have moved ahead while it was away.
+    pub async fn restart_node(&self, node_id: &str) -> Result<(), NodeError> {
+        let node = self.stopped.lock().unwrap().remove(node_id).ok_or(NodeError::NodeNotFound)?;
+        let handle = spawn_node(node, self.router.clone(), Arc::clone(&self.in_flight));
+        let _ = handle.send(NodeCommand::Rejoin);
+        self.nodes.write().unwrap().insert(node_id.to_string(), handle);
+        Ok(())
+    }
+
+    /// The ids of the running nodes, sorted. Use `handle` to reach one.
+    pub fn nodes(&self) -> Vec<String> {
+        let mut ids: Vec<String> = self.nodes.read().unwrap().keys().cloned().collect();
+        ids.sort();
+        ids
+    }
+
+    pub fn stopped_nodes(&self) -> Vec<String> {
+        let mut ids: Vec<String> = self.stopped.lock().unwrap().keys().cloned().collect();
+        ids.sort();
+        ids
+    }
+
+    pub async fn broadcast_block(&self, node_id: &str, block: Block) {
+        if let Some(node) = self.handle(node_id) {
+            let _ = node.send(NodeCommand::BroadcastBlock(block));
+        }
     }
 
     pub async fn broadcast_chain(&self, node_id: &str) {
-        let nodes = self.nodes.read().unwrap();
-        if let Some(node) = nodes.get(node_id) {
-            let node = node.lock().unwrap();
-            let chain = node.blockchain.blocks.clone();
-            let peers = node.peers.clone();
-            drop(node);
-
-            for peer in &peers {
-                if let Some(peer_node) = nodes.get(peer) {
-                    let mut peer_node = peer_node.lock().unwrap();
-                    if peer_node.receive_chain(&*chain) {
-                        println!("Node {} accepted chain from {} (length: {})", peer_node.id, node_id, chain.len());
-                    }
-                }
-            }
+        if let Some(node) = self.handle(node_id) {
+            let _ = node.send(NodeCommand::BroadcastChain);
         }
     }
 
     pub async fn add_transaction_to_node(&self, node_id: &str, transaction: Transaction) -> Result<(), NodeError> {
-        let nodes = self.nodes.read().unwrap();
-        if let Some(node) = nodes.get(node_id) {
-            let mut node = node.lock().unwrap();
-            return node.blockchain.add_transaction(transaction).map_err(|_| NodeError::InvalidTransaction);
-        } else {
-            Err(NodeError::NodeNotFound)
+        let node = self.handle(node_id).ok_or(NodeError::NodeNotFound)?;
+        node.submit_transaction(transaction).await
+    }
+
+    pub async fn mine_block(&self, node_id: &str) -> Result<Block, NodeError> {
+        let node = self.handle(node_id).ok_or(NodeError::NodeNotFound)?;
+        node.mine_block().await
+    }
+
+    pub async fn node_status(&self, node_id: &str) -> Result<NodeStatus, NodeError> {
+        let node = self.handle(node_id).ok_or(NodeError::NodeNotFound)?;
+        node.status().await
+    }
+
+    pub async fn statuses(&self) -> Vec<NodeStatus> {
+        let handles: Vec<NodeHandle> = self.nodes.read().unwrap().values().cloned().collect();
+        let mut statuses = Vec::with_capacity(handles.len());
+        for handle in handles {
+            if let Ok(status) = handle.status().await {
+                statuses.push(status);
+            }
         }
+        statuses.sort_by(|a, b| a.id.cmp(&b.id));
+        statuses
+    }
+
+    /// Transaction gossip counters summed over every node.
+    pub async fn gossip_metrics(&self) -> GossipMetrics {
+        let mut total = GossipMetrics::default();
+        for status in self.statuses().await {
+            total += status.gossip;
+        }
+        total
+    }
+
+    /// Cuts the network into groups that cannot reach each other until `heal` is called.
+    pub fn partition(&self, groups: Vec<Vec<String>>) {
+        self.links.lock().unwrap().partition(groups);
+    }
+
+    pub fn heal(&self) {
+        self.links.lock().unwrap().heal();
+    }
+
+    pub fn link_stats(&self) -> LinkStats {
+        self.links.lock().unwrap().stats()
+    }
+
+    /// Waits until every queued command and message in flight has been processed.
+    pub async fn settle(&self) {
+        while self.in_flight.load(Ordering::SeqCst) > 0 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    }
+}
+
+impl Default for Network {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+async fn route(
+    mut envelopes: mpsc::UnboundedReceiver<Envelope>,
+    nodes: Arc<RwLock<HashMap<String, NodeHandle>>>,
+    links: Arc<Mutex<LinkModel>>,
+    in_flight: Arc<AtomicUsize>,
+) {
+    let started = Instant::now();
+    while let Some(envelope) = envelopes.recv().await {
+        let fate = links.lock().unwrap().route(started.elapsed(), &envelope);
+        match fate {
+            None => {
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+            }
+            Some(delay) if delay.is_zero() => deliver(&nodes, &in_flight, envelope),
+            Some(delay) => {
+                let nodes = Arc::clone(&nodes);
+                let in_flight = Arc::clone(&in_flight);
+                tokio::spawn(async move {
+                    tokio::time::sleep(delay).await;
+                    deliver(&nodes, &in_flight, envelope);
+                });
+            }
+        }
+    }
+}
+
+fn deliver(nodes: &RwLock<HashMap<String, NodeHandle>>, in_flight: &AtomicUsize, envelope: Envelope) {
+    let destination = nodes.read().unwrap().get(&envelope.to).cloned();
+    let delivered = destination.is_some_and(|node| node.forward(envelope));
+    if !delivered {
+        in_flight.fetch_sub(1, Ordering::SeqCst);
     }
 }
 
@@ -85,21 +351,391 @@ pub trait DisplayAsync {
 impl DisplayAsync for Network{
     async fn fmt_async(&self) -> String {
         let mut output = String::new();
-        let nodes = self.nodes.read().unwrap();
         output.push_str("\n========== NETWORK STATUS ==========");
-        for (node_id, node_arc) in nodes.iter() {
-            let node = node_arc.lock().unwrap();
-            output.push_str(&format!("\nNode: {}", node_id));
-            output.push_str(&format!("\nChain length: {}", node.blockchain.blocks.len()));
-            if let Some(last_block) = node.blockchain.blocks.last() {
+        for status in self.statuses().await {
+            output.push_str(&format!("\nNode: {}", status.id));
+            output.push_str(&format!("\nChain length: {}", status.blockchain.blocks.len()));
+            if let Some(last_block) = status.blockchain.blocks.last() {
                 output.push_str(&format!("\nLatest block hash: {:?}", last_block.hash));
             } else {
                 output.push_str("\nLatest block hash: N/A");
             }
-            output.push_str(&format!("\nPending transactions: {}", node.blockchain.pending_transactions.len()));
-            output.push_str(&format!("\nPeers: {:?}", node.peers));
+            output.push_str(&format!("\nPending transactions: {}", status.blockchain.pending_transactions.len()));
+            output.push_str(&format!("\nPeers: {:?}", status.peers));
         }
         output.push_str("\n====================================\n");
         output
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::address;
+    use crate::models::simulator::LinkConfig;
+
+    #[tokio::test]
+    async fn test_concurrent_broadcasts_do_not_deadlock() {
+        let network = Network::new();
+        for id in ["A", "B", "C"] {
+            network.add_node(id.to_string(), 0).await;
+        }
+        network.connect_nodes("A", "B").await;
+        network.connect_nodes("B", "C").await;
+        network.connect_nodes("A", "C").await;
+
+        // Every node pushes its chain to every other node at the same time.
+        for _ in 0..50 {
+            for id in ["A", "B", "C"] {
+                network.broadcast_chain(id).await;
+            }
+        }
+        tokio::time::timeout(Duration::from_secs(5), network.settle())
+            .await
+            .expect("network did not settle");
+    }
+
+    #[tokio::test]
+    async fn test_mined_block_reaches_peers() {
+        let network = Network::new();
+        network.add_node("A".to_string(), 1).await;
+        network.add_node("B".to_string(), 1).await;
+        network.connect_nodes("A", "B").await;
+
+        network
+            .add_transaction_to_node("A", Transaction::new(address::from_name("Alice"), address::from_name("Bob"), 0.0))
+            .await
+            .unwrap();
+        let block = network.mine_block("A").await.unwrap();
+        network.broadcast_block("A", block.clone()).await;
+        network.settle().await;
+
+        let status = network.node_status("B").await.unwrap();
+        assert_eq!(status.blockchain.blocks.last(), Some(&block));
+        assert_eq!(status.peers, vec!["A".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_partition_blocks_delivery_until_healed() {
+        let links = LinkModel::new(3).with_default_link(LinkConfig {
+            latency: Duration::from_millis(5),
+            jitter: Duration::from_millis(5),
+            ..LinkConfig::default()
+        });
+        let network = Network::with_link_model(links);
+        network.add_node("A".to_string(), 1).await;
+        network.add_node("B".to_string(), 1).await;
+        network.connect_nodes("A", "B").await;
+        network.partition(vec![vec!["A".to_string()], vec!["B".to_string()]]);
+
+        network
+            .add_transaction_to_node("A", Transaction::new(address::from_name("Alice"), address::from_name("Bob"), 0.0))
+            .await
+            .unwrap();
+        let block = network.mine_block("A").await.unwrap();
+        network.broadcast_block("A", block.clone()).await;
+        network.settle().await;
+        assert_eq!(network.node_status("B").await.unwrap().blockchain.blocks.len(), 1);
+        // At least the transaction announcement and the block were cut off.
+        assert!(network.link_stats().partitioned >= 2);
+
+        network.heal();
+        network.broadcast_chain("A").await;
+        network.settle().await;
+        let status = network.node_status("B").await.unwrap();
+        assert_eq!(status.blockchain.blocks.last(), Some(&block));
+    }
+
+    #[tokio::test]
+    async fn test_transaction_gossips_to_every_mempool() {
+        let network = Network::new();
+        for id in ["A", "B", "C", "D"] {
+            network.add_node(id.to_string(), 1).await;
+        }
+        network.connect_nodes("A", "B").await;
+        network.connect_nodes("B", "C").await;
+        network.connect_nodes("C", "D").await;
+        network.connect_nodes("D", "A").await;
+
+        let transaction = Transaction::new(address::from_name("Alice"), address::from_name("Bob"), 10.0);
+        network.add_transaction_to_node("A", transaction.clone()).await.unwrap();
+        network.settle().await;
+
+        for status in network.statuses().await {
+            assert_eq!(status.blockchain.pending_transactions, vec![transaction.clone()], "{}", status.id);
+        }
+        let metrics = network.gossip_metrics().await;
+        assert_eq!(metrics.accepted, 3);
+        assert_eq!(metrics.duplicates + metrics.accepted, metrics.transactions_received);
+    }
+
+    #[tokio::test]
+    async fn test_block_relays_beyond_direct_peers() {
+        let network = Network::new();
+        for id in ["A", "B", "C", "D"] {
+            network.add_node(id.to_string(), 1).await;
+        }
+        network.connect_nodes("A", "B").await;
+        network.connect_nodes("B", "C").await;
+        network.connect_nodes("C", "D").await;
+
+        network
+            .add_transaction_to_node("A", Transaction::new(address::from_name("Alice"), address::from_name("Bob"), 1.0))
+            .await
+            .unwrap();
+        let block = network.mine_block("A").await.unwrap();
+        network.broadcast_block("A", block.clone()).await;
+        network.settle().await;
+
+        for status in network.statuses().await {
+            assert_eq!(status.blockchain.blocks.last(), Some(&block), "{}", status.id);
+            assert!(status.blockchain.pending_transactions.is_empty(), "{}", status.id);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_proof_of_stake_runs_beside_proof_of_work() {
+        use crate::models::blockchain::BlockchainError;
+        use crate::models::consensus::{ConsensusError, validator_address, validator_key};
+        use crate::models::stake::{StakeOp, stake_at};
+
+        let pow = Network::new();
+        let pos = Network::new().with_consensus(Consensus::ProofOfStake);
+        for network in [&pow, &pos] {
+            for id in ["A", "B"] {
+                network.add_node(id.to_string(), 1).await;
+            }
+            network.connect_nodes("A", "B").await;
+        }
+        for id in ["A", "B"] {
+            let mut bond = stake_at(validator_address(id), StakeOp::Bond, 50.0, 1);
+            bond.sign(&validator_key(id));
+            pos.add_transaction_to_node("A", bond).await.unwrap();
+        }
+        // Nothing is bonded yet, so any validator may propose the bonds.
+        let bonds = pos.mine_block("A").await.unwrap();
+        assert_eq!(bonds.signer(), Some(validator_address("A")));
+        pos.broadcast_block("A", bonds.clone()).await;
+        pos.settle().await;
+
+        let stakes = pos.statuses().await[1].blockchain.stakes.clone();
+        let leader = stakes.leader(stakes.seed()).unwrap().to_string();
+        let (leader, other) = if leader == validator_address("A") { ("A", "B") } else { ("B", "A") };
+        let transfer = Transaction::new(address::from_name("Alice"), address::from_name("Bob"), 1.0);
+        pos.add_transaction_to_node(other, transfer).await.unwrap();
+        pos.settle().await;
+        assert!(matches!(
+            pos.mine_block(other).await,
+            Err(NodeError::Mining(BlockchainError::Consensus(ConsensusError::NotProposer)))
+        ));
+        let block = pos.mine_block(leader).await.unwrap();
+        pos.broadcast_block(leader, block.clone()).await;
+        pos.settle().await;
+        for status in pos.statuses().await {
+            assert_eq!(status.blockchain.blocks.last(), Some(&block), "{}", status.id);
+        }
+
+        let mined = mine_and_broadcast(&pow, "B", 1.0).await;
+        assert_eq!((mined.signature, mined.hash[0]), (None, 0));
+        for status in pow.statuses().await {
+            assert_eq!(status.blockchain.blocks.last().unwrap().hash, mined.hash, "{}", status.id);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_validators_step_in_for_an_offline_leader() {
+        use crate::models::consensus::{validator_address, validator_key};
+        use crate::models::stake::{StakeOp, stake_at};
+
+        let ids = ["A", "B", "C"];
+        let network = Network::new().with_consensus(Consensus::ProofOfStake);
+        for id in ids {
+            network.add_node(id.to_string(), 0).await;
+        }
+        network.connect_nodes("A", "B").await;
+        network.connect_nodes("B", "C").await;
+        network.connect_nodes("A", "C").await;
+        for id in ids {
+            let mut bond = stake_at(validator_address(id), StakeOp::Bond, 50.0, 1);
+            bond.sign(&validator_key(id));
+            network.add_transaction_to_node("A", bond).await.unwrap();
+        }
+        let bonds = network.mine_block("A").await.unwrap();
+        network.broadcast_block("A", bonds).await;
+        network.settle().await;
+
+        // The validator drawn for the next block goes offline for good.
+        let stakes = network.node_status("A").await.unwrap().blockchain.stakes;
+        let leader = stakes.leader(stakes.seed()).unwrap().to_string();
+        let offline = ids.into_iter().find(|id| validator_address(id) == leader).unwrap();
+        network.remove_node(offline).await.unwrap();
+        let online: Vec<&str> = ids.into_iter().filter(|id| *id != offline).collect();
+        for id in &online {
+            network.handle(id).unwrap().set_mining(true).unwrap();
+        }
+
+        for height in 3..6 {
+            let transfer = Transaction::new_at(address::from_name("Alice"), address::from_name("Bob"), 1.0, height);
+            network.add_transaction_to_node(online[0], transfer).await.unwrap();
+            wait_for_height(&network, &online, height as usize).await;
+        }
+        let chain = network.node_status(online[0]).await.unwrap().blockchain.blocks;
+        assert_ne!(chain[2].signer(), Some(leader));
+        for id in &online[1..] {
+            assert_eq!(network.node_status(id).await.unwrap().blockchain.blocks, chain, "{}", id);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_authorities_sign_out_of_turn_for_an_offline_one() {
+        use crate::models::consensus::validator_address;
+
+        let ids = ["A", "B", "C"];
+        let network = Network::new().with_consensus(Consensus::ProofOfAuthority(ids.map(String::from).to_vec()));
+        for id in ids {
+            network.add_node(id.to_string(), 0).await;
+        }
+        network.connect_nodes("A", "B").await;
+        network.connect_nodes("B", "C").await;
+        network.connect_nodes("A", "C").await;
+        mine_and_broadcast(&network, "B", 1.0).await;
+
+        // B goes offline for good, yet every third block after its first is its turn.
+        network.remove_node("B").await.unwrap();
+        for id in ["A", "C"] {
+            network.handle(id).unwrap().set_mining(true).unwrap();
+        }
+        for height in 3..6 {
+            let transfer = Transaction::new_at(address::from_name("Alice"), address::from_name("Bob"), 1.0, height);
+            network.add_transaction_to_node("A", transfer).await.unwrap();
+            wait_for_height(&network, &["A", "C"], height as usize).await;
+        }
+        let chain = network.node_status("A").await.unwrap().blockchain.blocks;
+        // C is next in line after B, so it steps in well before A would.
+        assert_eq!(chain[4].signer(), Some(validator_address("C")));
+        assert_eq!(network.node_status("C").await.unwrap().blockchain.blocks, chain);
+    }
+
+    /// Waits until every node in `ids` holds at least `length` blocks, genesis included.
+    async fn wait_for_height(network: &Network, ids: &[&str], length: usize) {
+        let grown = async {
+            for id in ids {
+                while network.node_status(id).await.unwrap().blockchain.blocks.len() < length {
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                }
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(15), grown)
+            .await
+            .expect("the chain stopped growing");
+    }
+
+    async fn mine_and_broadcast(network: &Network, node_id: &str, amount: f64) -> Block {
+        network
+            .add_transaction_to_node(node_id, Transaction::new(address::from_name("Alice"), address::from_name("Bob"), amount))
+            .await
+            .unwrap();
+        let block = network.mine_block(node_id).await.unwrap();
+        network.broadcast_block(node_id, block.clone()).await;
+        network.settle().await;
+        block
+    }
+
+    #[tokio::test]
+    async fn test_remove_node_cleans_up_peer_lists() {
+        let network = Network::new();
+        for id in ["A", "B", "C"] {
+            network.add_node(id.to_string(), 1).await;
+        }
+        network.connect_nodes("A", "B").await;
+        network.connect_nodes("B", "C").await;
+        network.connect_nodes("A", "C").await;
+        network.settle().await;
+
+        network.remove_node("C").await.unwrap();
+        network.settle().await;
+
+        let statuses = network.statuses().await;
+        assert_eq!(statuses.iter().map(|s| s.id.as_str()).collect::<Vec<_>>(), vec!["A", "B"]);
+        assert_eq!(statuses[0].peers, vec!["B".to_string()]);
+        assert_eq!(statuses[1].peers, vec!["A".to_string()]);
+        assert!(matches!(network.remove_node("C").await, Err(NodeError::NodeNotFound)));
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_nodes_cuts_both_sides() {
+        let network = Network::new();
+        for id in ["A", "B", "C"] {
+            network.add_node(id.to_string(), 1).await;
+        }
+        network.connect_nodes("A", "B").await;
+        network.connect_nodes("B", "C").await;
+        network.settle().await;
+
+        network.disconnect_nodes("A", "B").await;
+        network.settle().await;
+        assert!(network.node_status("A").await.unwrap().peers.is_empty());
+        assert_eq!(network.node_status("B").await.unwrap().peers, vec!["C".to_string()]);
+
+        // A block from C no longer reaches A.
+        mine_and_broadcast(&network, "C", 1.0).await;
+        assert_eq!(network.node_status("A").await.unwrap().blockchain.blocks.len(), 1);
+        assert_eq!(network.node_status("B").await.unwrap().blockchain.blocks.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_restarted_node_resyncs_with_chain_intact() {
+        let network = Network::new();
+        for id in ["A", "B", "C"] {
+            network.add_node(id.to_string(), 1).await;
+        }
+        network.connect_nodes("A", "B").await;
+        network.connect_nodes("B", "C").await;
+        network.connect_nodes("A", "C").await;
+        let first = mine_and_broadcast(&network, "A", 1.0).await;
+
+        network.stop_node("C").await.unwrap();
+        network.settle().await;
+        assert_eq!(network.stopped_nodes(), vec!["C".to_string()]);
+        assert_eq!(network.nodes(), vec!["A".to_string(), "B".to_string()]);
+        assert!(matches!(network.mine_block("C").await, Err(NodeError::NodeNotFound)));
+        assert_eq!(network.node_status("A").await.unwrap().peers, vec!["B".to_string()]);
+
+        mine_and_broadcast(&network, "A", 2.0).await;
+        let tip = mine_and_broadcast(&network, "B", 3.0).await;
+
+        network.restart_node("C").await.unwrap();
+        network.settle().await;
+
+        let status = network.node_status("C").await.unwrap();
+        assert_eq!(status.blockchain.blocks[1], first);
+        assert_eq!(status.blockchain.blocks.last(), Some(&tip));
+        assert_eq!(status.peers, vec!["A".to_string(), "B".to_string()]);
+        assert!(network.stopped_nodes().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_topology_constructors_wire_every_edge() {
+        let network = Network::full_mesh(12, 0).await;
+        network.settle().await;
+        let topology = network.topology().await;
+        assert_eq!(topology.edges, Topology::full_mesh(12).edges);
+        assert_eq!(topology.diameter(), Some(1));
+
+        let expected = Topology::random_regular(10, 3, 7).unwrap();
+        let network = Network::random_regular(10, 3, 7, 0).await.unwrap();
+        network.settle().await;
+        assert_eq!(network.topology().await.edges, expected.edges);
+
+        let network = Network::line(4, 1).await;
+        network.settle().await;
+        assert_eq!(network.topology().await.diameter(), Some(3));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_node() {
+        let network = Network::new();
+        let result = network.mine_block("Nobody").await;
+        assert!(matches!(result, Err(NodeError::NodeNotFound)));
+    }
+}
@@ -0,0 +1,233 @@
+//! A registry of unique, transferable assets.
+//!
+//! Minting records a new asset under an id its minter picks, together with
+//! the hash of metadata kept off chain, and gives it to the transaction's
+//! receiver. From then on only the current owner can transfer it, and every
+//! change of hands is kept so the asset's provenance can be traced.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::models::{Transaction, hex};
+
+/// The longest id an asset may have.
+pub const MAX_ASSET_ID_LENGTH: usize = 64;
+
+/// What a transaction does with a unique asset. It goes to the transaction's receiver.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AssetOp {
+    Mint {
+        id: String,
+        #[serde(with = "hex")]
+        metadata_hash: [u8; 32],
+    },
+    Transfer { id: String },
+}
+
+impl AssetOp {
+    pub fn id(&self) -> &str {
+        match self {
+            AssetOp::Mint { id, .. } | AssetOp::Transfer { id } => id,
+        }
+    }
+}
+
+impl fmt::Display for AssetOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssetOp::Mint { id, metadata_hash } => {
+                write!(f, "minting asset {:?} with metadata {}", id, hex::encode(metadata_hash))
+            }
+            AssetOp::Transfer { id } => write!(f, "transferring asset {:?}", id),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AssetError {
+    /// The id is empty, too long or not printable ASCII.
+    InvalidId,
+    AlreadyMinted,
+    UnknownAsset,
+    /// Only the current owner may transfer an asset.
+    NotOwner,
+    /// The asset goes to more than one receiver.
+    MultipleReceivers,
+}
+
+impl fmt::Display for AssetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssetError::InvalidId => write!(f, "asset ids are 1 to {} printable characters", MAX_ASSET_ID_LENGTH),
+            AssetError::AlreadyMinted => write!(f, "an asset with that id already exists"),
+            AssetError::UnknownAsset => write!(f, "no such asset"),
+            AssetError::NotOwner => write!(f, "only the asset's owner may transfer it"),
+            AssetError::MultipleReceivers => write!(f, "an asset goes to a single receiver"),
+        }
+    }
+}
+
+/// One owner in an asset's history.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ownership {
+    pub owner: String,
+    /// The transaction that gave the asset to `owner`.
+    pub txid: [u8; 32],
+    /// The timestamp of the block including it.
+    pub since: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Asset {
+    pub minter: String,
+    pub metadata_hash: [u8; 32],
+    /// Every owner the asset has had, oldest first; the last is the current one.
+    pub history: Vec<Ownership>,
+}
+
+impl Asset {
+    pub fn owner(&self) -> &str {
+        &self.history.last().expect("minting records the first owner").owner
+    }
+}
+
+/// Every asset minted on a chain, by id.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Assets {
+    assets: BTreeMap<String, Asset>,
+}
+
+impl Assets {
+    pub fn get(&self, id: &str) -> Option<&Asset> {
+        self.assets.get(id)
+    }
+
+    /// Mints or hands over the asset the transaction names. `timestamp` is
+    /// that of the including block, or `None` while it is pending, in which
+    /// case the transaction's own timestamp is recorded.
+    pub fn apply(&mut self, transaction: &Transaction, timestamp: Option<u64>) -> Result<(), AssetError> {
+        self.check(transaction)?;
+        let Some(op) = &transaction.asset else {
+            return Ok(());
+        };
+        let ownership = Ownership {
+            owner: transaction.receiver.clone(),
+            txid: transaction.hash(),
+            since: timestamp.unwrap_or(transaction.timestamp),
+        };
+        match op {
+            AssetOp::Mint { id, metadata_hash } => {
+                let asset = Asset {
+                    minter: transaction.sender.clone(),
+                    metadata_hash: *metadata_hash,
+                    history: vec![ownership],
+                };
+                self.assets.insert(id.clone(), asset);
+            }
+            AssetOp::Transfer { id } => self.assets.get_mut(id).unwrap().history.push(ownership),
+        }
+        Ok(())
+    }
+
+    /// Whether `apply` would accept the transaction, without applying it.
+    pub fn check(&self, transaction: &Transaction) -> Result<(), AssetError> {
+        let Some(op) = &transaction.asset else {
+            return Ok(());
+        };
+        if !transaction.outputs.is_empty() {
+            return Err(AssetError::MultipleReceivers);
+        }
+        match op {
+            AssetOp::Mint { id, .. } => {
+                if !is_valid_id(id) {
+                    return Err(AssetError::InvalidId);
+                }
+                if self.assets.contains_key(id) {
+                    return Err(AssetError::AlreadyMinted);
+                }
+            }
+            AssetOp::Transfer { id } => {
+                let asset = self.assets.get(id).ok_or(AssetError::UnknownAsset)?;
+                if asset.owner() != transaction.sender {
+                    return Err(AssetError::NotOwner);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_valid_id(id: &str) -> bool {
+    (1..=MAX_ASSET_ID_LENGTH).contains(&id.len()) && id.bytes().all(|byte| byte.is_ascii_graphic() || byte == b' ')
+}
+
+/// A transaction carrying `op` from `sender` to `receiver`, moving no native currency.
+pub fn asset_at(sender: String, receiver: String, op: AssetOp, timestamp: u64) -> Transaction {
+    let mut transaction = Transaction::new_at(sender, receiver, 0.0, timestamp);
+    transaction.asset = Some(op);
+    transaction
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::models::blockchain::{Blockchain, BlockchainError, GENESIS_TIMESTAMP};
+    use crate::models::{Block, address};
+
+    #[test]
+    fn test_assets_change_hands_only_by_their_owner() {
+        let (alice, bob, carol) = (address::from_name("Alice"), address::from_name("Bob"), address::from_name("Carol"));
+        let id = || "pallet-0042".to_string();
+        let mut bc = Blockchain::new(0);
+        let mint = asset_at(alice.clone(), alice.clone(), AssetOp::Mint { id: id(), metadata_hash: [7; 32] }, 1);
+        bc.add_transaction(mint.clone()).unwrap();
+        let to_bob = asset_at(alice.clone(), bob.clone(), AssetOp::Transfer { id: id() }, 2);
+        bc.add_transaction(to_bob.clone()).unwrap();
+        // Alice no longer owns it once the pending transfer to Bob is counted.
+        let again = asset_at(alice.clone(), carol.clone(), AssetOp::Transfer { id: id() }, 3);
+        assert_eq!(bc.add_transaction(again), Err(BlockchainError::Asset(AssetError::NotOwner)));
+        bc.mine_block_at(GENESIS_TIMESTAMP + 100).unwrap();
+        assert_eq!(bc.asset_owner("pallet-0042"), Some(bob.as_str()));
+
+        let refused =
+            |sender: &str, op: AssetOp| bc.clone().add_transaction(asset_at(sender.to_string(), carol.clone(), op, 4));
+        let stolen = AssetOp::Transfer { id: id() };
+        assert_eq!(refused(&carol, stolen), Err(BlockchainError::Asset(AssetError::NotOwner)));
+        let unknown = AssetOp::Transfer { id: "pallet-0043".to_string() };
+        assert_eq!(refused(&bob, unknown), Err(BlockchainError::Asset(AssetError::UnknownAsset)));
+        let duplicate = AssetOp::Mint { id: id(), metadata_hash: [8; 32] };
+        assert_eq!(refused(&carol, duplicate), Err(BlockchainError::Asset(AssetError::AlreadyMinted)));
+        let unnamed = AssetOp::Mint { id: String::new(), metadata_hash: [8; 32] };
+        assert_eq!(refused(&carol, unnamed), Err(BlockchainError::Asset(AssetError::InvalidId)));
+
+        let to_carol = asset_at(bob.clone(), carol.clone(), AssetOp::Transfer { id: id() }, 5);
+        bc.add_transaction(to_carol.clone()).unwrap();
+        bc.mine_block_at(GENESIS_TIMESTAMP + 200).unwrap();
+        let asset = bc.assets.get("pallet-0042").unwrap();
+        assert_eq!((asset.minter.as_str(), asset.metadata_hash), (alice.as_str(), [7; 32]));
+        let history: Vec<(&str, [u8; 32], u64)> =
+            asset.history.iter().map(|entry| (entry.owner.as_str(), entry.txid, entry.since)).collect();
+        let expected = vec![
+            (alice.as_str(), mint.hash(), GENESIS_TIMESTAMP + 100),
+            (bob.as_str(), to_bob.hash(), GENESIS_TIMESTAMP + 100),
+            (carol.as_str(), to_carol.hash(), GENESIS_TIMESTAMP + 200),
+        ];
+        assert_eq!(history, expected);
+        assert!(bc.validate_chain().is_ok());
+
+        // A block in which Bob hands over the asset again is invalid.
+        let mut blocks = (*bc.blocks).clone();
+        let stale = asset_at(bob.clone(), bob, AssetOp::Transfer { id: id() }, 6);
+        let mut block = Block::new_at(3, blocks[2].hash, vec![stale], GENESIS_TIMESTAMP + 300);
+        block.hash = block.hash();
+        blocks.push(block);
+        let mut forged = Blockchain::new(0);
+        forged.blocks = Arc::new(blocks);
+        assert_eq!(forged.validate_chain(), Err(BlockchainError::Asset(AssetError::NotOwner)));
+    }
+}
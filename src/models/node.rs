@@ -1,56 +1,539 @@
+use std::fmt;
 use std::sync::Arc;
 
-use crate::models::{Block, Blockchain};
+use crate::models::blockchain::{BlockchainError, LedgerModel, MAX_DRIFT};
+use crate::models::consensus::ConsensusEngine;
+use crate::models::message::{Envelope, Message};
+use crate::models::peer::{Direction, PeerConfig, PeerError, PeerManager};
+use crate::models::seen_cache::SeenCache;
+use crate::models::{Block, Blockchain, Transaction};
+
+/// How many transaction hashes a node remembers to stop rebroadcast loops.
+const SEEN_TRANSACTIONS_CAPACITY: usize = 10_000;
+
+/// How many block hashes a node remembers to suppress duplicate relays.
+const SEEN_BLOCKS_CAPACITY: usize = 1_000;
+
+/// Misbehavior points for relaying a block with a bad hash or proof of work,
+/// or a chain that does not validate.
+const INVALID_BLOCK_PENALTY: u32 = 100;
+
+/// Counters for the transaction gossip a node has taken part in.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct GossipMetrics {
+    pub announcements_sent: u64,
+    pub announcements_received: u64,
+    pub requests_sent: u64,
+    pub transactions_sent: u64,
+    pub transactions_received: u64,
+    pub duplicates: u64,
+    pub accepted: u64,
+    pub rejected: u64,
+}
+
+impl std::ops::AddAssign for GossipMetrics {
+    fn add_assign(&mut self, other: Self) {
+        self.announcements_sent += other.announcements_sent;
+        self.announcements_received += other.announcements_received;
+        self.requests_sent += other.requests_sent;
+        self.transactions_sent += other.transactions_sent;
+        self.transactions_received += other.transactions_received;
+        self.duplicates += other.duplicates;
+        self.accepted += other.accepted;
+        self.rejected += other.rejected;
+    }
+}
+
+/// Counters for the blocks a node has received and passed on.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RelayMetrics {
+    pub blocks_received: u64,
+    /// Blocks from peers that extended the chain.
+    pub blocks_accepted: u64,
+    pub blocks_relayed: u64,
+    pub duplicates: u64,
+    pub chains_requested: u64,
+    /// Longer chains from peers that replaced ours.
+    pub chains_adopted: u64,
+    /// Peers banned for relaying invalid blocks or chains.
+    pub peers_banned: u64,
+}
+
+impl std::ops::AddAssign for RelayMetrics {
+    fn add_assign(&mut self, other: Self) {
+        self.blocks_received += other.blocks_received;
+        self.blocks_accepted += other.blocks_accepted;
+        self.blocks_relayed += other.blocks_relayed;
+        self.duplicates += other.duplicates;
+        self.chains_requested += other.chains_requested;
+        self.chains_adopted += other.chains_adopted;
+        self.peers_banned += other.peers_banned;
+    }
+}
 
 pub struct Node {
     pub id: String,
     pub blockchain: Blockchain,
-    pub peers: Vec<String>,
+    pub peers: PeerManager,
+    pub gossip: GossipMetrics,
+    pub relay: RelayMetrics,
+    seen_transactions: SeenCache<[u8; 32]>,
+    seen_blocks: SeenCache<[u8; 32]>,
 }
 
 impl Node {
     pub fn new(id: String, difficulty: usize) -> Self {
         Node {
+            peers: PeerManager::new(id.clone(), PeerConfig::default()),
             id,
             blockchain: Blockchain::new(difficulty),
-            peers: Vec::new(),
+            gossip: GossipMetrics::default(),
+            relay: RelayMetrics::default(),
+            seen_transactions: SeenCache::new(SEEN_TRANSACTIONS_CAPACITY),
+            seen_blocks: SeenCache::new(SEEN_BLOCKS_CAPACITY),
         }
     }
 
-    pub fn add_peer(&mut self, peer_id: String) {
-        if !self.peers.contains(&peer_id) && self.id != peer_id {
-            self.peers.push(peer_id);
+    /// Replaces the default connection limits and ban policy. Existing
+    /// connections and known addresses are forgotten.
+    pub fn with_peer_config(mut self, config: PeerConfig) -> Self {
+        self.peers = PeerManager::new(self.id.clone(), config);
+        self
+    }
+
+    /// Starts over on an empty chain kept in the given ledger model.
+    pub fn with_ledger(mut self, ledger: LedgerModel) -> Self {
+        let consensus = Arc::clone(&self.blockchain.consensus);
+        self.blockchain = Blockchain::with_ledger(self.blockchain.difficulty, ledger).with_consensus(consensus);
+        self
+    }
+
+    /// Runs `consensus` in place of proof of work; see `Blockchain::with_consensus`.
+    pub fn with_consensus(mut self, consensus: Arc<dyn ConsensusEngine>) -> Self {
+        self.blockchain = self.blockchain.with_consensus(consensus);
+        self
+    }
+
+    pub fn add_peer(&mut self, peer_id: String, direction: Direction, now: u64) -> Result<(), PeerError> {
+        self.peers.connect(&peer_id, direction, now)
+    }
+
+    /// Drops a connection and tells the peer so it drops its side too.
+    pub fn disconnect_peer(&mut self, peer_id: &str) -> Vec<Envelope> {
+        if self.peers.disconnect(peer_id) {
+            vec![self.envelope(peer_id, Message::Disconnect)]
+        } else {
+            Vec::new()
         }
     }
 
-    pub fn receive_block(&mut self, block: Block) -> Result<(), NodeError> {
-        let latest = self.blockchain.blocks.last().unwrap();
+    /// Forgets a peer that has left the network, so it is not redialled.
+    pub fn forget_peer(&mut self, peer_id: &str) {
+        self.peers.forget(peer_id);
+    }
+
+    /// Closes every connection before the node shuts down. The chain, mempool
+    /// and known addresses are kept so the node can rejoin with `restart`.
+    pub fn stop(&mut self) -> Vec<Envelope> {
+        self.peers
+            .disconnect_all()
+            .iter()
+            .map(|peer| self.envelope(peer, Message::Disconnect))
+            .collect()
+    }
 
-        if block.previous_hash == latest.hash && block.index == latest.index + 1 {
-            if block.hash[..self.blockchain.difficulty].iter().all(|&b| b == 0) {
-                let mut new_blocks = (*self.blockchain.blocks).clone();
-                new_blocks.push(block);
-                self.blockchain.blocks = Arc::new(new_blocks);
-                return Ok(());
+    /// Redials every known address after a stop. Peers that are ahead send
+    /// their chain once the handshake completes.
+    pub fn restart(&mut self, now: u64) -> Vec<Envelope> {
+        self.peers.reset_attempts();
+        self.tick(now)
+    }
+
+    /// Announces our chain height to a newly connected peer.
+    pub fn hello(&self, peer_id: &str) -> Envelope {
+        self.envelope(peer_id, Message::Hello { height: self.height() })
+    }
+
+    fn height(&self) -> u64 {
+        self.blockchain.blocks.last().unwrap().index
+    }
+
+    /// Asks a peer for its chain if it reports being ahead of us.
+    fn sync_with(&mut self, peer_id: &str, height: u64) -> Vec<Envelope> {
+        if height > self.height() {
+            self.relay.chains_requested += 1;
+            vec![self.envelope(peer_id, Message::GetChain)]
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Periodic housekeeping: dials known addresses to fill free outbound
+    /// slots and asks peers for more addresses while slots remain free.
+    pub fn tick(&mut self, now: u64) -> Vec<Envelope> {
+        let mut outgoing = Vec::new();
+        for candidate in self.peers.candidates(now) {
+            if self.peers.connect(&candidate, Direction::Outbound, now).is_ok() {
+                outgoing.push(self.hello(&candidate));
+            }
+        }
+        if self.peers.count(Direction::Outbound) < self.peers.config().max_outbound {
+            for peer in self.peers.ids() {
+                outgoing.push(self.envelope(&peer, Message::GetAddresses));
             }
         }
+        outgoing
+    }
 
+    /// Appends a peer's block that extends our tip. `now` is our clock, which a
+    /// block dated too far past is held to before it counts against its sender.
+    pub fn receive_block(&mut self, block: Block, now: u64) -> Result<(), NodeError> {
+        if block.hash != block.hash() {
+            return Err(NodeError::InvalidBlockHash);
+        }
 
-        Err(NodeError::InvalidBlockHash)
+        let latest = self.blockchain.blocks.last().unwrap();
+        if block.index <= latest.index {
+            return Err(NodeError::StaleBlock);
+        }
+        if block.previous_hash != latest.hash || block.index != latest.index + 1 {
+            return Err(NodeError::UnknownParent);
+        }
+        if block.timestamp > now.saturating_add(MAX_DRIFT) {
+            return Err(NodeError::FutureBlock);
+        }
+
+        self.blockchain.append_block(block).map_err(NodeError::InvalidBlock)?;
+        self.blockchain.prune_pending();
+        Ok(())
     }
 
-    pub fn receive_chain(&mut self, chain: &Vec<Block>) -> bool {
-        self.blockchain.replace_chain(Arc::new(chain.clone()))
+    /// Adopts `chain` if it is valid and heavier than ours. `Ok(false)` means
+    /// it is no heavier; an invalid chain is an error, whatever its length.
+    pub fn receive_chain(&mut self, chain: Arc<Vec<Block>>) -> Result<bool, NodeError> {
+        let replaced = self.blockchain.try_replace_chain(chain).map_err(NodeError::InvalidChain)?;
+        if replaced {
+            self.blockchain.prune_pending();
+        }
+        Ok(replaced)
     }
 
-    pub fn mine_block(&mut self) -> Result<Block, super::blockchain::BlockchainError> {
+    pub fn mine_block(&mut self) -> Result<Block, BlockchainError> {
         self.blockchain.mine_block()
     }
+
+    pub fn mine_block_at(&mut self, timestamp: u64) -> Result<Block, BlockchainError> {
+        self.blockchain.mine_block_at(timestamp)
+    }
+
+    /// Adds a locally submitted transaction to the mempool and announces it to every peer.
+    pub fn submit_transaction(&mut self, transaction: Transaction) -> Result<Vec<Envelope>, NodeError> {
+        let hash = transaction.hash();
+        self.blockchain
+            .add_transaction(transaction)
+            .map_err(NodeError::InvalidTransaction)?;
+        self.seen_transactions.insert(hash);
+        Ok(self.announce(hash, None))
+    }
+
+    /// Handles a message from a peer and returns any messages to send in response.
+    ///
+    /// `now` is the current unix time in seconds. Apart from connection
+    /// requests, messages from nodes that are not connected peers are ignored.
+    pub fn handle_message(&mut self, now: u64, from: &str, message: Message) -> Vec<Envelope> {
+        if let Message::Hello { height } = message {
+            return match self.peers.connect(from, Direction::Inbound, now) {
+                Ok(()) | Err(PeerError::AlreadyConnected) => {
+                    let mut outgoing = vec![self.envelope(from, Message::Welcome { height: self.height() })];
+                    outgoing.extend(self.sync_with(from, height));
+                    outgoing
+                }
+                Err(_) => vec![self.envelope(from, Message::Disconnect)],
+            };
+        }
+        if !self.peers.contains(from) {
+            return Vec::new();
+        }
+
+        match message {
+            Message::Hello { .. } => Vec::new(),
+            Message::Welcome { height } => self.sync_with(from, height),
+            Message::Disconnect => {
+                self.peers.disconnect(from);
+                Vec::new()
+            }
+            Message::GetAddresses => {
+                vec![self.envelope(from, Message::Addresses(self.peers.addresses()))]
+            }
+            Message::Addresses(addresses) => {
+                self.peers.learn(addresses);
+                Vec::new()
+            }
+            Message::Block(block) => {
+                self.relay.blocks_received += 1;
+                if self.seen_blocks.contains(&block.hash) {
+                    self.relay.duplicates += 1;
+                    return Vec::new();
+                }
+                // Only blocks we accept or ask the chain for are remembered, so a
+                // tampered copy carrying a genuine block's hash cannot shadow it.
+                match self.receive_block(block.clone(), now) {
+                    Ok(()) => {
+                        self.relay.blocks_accepted += 1;
+                        self.relay_block(block, Some(from))
+                    }
+                    Err(NodeError::UnknownParent) => {
+                        // The sender is ahead of us on a chain we do not have.
+                        self.seen_blocks.insert(block.hash);
+                        self.relay.chains_requested += 1;
+                        vec![self.envelope(from, Message::GetChain)]
+                    }
+                    Err(NodeError::InvalidBlockHash | NodeError::InvalidBlock(_)) => self.penalize(from, now),
+                    Err(_) => Vec::new(),
+                }
+            }
+            Message::Chain(chain) => {
+                match self.receive_chain(chain) {
+                    Ok(true) => {}
+                    Ok(false) => return Vec::new(),
+                    Err(_) => return self.penalize(from, now),
+                }
+                self.relay.chains_adopted += 1;
+                // Let our other peers know about the new tip; they ask for the chain if they need it.
+                let tip = self.blockchain.blocks.last().unwrap().clone();
+                self.relay_block(tip, Some(from))
+            }
+            Message::GetChain => {
+                vec![self.envelope(from, Message::Chain(self.blockchain.blocks.clone()))]
+            }
+            Message::Inventory(hashes) => {
+                self.gossip.announcements_received += 1;
+                let wanted: Vec<[u8; 32]> = hashes
+                    .into_iter()
+                    .filter(|hash| !self.seen_transactions.contains(hash))
+                    .collect();
+                if wanted.is_empty() {
+                    return Vec::new();
+                }
+                self.gossip.requests_sent += 1;
+                vec![self.envelope(from, Message::GetTransactions(wanted))]
+            }
+            Message::GetTransactions(hashes) => {
+                let found: Vec<Transaction> = self
+                    .blockchain
+                    .pending_transactions
+                    .iter()
+                    .filter(|transaction| hashes.contains(&transaction.hash()))
+                    .cloned()
+                    .collect();
+                self.gossip.transactions_sent += found.len() as u64;
+                found
+                    .into_iter()
+                    .map(|transaction| self.envelope(from, Message::Transaction(Box::new(transaction))))
+                    .collect()
+            }
+            Message::Transaction(transaction) => {
+                self.gossip.transactions_received += 1;
+                let hash = transaction.hash();
+                if self.seen_transactions.contains(&hash) {
+                    self.gossip.duplicates += 1;
+                    return Vec::new();
+                }
+                // Refused transactions are not remembered: one that arrives before
+                // the block funding it is requested again when next announced.
+                if self.blockchain.add_transaction(*transaction).is_err() {
+                    self.gossip.rejected += 1;
+                    return Vec::new();
+                }
+                self.seen_transactions.insert(hash);
+                self.gossip.accepted += 1;
+                self.announce(hash, Some(from))
+            }
+        }
+    }
+
+    /// Sends a block to every peer except the one it came from. Blocks this
+    /// node produced itself are passed with no origin.
+    pub fn relay_block(&mut self, block: Block, from: Option<&str>) -> Vec<Envelope> {
+        self.seen_blocks.insert(block.hash);
+        let envelopes: Vec<Envelope> = self
+            .peers
+            .ids()
+            .iter()
+            .filter(|peer| Some(peer.as_str()) != from)
+            .map(|peer| self.envelope(peer, Message::Block(block.clone())))
+            .collect();
+        self.relay.blocks_relayed += envelopes.len() as u64;
+        envelopes
+    }
+
+    /// Addresses a copy of `message` to every peer.
+    pub fn broadcast(&self, message: Message) -> Vec<Envelope> {
+        self.peers
+            .ids()
+            .iter()
+            .map(|peer| self.envelope(peer, message.clone()))
+            .collect()
+    }
+
+    fn announce(&mut self, hash: [u8; 32], except: Option<&str>) -> Vec<Envelope> {
+        let announcements: Vec<Envelope> = self
+            .peers
+            .ids()
+            .iter()
+            .filter(|peer| Some(peer.as_str()) != except)
+            .map(|peer| self.envelope(peer, Message::Inventory(vec![hash])))
+            .collect();
+        self.gossip.announcements_sent += announcements.len() as u64;
+        announcements
+    }
+
+    /// Penalizes a peer that relayed an invalid block or chain, disconnecting it once banned.
+    fn penalize(&mut self, peer_id: &str, now: u64) -> Vec<Envelope> {
+        if self.peers.penalize(peer_id, INVALID_BLOCK_PENALTY, now) {
+            self.relay.peers_banned += 1;
+            vec![self.envelope(peer_id, Message::Disconnect)]
+        } else {
+            Vec::new()
+        }
+    }
+
+    fn envelope(&self, to: &str, message: Message) -> Envelope {
+        Envelope {
+            from: self.id.clone(),
+            to: to.to_string(),
+            message,
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum NodeError {
     InvalidBlockHash,
+    /// The block extends the tip but includes a transaction the ledger refuses.
+    InvalidBlock(BlockchainError),
+    /// The block is no higher than our tip.
+    StaleBlock,
+    /// The block is ahead of our tip but does not build on it.
+    UnknownParent,
+    /// The block is dated more than `MAX_DRIFT` past our clock. It may be
+    /// valid later, so the sender is not penalized.
+    FutureBlock,
+    /// The offered chain does not validate.
+    InvalidChain(BlockchainError),
     NodeNotFound,
-    InvalidTransaction,
-}
\ No newline at end of file
+    /// The mempool refuses the transaction, for the reason given.
+    InvalidTransaction(BlockchainError),
+    Mining(BlockchainError),
+}
+
+impl fmt::Display for NodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NodeError::InvalidBlockHash => write!(f, "invalid block hash"),
+            NodeError::InvalidBlock(e) => write!(f, "invalid block: {}", e),
+            NodeError::StaleBlock => write!(f, "block is not ahead of the chain tip"),
+            NodeError::UnknownParent => write!(f, "block does not extend the chain tip"),
+            NodeError::FutureBlock => write!(f, "block is dated too far in the future"),
+            NodeError::InvalidChain(e) => write!(f, "invalid chain: {}", e),
+            NodeError::NodeNotFound => write!(f, "node not found"),
+            NodeError::InvalidTransaction(e) => write!(f, "invalid transaction: {}", e),
+            NodeError::Mining(e) => write!(f, "mining failed: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::address;
+    use crate::models::blockchain::GENESIS_TIMESTAMP;
+
+    fn connected(id: &str, peers: &[&str]) -> Node {
+        let mut node = Node::new(id.to_string(), 0);
+        for peer in peers {
+            node.add_peer(peer.to_string(), Direction::Outbound, 0).unwrap();
+        }
+        node
+    }
+
+    fn transfer(sender: &str, receiver: &str, amount: f64) -> Transaction {
+        Transaction::new_at(address::from_name(sender), address::from_name(receiver), amount, 1)
+    }
+
+    #[test]
+    fn test_tampered_block_does_not_shadow_the_genuine_one() {
+        let now = GENESIS_TIMESTAMP + 100;
+        let mut miner = Blockchain::new(0);
+        miner.add_transaction(transfer("Alice", "Bob", 1.0)).unwrap();
+        let genuine = miner.mine_block_at(now).unwrap();
+        let mut tampered = genuine.clone();
+        tampered.transactions[0].amount = 90.0;
+
+        let mut node = connected("A", &["B", "C", "D"]);
+        assert!(node.handle_message(now, "B", Message::Block(tampered)).iter().all(|e| e.to == "B"));
+        let relayed = node.handle_message(now, "C", Message::Block(genuine.clone()));
+        assert_eq!(relayed.len(), 1);
+        assert_eq!((relayed[0].to.as_str(), node.relay.blocks_accepted), ("D", 1));
+        assert_eq!(node.blockchain.blocks.last(), Some(&genuine));
+        assert!(node.handle_message(now, "D", Message::Block(genuine)).is_empty());
+        assert_eq!(node.relay.duplicates, 1);
+    }
+
+    #[test]
+    fn test_refused_transaction_is_requested_again() {
+        let now = GENESIS_TIMESTAMP + 100;
+        let mut funding = Blockchain::new(0);
+        funding.add_transaction(transfer("Alice", "Bob", 100.0)).unwrap();
+        let block = funding.mine_block_at(now).unwrap();
+        let early = transfer("Bob", "Carol", 150.0);
+        let hash = early.hash();
+
+        let mut node = connected("A", &["B"]);
+        let requested = |requests: &[Envelope]| {
+            matches!(requests, [Envelope { message: Message::GetTransactions(hashes), .. }] if *hashes == vec![hash])
+        };
+        assert!(requested(&node.handle_message(now, "B", Message::Inventory(vec![hash]))));
+        node.handle_message(now, "B", Message::Transaction(Box::new(early.clone())));
+        assert_eq!((node.gossip.rejected, node.gossip.duplicates), (1, 0));
+
+        node.handle_message(now, "B", Message::Block(block));
+        assert!(requested(&node.handle_message(now, "B", Message::Inventory(vec![hash]))));
+        node.handle_message(now, "B", Message::Transaction(Box::new(early.clone())));
+        assert_eq!(node.blockchain.pending_transactions, vec![early]);
+        assert_eq!(node.gossip.accepted, 1);
+    }
+
+    #[test]
+    fn test_blocks_dated_too_far_ahead_wait_without_penalty() {
+        let now = GENESIS_TIMESTAMP + 100;
+        let mut miner = Blockchain::new(0);
+        miner.add_transaction(transfer("Alice", "Bob", 1.0)).unwrap();
+        let block = miner.mine_block_at(now + MAX_DRIFT + 1).unwrap();
+
+        let mut node = connected("A", &["B"]);
+        assert!(matches!(node.receive_block(block.clone(), now), Err(NodeError::FutureBlock)));
+        assert!(node.handle_message(now, "B", Message::Block(block.clone())).is_empty());
+        assert_eq!((node.relay.peers_banned, node.peers.ids()), (0, vec!["B".to_string()]));
+        node.handle_message(now + 1, "B", Message::Block(block.clone()));
+        assert_eq!(node.blockchain.blocks.last(), Some(&block));
+    }
+
+    #[test]
+    fn test_peer_sending_a_forged_chain_is_banned() {
+        let now = GENESIS_TIMESTAMP + 100;
+        let mut miner = Blockchain::new(0);
+        miner.add_transaction(transfer("Alice", "Bob", 1.0)).unwrap();
+        miner.mine_block_at(now).unwrap();
+        let mut forged = (*miner.blocks).clone();
+        forged[1].transactions[0].amount = 90.0;
+
+        let mut node = connected("A", &["B", "C"]);
+        assert!(node.handle_message(now, "C", Message::Chain(Arc::new(vec![forged[0].clone()]))).is_empty());
+        let replies = node.handle_message(now, "B", Message::Chain(Arc::new(forged)));
+        assert!(matches!(replies.as_slice(), [Envelope { to, message: Message::Disconnect, .. }] if to == "B"));
+        assert_eq!(node.relay.peers_banned, 1);
+        assert_eq!(node.peers.ids(), vec!["C".to_string()]);
+        assert_eq!(node.blockchain.blocks.len(), 1);
+    }
+}
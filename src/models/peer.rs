@@ -0,0 +1,271 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+/// Which side opened a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PeerConfig {
+    pub max_inbound: usize,
+    pub max_outbound: usize,
+    /// Misbehavior score at which a peer is banned.
+    pub ban_threshold: u32,
+    /// How long a ban lasts, in seconds.
+    pub ban_duration: u64,
+    /// Minimum time between two connection attempts to the same address, in seconds.
+    pub retry_interval: u64,
+    /// Most addresses shared in a single address list.
+    pub max_addresses: usize,
+}
+
+impl Default for PeerConfig {
+    fn default() -> Self {
+        PeerConfig {
+            max_inbound: 16,
+            max_outbound: 8,
+            ban_threshold: 100,
+            ban_duration: 24 * 60 * 60,
+            retry_interval: 30,
+            max_addresses: 100,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerInfo {
+    pub direction: Direction,
+    pub score: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PeerError {
+    SelfConnection,
+    AlreadyConnected,
+    Banned,
+    NoFreeSlots,
+}
+
+impl fmt::Display for PeerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PeerError::SelfConnection => write!(f, "a node cannot connect to itself"),
+            PeerError::AlreadyConnected => write!(f, "already connected"),
+            PeerError::Banned => write!(f, "peer is banned"),
+            PeerError::NoFreeSlots => write!(f, "no free connection slots"),
+        }
+    }
+}
+
+/// A node's connections, the addresses it has heard of, and the peers it has banned.
+///
+/// All times are unix timestamps in seconds, passed in by the caller so that
+/// simulations can run the manager on a virtual clock.
+#[derive(Debug, Clone)]
+pub struct PeerManager {
+    own_id: String,
+    config: PeerConfig,
+    connected: BTreeMap<String, PeerInfo>,
+    known: BTreeSet<String>,
+    banned: BTreeMap<String, u64>,
+    last_attempt: BTreeMap<String, u64>,
+}
+
+impl PeerManager {
+    pub fn new(own_id: String, config: PeerConfig) -> Self {
+        PeerManager {
+            own_id,
+            config,
+            connected: BTreeMap::new(),
+            known: BTreeSet::new(),
+            banned: BTreeMap::new(),
+            last_attempt: BTreeMap::new(),
+        }
+    }
+
+    pub fn config(&self) -> PeerConfig {
+        self.config
+    }
+
+    /// Connected peer ids, in sorted order.
+    pub fn ids(&self) -> Vec<String> {
+        self.connected.keys().cloned().collect()
+    }
+
+    pub fn contains(&self, peer_id: &str) -> bool {
+        self.connected.contains_key(peer_id)
+    }
+
+    pub fn info(&self, peer_id: &str) -> Option<&PeerInfo> {
+        self.connected.get(peer_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.connected.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.connected.is_empty()
+    }
+
+    pub fn count(&self, direction: Direction) -> usize {
+        self.connected.values().filter(|info| info.direction == direction).count()
+    }
+
+    pub fn is_banned(&self, peer_id: &str, now: u64) -> bool {
+        self.banned.get(peer_id).is_some_and(|&until| now < until)
+    }
+
+    pub fn banned(&self) -> Vec<String> {
+        self.banned.keys().cloned().collect()
+    }
+
+    pub fn connect(&mut self, peer_id: &str, direction: Direction, now: u64) -> Result<(), PeerError> {
+        if peer_id == self.own_id {
+            return Err(PeerError::SelfConnection);
+        }
+        if self.connected.contains_key(peer_id) {
+            return Err(PeerError::AlreadyConnected);
+        }
+        if self.is_banned(peer_id, now) {
+            return Err(PeerError::Banned);
+        }
+        let limit = match direction {
+            Direction::Inbound => self.config.max_inbound,
+            Direction::Outbound => self.config.max_outbound,
+        };
+        if self.count(direction) >= limit {
+            return Err(PeerError::NoFreeSlots);
+        }
+
+        self.banned.remove(peer_id);
+        self.known.insert(peer_id.to_string());
+        self.connected.insert(peer_id.to_string(), PeerInfo { direction, score: 0 });
+        Ok(())
+    }
+
+    /// Drops the connection but remembers the address for reconnecting later.
+    pub fn disconnect(&mut self, peer_id: &str) -> bool {
+        self.connected.remove(peer_id).is_some()
+    }
+
+    /// Drops every connection, returning the ids that were connected.
+    pub fn disconnect_all(&mut self) -> Vec<String> {
+        let ids = self.ids();
+        self.connected.clear();
+        ids
+    }
+
+    /// Clears connection attempt history so every known address can be dialled at once.
+    pub fn reset_attempts(&mut self) {
+        self.last_attempt.clear();
+    }
+
+    /// Forgets a peer entirely, including its address.
+    pub fn forget(&mut self, peer_id: &str) {
+        self.connected.remove(peer_id);
+        self.known.remove(peer_id);
+        self.last_attempt.remove(peer_id);
+    }
+
+    /// Records addresses heard from a peer.
+    pub fn learn(&mut self, addresses: impl IntoIterator<Item = String>) {
+        for address in addresses {
+            if address != self.own_id {
+                self.known.insert(address);
+            }
+        }
+    }
+
+    /// Addresses worth sharing with a peer that asks for them.
+    pub fn addresses(&self) -> Vec<String> {
+        self.known.iter().take(self.config.max_addresses).cloned().collect()
+    }
+
+    /// Adds `points` to a peer's misbehavior score. Once the score reaches the
+    /// ban threshold the peer is disconnected and banned; returns whether it was.
+    pub fn penalize(&mut self, peer_id: &str, points: u32, now: u64) -> bool {
+        let Some(info) = self.connected.get_mut(peer_id) else {
+            return false;
+        };
+        info.score = info.score.saturating_add(points);
+        if info.score < self.config.ban_threshold {
+            return false;
+        }
+        self.connected.remove(peer_id);
+        self.banned.insert(peer_id.to_string(), now + self.config.ban_duration);
+        true
+    }
+
+    /// Known addresses to dial to fill free outbound slots, skipping banned
+    /// peers and ones tried within the retry interval. Marks them as attempted.
+    pub fn candidates(&mut self, now: u64) -> Vec<String> {
+        self.banned.retain(|_, until| now < *until);
+        let free = self.config.max_outbound.saturating_sub(self.count(Direction::Outbound));
+        let candidates: Vec<String> = self
+            .known
+            .iter()
+            .filter(|address| !self.connected.contains_key(*address) && !self.banned.contains_key(*address))
+            .filter(|address| {
+                self.last_attempt
+                    .get(*address)
+                    .is_none_or(|&last| now >= last + self.config.retry_interval)
+            })
+            .take(free)
+            .cloned()
+            .collect();
+        for address in &candidates {
+            self.last_attempt.insert(address.clone(), now);
+        }
+        candidates
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manager(config: PeerConfig) -> PeerManager {
+        PeerManager::new("Self".to_string(), config)
+    }
+
+    #[test]
+    fn test_connect_rejects_self_duplicates_and_full_slots() {
+        let mut peers = manager(PeerConfig { max_inbound: 1, ..PeerConfig::default() });
+        assert_eq!(peers.connect("Self", Direction::Outbound, 0), Err(PeerError::SelfConnection));
+        assert_eq!(peers.connect("A", Direction::Inbound, 0), Ok(()));
+        assert_eq!(peers.connect("A", Direction::Outbound, 0), Err(PeerError::AlreadyConnected));
+        assert_eq!(peers.connect("B", Direction::Inbound, 0), Err(PeerError::NoFreeSlots));
+        assert_eq!(peers.connect("B", Direction::Outbound, 0), Ok(()));
+        assert_eq!(peers.ids(), vec!["A".to_string(), "B".to_string()]);
+    }
+
+    #[test]
+    fn test_ban_expires() {
+        let mut peers = manager(PeerConfig { ban_duration: 60, ..PeerConfig::default() });
+        peers.connect("A", Direction::Outbound, 0).unwrap();
+        assert!(!peers.penalize("A", 40, 10));
+        assert!(peers.penalize("A", 60, 10));
+        assert!(!peers.contains("A"));
+        assert_eq!(peers.connect("A", Direction::Inbound, 69), Err(PeerError::Banned));
+        assert!(peers.candidates(69).is_empty());
+        assert_eq!(peers.candidates(70), vec!["A".to_string()]);
+        assert_eq!(peers.connect("A", Direction::Inbound, 70), Ok(()));
+        assert_eq!(peers.info("A").unwrap().score, 0);
+    }
+
+    #[test]
+    fn test_candidates_respect_slots_and_retry_interval() {
+        let mut peers = manager(PeerConfig { max_outbound: 2, retry_interval: 30, ..PeerConfig::default() });
+        peers.connect("A", Direction::Outbound, 0).unwrap();
+        peers.learn(["B", "C", "Self"].map(String::from));
+        assert_eq!(peers.candidates(0), vec!["B".to_string()]);
+        assert_eq!(peers.candidates(10), vec!["C".to_string()]);
+        assert!(peers.candidates(20).is_empty());
+        assert_eq!(peers.candidates(30), vec!["B".to_string()]);
+        assert!(!peers.addresses().contains(&"Self".to_string()));
+    }
+}
@@ -0,0 +1,467 @@
+//! A small stack-based language for spending conditions, after Bitcoin Script.
+//!
+//! An account can be controlled by a locking script instead of a key: its
+//! address commits to the script's hash, and a transaction spending from it
+//! reveals the locking script along with an unlocking script that supplies
+//! the data it asks for, such as signatures or a secret. The unlocking script
+//! runs first, then the locking script on the same stack, and the spend is
+//! authorised if the value left on top is true.
+//!
+//! Scripts have no loops, and their length, stack depth, element size and the
+//! number of steps they take are all bounded, so evaluation always ends quickly.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::models::{Signature, Transaction, address, hex};
+
+/// The most operations a single script may contain.
+pub const MAX_SCRIPT_LENGTH: usize = 201;
+/// The most steps both scripts together may take; each key a multisig check
+/// considers counts as a step.
+pub const MAX_STEPS: usize = 300;
+/// The most items the stack may hold.
+pub const MAX_STACK_SIZE: usize = 100;
+/// The largest item that may be pushed, in bytes.
+pub const MAX_ELEMENT_SIZE: usize = 520;
+/// The most keys a multisig check may consider.
+pub const MAX_MULTISIG_KEYS: usize = 15;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Op {
+    /// Pushes bytes.
+    Push(#[serde(with = "hex::bytes")] Vec<u8>),
+    /// Pushes a number, as eight little-endian bytes.
+    Number(u64),
+    Dup,
+    Drop,
+    Swap,
+    /// Pops two items and pushes whether they are equal.
+    Equal,
+    /// Pops the top item and fails unless it is true.
+    Verify,
+    EqualVerify,
+    /// Replaces the top item with its SHA-256 hash.
+    Sha256,
+    /// Pops a public key and a signature below it, and pushes whether the
+    /// signature signs the transaction's signing input.
+    CheckSig,
+    CheckSigVerify,
+    /// Pops a key count n, n public keys, a threshold m and m signatures, and
+    /// pushes whether m different keys among them signed the transaction.
+    CheckMultisig,
+    /// Pops a timestamp and fails unless the transaction is locked until at least then.
+    CheckLockTime,
+    /// Pops a height and fails unless the transaction is locked until at least that height.
+    CheckLockHeight,
+    /// Pops the top item and runs the branch up to `Else` or `EndIf` if it is true.
+    If,
+    Else,
+    EndIf,
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Op::Push(bytes) => write!(f, "<{}>", hex::encode(bytes)),
+            Op::Number(number) => write!(f, "{}", number),
+            Op::Dup => write!(f, "DUP"),
+            Op::Drop => write!(f, "DROP"),
+            Op::Swap => write!(f, "SWAP"),
+            Op::Equal => write!(f, "EQUAL"),
+            Op::Verify => write!(f, "VERIFY"),
+            Op::EqualVerify => write!(f, "EQUALVERIFY"),
+            Op::Sha256 => write!(f, "SHA256"),
+            Op::CheckSig => write!(f, "CHECKSIG"),
+            Op::CheckSigVerify => write!(f, "CHECKSIGVERIFY"),
+            Op::CheckMultisig => write!(f, "CHECKMULTISIG"),
+            Op::CheckLockTime => write!(f, "CHECKLOCKTIME"),
+            Op::CheckLockHeight => write!(f, "CHECKLOCKHEIGHT"),
+            Op::If => write!(f, "IF"),
+            Op::Else => write!(f, "ELSE"),
+            Op::EndIf => write!(f, "ENDIF"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Script(pub Vec<Op>);
+
+impl Script {
+    /// The address of the account this script locks.
+    pub fn address(&self) -> String {
+        let hash = Sha256::digest(self.to_string().as_bytes());
+        address::encode(address::SCRIPT_HASH_VERSION, hash[..20].try_into().unwrap())
+    }
+
+    fn is_push_only(&self) -> bool {
+        self.0.iter().all(|op| matches!(op, Op::Push(_) | Op::Number(_)))
+    }
+}
+
+impl fmt::Display for Script {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ops: Vec<String> = self.0.iter().map(Op::to_string).collect();
+        write!(f, "{}", ops.join(" "))
+    }
+}
+
+/// What a transaction from a script-hash address carries to satisfy it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScriptSpend {
+    pub locking: Script,
+    pub unlocking: Script,
+}
+
+impl fmt::Display for ScriptSpend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unlocking [{}] with [{}]", self.locking, self.unlocking)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScriptError {
+    /// The transaction reveals no scripts.
+    MissingScript,
+    /// The locking script does not hash to the sender's address.
+    WrongScript,
+    /// The unlocking script does more than push data.
+    NotPushOnly,
+    ScriptTooLong,
+    TooManySteps,
+    StackOverflow,
+    StackUnderflow,
+    ElementTooLarge,
+    InvalidNumber,
+    TooManyKeys,
+    VerifyFailed,
+    LockNotReached,
+    UnbalancedConditional,
+    /// Both scripts ran, but left a false value or nothing on the stack.
+    EvaluatedFalse,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScriptError::MissingScript => write!(f, "spends from a script address need its scripts"),
+            ScriptError::WrongScript => write!(f, "locking script does not match the sender's address"),
+            ScriptError::NotPushOnly => write!(f, "unlocking script may only push data"),
+            ScriptError::ScriptTooLong => write!(f, "script has more than {} operations", MAX_SCRIPT_LENGTH),
+            ScriptError::TooManySteps => write!(f, "scripts took more than {} steps", MAX_STEPS),
+            ScriptError::StackOverflow => write!(f, "stack holds more than {} items", MAX_STACK_SIZE),
+            ScriptError::StackUnderflow => write!(f, "operation needs more items than the stack holds"),
+            ScriptError::ElementTooLarge => write!(f, "item is larger than {} bytes", MAX_ELEMENT_SIZE),
+            ScriptError::InvalidNumber => write!(f, "item is not a number"),
+            ScriptError::TooManyKeys => write!(f, "multisig check has more than {} keys", MAX_MULTISIG_KEYS),
+            ScriptError::VerifyFailed => write!(f, "verification failed"),
+            ScriptError::LockNotReached => write!(f, "transaction is not locked long enough"),
+            ScriptError::UnbalancedConditional => write!(f, "IF, ELSE and ENDIF do not match"),
+            ScriptError::EvaluatedFalse => write!(f, "script evaluated to false"),
+        }
+    }
+}
+
+/// Whether a transaction from a script-hash address reveals the locking
+/// script behind it and an unlocking script that satisfies it.
+pub fn verify(transaction: &Transaction) -> Result<(), ScriptError> {
+    let spend = transaction.script.as_ref().ok_or(ScriptError::MissingScript)?;
+    if spend.locking.address() != transaction.sender {
+        return Err(ScriptError::WrongScript);
+    }
+    // Otherwise the unlocking script could rewrite the locking script's logic.
+    if !spend.unlocking.is_push_only() {
+        return Err(ScriptError::NotPushOnly);
+    }
+    let mut machine = Machine {
+        transaction,
+        stack: Vec::new(),
+        steps: 0,
+    };
+    machine.run(&spend.unlocking)?;
+    machine.run(&spend.locking)?;
+    match machine.stack.last() {
+        Some(top) if is_true(top) => Ok(()),
+        _ => Err(ScriptError::EvaluatedFalse),
+    }
+}
+
+struct Machine<'a> {
+    transaction: &'a Transaction,
+    stack: Vec<Vec<u8>>,
+    steps: usize,
+}
+
+impl Machine<'_> {
+    fn run(&mut self, script: &Script) -> Result<(), ScriptError> {
+        if script.0.len() > MAX_SCRIPT_LENGTH {
+            return Err(ScriptError::ScriptTooLong);
+        }
+        // Whether each enclosing branch is taken.
+        let mut branches: Vec<bool> = Vec::new();
+        for op in &script.0 {
+            self.step(1)?;
+            let executing = branches.iter().all(|&taken| taken);
+            match op {
+                Op::If => {
+                    let taken = executing && is_true(&self.pop()?);
+                    branches.push(taken);
+                }
+                Op::Else => {
+                    let taken = branches.pop().ok_or(ScriptError::UnbalancedConditional)?;
+                    let outer = branches.iter().all(|&taken| taken);
+                    branches.push(outer && !taken);
+                }
+                Op::EndIf => {
+                    branches.pop().ok_or(ScriptError::UnbalancedConditional)?;
+                }
+                _ if !executing => {}
+                op => self.execute(op)?,
+            }
+        }
+        if !branches.is_empty() {
+            return Err(ScriptError::UnbalancedConditional);
+        }
+        Ok(())
+    }
+
+    fn execute(&mut self, op: &Op) -> Result<(), ScriptError> {
+        match op {
+            Op::Push(bytes) => self.push(bytes.clone())?,
+            Op::Number(number) => self.push(number.to_le_bytes().to_vec())?,
+            Op::Dup => {
+                let top = self.stack.last().ok_or(ScriptError::StackUnderflow)?.clone();
+                self.push(top)?;
+            }
+            Op::Drop => {
+                self.pop()?;
+            }
+            Op::Swap => {
+                let (top, below) = (self.pop()?, self.pop()?);
+                self.push(top)?;
+                self.push(below)?;
+            }
+            Op::Equal => {
+                let equal = self.pop()? == self.pop()?;
+                self.push_bool(equal)?;
+            }
+            Op::Verify => self.verify()?,
+            Op::EqualVerify => {
+                self.execute(&Op::Equal)?;
+                self.verify()?;
+            }
+            Op::Sha256 => {
+                let item = self.pop()?;
+                self.push(Sha256::digest(&item).to_vec())?;
+            }
+            Op::CheckSig => {
+                let public_key = self.pop()?;
+                let signature = self.pop()?;
+                let valid = self.signed_by(&public_key, signature);
+                self.push_bool(valid)?;
+            }
+            Op::CheckSigVerify => {
+                self.execute(&Op::CheckSig)?;
+                self.verify()?;
+            }
+            Op::CheckMultisig => {
+                let key_count = self.pop_number()? as usize;
+                if key_count > MAX_MULTISIG_KEYS {
+                    return Err(ScriptError::TooManyKeys);
+                }
+                self.step(key_count)?;
+                let keys = (0..key_count).map(|_| self.pop()).collect::<Result<Vec<_>, _>>()?;
+                let threshold = self.pop_number()? as usize;
+                if threshold > key_count {
+                    return Err(ScriptError::InvalidNumber);
+                }
+                let signatures = (0..threshold).map(|_| self.pop()).collect::<Result<Vec<_>, _>>()?;
+                let mut unused = keys;
+                let mut valid = 0;
+                for signature in signatures {
+                    if let Some(i) = unused.iter().position(|key| self.signed_by(key, signature.clone())) {
+                        unused.swap_remove(i);
+                        valid += 1;
+                    }
+                }
+                self.push_bool(valid == threshold)?;
+            }
+            Op::CheckLockTime => {
+                let time = self.pop_number()?;
+                if self.transaction.lock_time.is_none_or(|lock| lock < time) {
+                    return Err(ScriptError::LockNotReached);
+                }
+            }
+            Op::CheckLockHeight => {
+                let height = self.pop_number()?;
+                if self.transaction.lock_height.is_none_or(|lock| lock < height) {
+                    return Err(ScriptError::LockNotReached);
+                }
+            }
+            Op::If | Op::Else | Op::EndIf => unreachable!("conditionals are handled by run"),
+        }
+        Ok(())
+    }
+
+    fn step(&mut self, cost: usize) -> Result<(), ScriptError> {
+        self.steps += cost;
+        if self.steps > MAX_STEPS {
+            return Err(ScriptError::TooManySteps);
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, item: Vec<u8>) -> Result<(), ScriptError> {
+        if item.len() > MAX_ELEMENT_SIZE {
+            return Err(ScriptError::ElementTooLarge);
+        }
+        if self.stack.len() >= MAX_STACK_SIZE {
+            return Err(ScriptError::StackOverflow);
+        }
+        self.stack.push(item);
+        Ok(())
+    }
+
+    fn push_bool(&mut self, value: bool) -> Result<(), ScriptError> {
+        self.push(if value { vec![1] } else { vec![] })
+    }
+
+    fn pop(&mut self) -> Result<Vec<u8>, ScriptError> {
+        self.stack.pop().ok_or(ScriptError::StackUnderflow)
+    }
+
+    fn pop_number(&mut self) -> Result<u64, ScriptError> {
+        let item = self.pop()?;
+        if item.len() > 8 {
+            return Err(ScriptError::InvalidNumber);
+        }
+        let mut bytes = [0u8; 8];
+        bytes[..item.len()].copy_from_slice(&item);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn verify(&mut self) -> Result<(), ScriptError> {
+        if is_true(&self.pop()?) {
+            Ok(())
+        } else {
+            Err(ScriptError::VerifyFailed)
+        }
+    }
+
+    fn signed_by(&self, public_key: &[u8], signature: Vec<u8>) -> bool {
+        let Ok(public_key) = public_key.try_into() else {
+            return false;
+        };
+        Signature { public_key, signature }.verifies(self.transaction.signing_input().as_bytes())
+    }
+}
+
+fn is_true(item: &[u8]) -> bool {
+    item.iter().any(|&byte| byte != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::SigningKey;
+
+    fn spend(locking: Script, unlocking: Vec<Op>, lock_time: Option<u64>) -> Transaction {
+        let mut transaction = Transaction::new_at(locking.address(), address::from_name("Bob"), 5.0, 1);
+        transaction.lock_time = lock_time;
+        transaction.script = Some(ScriptSpend {
+            locking,
+            unlocking: Script(unlocking),
+        });
+        transaction
+    }
+
+    fn sign(key: &SigningKey, transaction: &Transaction) -> Op {
+        Op::Push(Signature::new(key, transaction.signing_input().as_bytes()).signature)
+    }
+
+    fn public_key(key: &SigningKey) -> Op {
+        Op::Push(key.verifying_key().to_bytes().to_vec())
+    }
+
+    #[test]
+    fn test_signature_and_multisig_checks() {
+        let keys: Vec<SigningKey> = (1..=3).map(|seed| SigningKey::from_bytes(&[seed; 32])).collect();
+        let single = Script(vec![public_key(&keys[0]), Op::CheckSig]);
+        let mut transaction = spend(single.clone(), vec![], None);
+        transaction.script.as_mut().unwrap().unlocking = Script(vec![sign(&keys[0], &transaction)]);
+        assert_eq!(verify(&transaction), Ok(()));
+        transaction.script.as_mut().unwrap().unlocking = Script(vec![sign(&keys[1], &transaction)]);
+        assert_eq!(verify(&transaction), Err(ScriptError::EvaluatedFalse));
+        transaction.script.as_mut().unwrap().unlocking = Script(vec![Op::Number(1), Op::Verify]);
+        assert_eq!(verify(&transaction), Err(ScriptError::NotPushOnly));
+
+        let mut two_of_three = vec![Op::Number(2)];
+        two_of_three.extend(keys.iter().map(public_key));
+        two_of_three.extend([Op::Number(3), Op::CheckMultisig]);
+        let mut transaction = spend(Script(two_of_three), vec![], None);
+        let approvals = vec![sign(&keys[2], &transaction), sign(&keys[0], &transaction)];
+        transaction.script.as_mut().unwrap().unlocking = Script(approvals);
+        assert_eq!(verify(&transaction), Ok(()));
+        let twice = vec![sign(&keys[2], &transaction), sign(&keys[2], &transaction)];
+        transaction.script.as_mut().unwrap().unlocking = Script(twice);
+        assert_eq!(verify(&transaction), Err(ScriptError::EvaluatedFalse));
+
+        let mut wrong = spend(single, vec![], None);
+        wrong.sender = address::from_name("Alice");
+        assert_eq!(verify(&wrong), Err(ScriptError::WrongScript));
+    }
+
+    #[test]
+    fn test_hash_lock_or_time_lock() {
+        // Bob's secret unlocks the coins at once; after time 1000 anyone can take them back.
+        let secret = b"open sesame".to_vec();
+        let hash = Sha256::digest(&secret).to_vec();
+        let locking = Script(vec![
+            Op::If,
+            Op::Sha256,
+            Op::Push(hash),
+            Op::Equal,
+            Op::Else,
+            Op::Number(1_000),
+            Op::CheckLockTime,
+            Op::Number(1),
+            Op::EndIf,
+        ]);
+        let claim = |preimage: &[u8]| spend(locking.clone(), vec![Op::Push(preimage.to_vec()), Op::Number(1)], None);
+        assert_eq!(verify(&claim(&secret)), Ok(()));
+        assert_eq!(verify(&claim(b"guess")), Err(ScriptError::EvaluatedFalse));
+
+        assert_eq!(verify(&spend(locking.clone(), vec![Op::Number(0)], Some(999))), Err(ScriptError::LockNotReached));
+        assert_eq!(verify(&spend(locking.clone(), vec![Op::Number(0)], None)), Err(ScriptError::LockNotReached));
+        assert_eq!(verify(&spend(locking, vec![Op::Number(0)], Some(1_000))), Ok(()));
+    }
+
+    #[test]
+    fn test_limits() {
+        let too_long = Script(vec![Op::Number(1); MAX_SCRIPT_LENGTH + 1]);
+        assert_eq!(verify(&spend(too_long, vec![], None)), Err(ScriptError::ScriptTooLong));
+        let deep = Script(vec![Op::Dup; MAX_STACK_SIZE]);
+        assert_eq!(verify(&spend(deep, vec![Op::Number(1)], None)), Err(ScriptError::StackOverflow));
+        let large = vec![Op::Push(vec![1; MAX_ELEMENT_SIZE + 1])];
+        assert_eq!(verify(&spend(Script(vec![]), large, None)), Err(ScriptError::ElementTooLarge));
+        let unbalanced = Script(vec![Op::Number(1), Op::If, Op::Number(1)]);
+        assert_eq!(verify(&spend(unbalanced, vec![], None)), Err(ScriptError::UnbalancedConditional));
+        assert_eq!(verify(&spend(Script(vec![Op::Drop]), vec![], None)), Err(ScriptError::StackUnderflow));
+
+        // Both scripts' operations count towards the step limit.
+        let unlocking = vec![Op::Number(1); MAX_STACK_SIZE];
+        let busy = |pairs: usize| {
+            let mut ops = vec![Op::Drop; MAX_STACK_SIZE - 1];
+            for _ in 0..pairs {
+                ops.extend([Op::Dup, Op::Drop]);
+            }
+            Script(ops)
+        };
+        assert_eq!(verify(&spend(busy(50), unlocking.clone(), None)), Ok(()));
+        assert_eq!(verify(&spend(busy(51), unlocking, None)), Err(ScriptError::TooManySteps));
+    }
+}
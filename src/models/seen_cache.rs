@@ -0,0 +1,67 @@
+use std::collections::{HashSet, VecDeque};
+use std::hash::Hash;
+
+/// A set that remembers at most `capacity` items, forgetting the oldest first.
+///
+/// Used to recognise gossip a node has already handled without letting the
+/// set grow for as long as the node runs.
+#[derive(Debug, Clone)]
+pub struct SeenCache<T> {
+    capacity: usize,
+    order: VecDeque<T>,
+    items: HashSet<T>,
+}
+
+impl<T: Clone + Eq + Hash> SeenCache<T> {
+    pub fn new(capacity: usize) -> Self {
+        SeenCache {
+            capacity,
+            order: VecDeque::with_capacity(capacity),
+            items: HashSet::with_capacity(capacity),
+        }
+    }
+
+    /// Records `item`, returning `false` if it was already present.
+    pub fn insert(&mut self, item: T) -> bool {
+        if self.items.contains(&item) {
+            return false;
+        }
+        if self.order.len() == self.capacity
+            && let Some(oldest) = self.order.pop_front()
+        {
+            self.items.remove(&oldest);
+        }
+        self.order.push_back(item.clone());
+        self.items.insert(item);
+        true
+    }
+
+    pub fn contains(&self, item: &T) -> bool {
+        self.items.contains(item)
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_and_evict() {
+        let mut cache = SeenCache::new(2);
+        assert!(cache.insert(1));
+        assert!(!cache.insert(1));
+        assert!(cache.insert(2));
+        assert!(cache.insert(3));
+        assert_eq!(cache.len(), 2);
+        assert!(!cache.contains(&1));
+        assert!(cache.contains(&2) && cache.contains(&3));
+    }
+}
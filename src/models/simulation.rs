@@ -0,0 +1,699 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::models::blockchain::GENESIS_TIMESTAMP;
+use crate::models::message::{Envelope, Message};
+use crate::models::node::{GossipMetrics, RelayMetrics};
+use crate::models::peer::{Direction, PeerConfig};
+use crate::models::simulator::LinkModel;
+use crate::models::{Node, Transaction};
+
+/// Something that happens to the simulated network at a point in virtual time.
+#[derive(Debug, Clone)]
+pub enum Event {
+    Deliver(Envelope),
+    /// A node tries to mine its pending transactions and broadcast the block.
+    Mine(String),
+    SubmitTransaction(String, Box<Transaction>),
+    /// A node runs its periodic peer housekeeping.
+    Tick(String),
+    /// Submits a random transfer between the simulation's accounts.
+    GenerateTransaction,
+}
+
+#[derive(Debug)]
+struct Scheduled {
+    at: Duration,
+    sequence: u64,
+    event: Event,
+}
+
+impl PartialEq for Scheduled {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Scheduled {}
+
+impl PartialOrd for Scheduled {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scheduled {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.at, self.sequence).cmp(&(other.at, other.sequence))
+    }
+}
+
+/// A discrete-event simulation of many nodes on a virtual clock.
+///
+/// Nodes are driven directly, one event at a time, in order of virtual time
+/// and then scheduling order. Every random choice comes from one seeded RNG
+/// and every timestamp from the virtual clock, so a seed always reproduces the
+/// same chains bit-for-bit.
+pub struct Simulation {
+    now: Duration,
+    sequence: u64,
+    queue: BinaryHeap<Reverse<Scheduled>>,
+    nodes: BTreeMap<String, Node>,
+    stopped: BTreeMap<String, Node>,
+    links: LinkModel,
+    rng: StdRng,
+    difficulty: usize,
+    mining_interval: Option<Duration>,
+    transaction_interval: Option<Duration>,
+    tick_interval: Option<Duration>,
+    peer_config: PeerConfig,
+    accounts: Vec<String>,
+    events_processed: u64,
+    propagation: HashMap<[u8; 32], Propagation>,
+}
+
+/// How far a submitted transaction has spread through the mempools.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Propagation {
+    pub submitted_at: Duration,
+    /// Number of nodes that have accepted the transaction, including the one it was submitted to.
+    pub reached: usize,
+    pub last_reached_at: Duration,
+}
+
+impl Simulation {
+    pub fn new(seed: u64, difficulty: usize) -> Self {
+        Simulation {
+            now: Duration::ZERO,
+            sequence: 0,
+            queue: BinaryHeap::new(),
+            nodes: BTreeMap::new(),
+            stopped: BTreeMap::new(),
+            links: LinkModel::new(seed),
+            rng: StdRng::seed_from_u64(seed),
+            difficulty,
+            mining_interval: None,
+            transaction_interval: None,
+            tick_interval: None,
+            peer_config: PeerConfig::default(),
+            accounts: Vec::new(),
+            events_processed: 0,
+            propagation: HashMap::new(),
+        }
+    }
+
+    /// Replaces the instant, lossless default links.
+    pub fn with_link_model(mut self, links: LinkModel) -> Self {
+        self.links = links;
+        self
+    }
+
+    /// Sets the connection limits and ban policy of nodes added from now on.
+    pub fn with_peer_config(mut self, peer_config: PeerConfig) -> Self {
+        self.peer_config = peer_config;
+        self
+    }
+
+    pub fn add_node(&mut self, node_id: &str) {
+        if !self.nodes.contains_key(node_id) {
+            let node = Node::new(node_id.to_string(), self.difficulty).with_peer_config(self.peer_config);
+            self.nodes.insert(node_id.to_string(), node);
+            if let Some(interval) = self.mining_interval {
+                let delay = self.sample_interval(interval);
+                self.schedule(delay, Event::Mine(node_id.to_string()));
+            }
+            if let Some(interval) = self.tick_interval {
+                self.schedule(interval, Event::Tick(node_id.to_string()));
+            }
+        }
+    }
+
+    /// Links two nodes immediately, as if the connection was already open.
+    /// If either side refuses, neither keeps the connection.
+    pub fn connect(&mut self, node_id1: &str, node_id2: &str) {
+        if node_id1 == node_id2 || !self.nodes.contains_key(node_id1) || !self.nodes.contains_key(node_id2) {
+            return;
+        }
+        let now = self.timestamp();
+        let outbound = self.nodes.get_mut(node_id1).unwrap().add_peer(node_id2.to_string(), Direction::Outbound, now);
+        let inbound = self.nodes.get_mut(node_id2).unwrap().add_peer(node_id1.to_string(), Direction::Inbound, now);
+        if outbound.is_err() || inbound.is_err() {
+            if outbound.is_ok() {
+                self.nodes.get_mut(node_id1).unwrap().peers.disconnect(node_id2);
+            }
+            if inbound.is_ok() {
+                self.nodes.get_mut(node_id2).unwrap().peers.disconnect(node_id1);
+            }
+        }
+    }
+
+    /// Closes the connection between two nodes on both sides.
+    pub fn disconnect(&mut self, node_id1: &str, node_id2: &str) {
+        if let Some(node) = self.nodes.get_mut(node_id1) {
+            node.peers.disconnect(node_id2);
+        }
+        if let Some(node) = self.nodes.get_mut(node_id2) {
+            node.peers.disconnect(node_id1);
+        }
+    }
+
+    /// Takes a node offline with its state intact. Messages sent to it are
+    /// lost until it is restarted.
+    pub fn stop_node(&mut self, node_id: &str) -> bool {
+        let Some(mut node) = self.nodes.remove(node_id) else { return false };
+        let farewells = node.stop();
+        self.stopped.insert(node_id.to_string(), node);
+        self.send(farewells);
+        true
+    }
+
+    pub fn restart_node(&mut self, node_id: &str) -> bool {
+        let Some(mut node) = self.stopped.remove(node_id) else { return false };
+        let greetings = node.restart(self.timestamp());
+        self.nodes.insert(node_id.to_string(), node);
+        self.send(greetings);
+        true
+    }
+
+    /// Removes a node for good; every other node forgets its address.
+    pub fn remove_node(&mut self, node_id: &str) -> bool {
+        if !self.stop_node(node_id) {
+            return false;
+        }
+        self.stopped.remove(node_id);
+        for node in self.nodes.values_mut().chain(self.stopped.values_mut()) {
+            node.forget_peer(node_id);
+        }
+        true
+    }
+
+    /// Makes every node run its peer housekeeping once per `interval`.
+    pub fn tick_every(&mut self, interval: Duration) {
+        self.tick_interval = Some(interval);
+        let node_ids: Vec<String> = self.nodes.keys().cloned().collect();
+        for node_id in node_ids {
+            self.schedule(interval, Event::Tick(node_id));
+        }
+    }
+
+    /// Makes every node attempt to mine on average once per `interval`.
+    pub fn mine_every(&mut self, interval: Duration) {
+        self.mining_interval = Some(interval);
+        let node_ids: Vec<String> = self.nodes.keys().cloned().collect();
+        for node_id in node_ids {
+            let delay = self.sample_interval(interval);
+            self.schedule(delay, Event::Mine(node_id));
+        }
+    }
+
+    /// Submits a random transfer between `accounts` on average once per `interval`.
+    pub fn transact_every(&mut self, interval: Duration, accounts: Vec<String>) {
+        self.transaction_interval = Some(interval);
+        self.accounts = accounts;
+        let delay = self.sample_interval(interval);
+        self.schedule(delay, Event::GenerateTransaction);
+    }
+
+    /// Queues `event` to happen `delay` after the current virtual time.
+    pub fn schedule(&mut self, delay: Duration, event: Event) {
+        self.sequence += 1;
+        self.queue.push(Reverse(Scheduled {
+            at: self.now + delay,
+            sequence: self.sequence,
+            event,
+        }));
+    }
+
+    pub fn now(&self) -> Duration {
+        self.now
+    }
+
+    /// The block timestamp corresponding to the current virtual time.
+    pub fn timestamp(&self) -> u64 {
+        GENESIS_TIMESTAMP + self.now.as_secs()
+    }
+
+    pub fn events_processed(&self) -> u64 {
+        self.events_processed
+    }
+
+    pub fn propagation(&self, hash: &[u8; 32]) -> Option<Propagation> {
+        self.propagation.get(hash).copied()
+    }
+
+    /// Block relay counters summed over every node.
+    pub fn relay_metrics(&self) -> RelayMetrics {
+        let mut total = RelayMetrics::default();
+        for node in self.nodes.values() {
+            total += node.relay;
+        }
+        total
+    }
+
+    /// Transaction gossip counters summed over every node.
+    pub fn gossip_metrics(&self) -> GossipMetrics {
+        let mut total = GossipMetrics::default();
+        for node in self.nodes.values() {
+            total += node.gossip;
+        }
+        total
+    }
+
+    pub fn node(&self, node_id: &str) -> Option<&Node> {
+        self.nodes.get(node_id)
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = &Node> {
+        self.nodes.values()
+    }
+
+    pub fn links(&self) -> &LinkModel {
+        &self.links
+    }
+
+    /// Processes events in order until none are due at or before `until`,
+    /// then moves the clock to `until`.
+    pub fn run_until(&mut self, until: Duration) {
+        while let Some(Reverse(next)) = self.queue.peek() {
+            if next.at > until {
+                break;
+            }
+            let Reverse(scheduled) = self.queue.pop().unwrap();
+            self.now = scheduled.at;
+            self.process(scheduled.event);
+        }
+        self.now = until;
+    }
+
+    /// Processes events until the queue is empty. Never returns while periodic
+    /// mining or transaction generation is enabled.
+    pub fn run(&mut self) {
+        while let Some(Reverse(scheduled)) = self.queue.pop() {
+            self.now = scheduled.at;
+            self.process(scheduled.event);
+        }
+    }
+
+    fn process(&mut self, event: Event) {
+        self.events_processed += 1;
+        match event {
+            Event::Deliver(envelope) => {
+                let timestamp = self.timestamp();
+                let gossiped = match &envelope.message {
+                    Message::Transaction(transaction) => Some(transaction.hash()),
+                    _ => None,
+                };
+                let Some(node) = self.nodes.get_mut(&envelope.to) else { return };
+                let accepted_before = node.gossip.accepted;
+                let outgoing = node.handle_message(timestamp, &envelope.from, envelope.message);
+                if let Some(hash) = gossiped
+                    && node.gossip.accepted > accepted_before
+                    && let Some(propagation) = self.propagation.get_mut(&hash)
+                {
+                    propagation.reached += 1;
+                    propagation.last_reached_at = self.now;
+                }
+                self.send(outgoing);
+            }
+            Event::Mine(node_id) => {
+                let timestamp = self.timestamp();
+                let outgoing = match self.nodes.get_mut(&node_id) {
+                    Some(node) => match node.mine_block_at(timestamp) {
+                        Ok(block) => node.relay_block(block, None),
+                        Err(_) => Vec::new(),
+                    },
+                    None => Vec::new(),
+                };
+                self.send(outgoing);
+                if let Some(interval) = self.mining_interval {
+                    let delay = self.sample_interval(interval);
+                    self.schedule(delay, Event::Mine(node_id));
+                }
+            }
+            Event::SubmitTransaction(node_id, transaction) => {
+                let hash = transaction.hash();
+                let Some(node) = self.nodes.get_mut(&node_id) else { return };
+                let Ok(announcements) = node.submit_transaction(*transaction) else { return };
+                self.propagation.insert(
+                    hash,
+                    Propagation {
+                        submitted_at: self.now,
+                        reached: 1,
+                        last_reached_at: self.now,
+                    },
+                );
+                self.send(announcements);
+            }
+            Event::Tick(node_id) => {
+                let timestamp = self.timestamp();
+                let Some(node) = self.nodes.get_mut(&node_id) else { return };
+                let outgoing = node.tick(timestamp);
+                self.send(outgoing);
+                if let Some(interval) = self.tick_interval {
+                    self.schedule(interval, Event::Tick(node_id));
+                }
+            }
+            Event::GenerateTransaction => {
+                if let Some((node_id, transaction)) = self.random_transaction() {
+                    self.process(Event::SubmitTransaction(node_id, Box::new(transaction)));
+                }
+                if let Some(interval) = self.transaction_interval {
+                    let delay = self.sample_interval(interval);
+                    self.schedule(delay, Event::GenerateTransaction);
+                }
+            }
+        }
+    }
+
+    fn send(&mut self, envelopes: Vec<Envelope>) {
+        for envelope in envelopes {
+            if let Some(delay) = self.links.route(self.now, &envelope) {
+                self.schedule(delay, Event::Deliver(envelope));
+            }
+        }
+    }
+
+    fn random_transaction(&mut self) -> Option<(String, Transaction)> {
+        if self.accounts.len() < 2 || self.nodes.is_empty() {
+            return None;
+        }
+        let sender = self.rng.random_range(0..self.accounts.len());
+        let mut receiver = self.rng.random_range(0..self.accounts.len() - 1);
+        if receiver >= sender {
+            receiver += 1;
+        }
+        let amount = self.rng.random_range(1..=10) as f64;
+        let node_index = self.rng.random_range(0..self.nodes.len());
+        let node_id = self.nodes.keys().nth(node_index).unwrap().clone();
+        let transaction = Transaction::new_at(
+            self.accounts[sender].clone(),
+            self.accounts[receiver].clone(),
+            amount,
+            self.timestamp(),
+        );
+        Some((node_id, transaction))
+    }
+
+    /// Draws an exponentially distributed delay with the given mean, so events
+    /// form a Poisson process.
+    fn sample_interval(&mut self, mean: Duration) -> Duration {
+        let uniform: f64 = self.rng.random_range(f64::EPSILON..1.0);
+        mean.mul_f64(-uniform.ln())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::address;
+    use crate::models::Block;
+    use crate::models::simulator::LinkConfig;
+
+    fn run(seed: u64) -> Vec<Vec<Block>> {
+        let links = LinkModel::new(seed).with_default_link(LinkConfig {
+            latency: Duration::from_millis(200),
+            jitter: Duration::from_millis(100),
+            drop_rate: 0.05,
+            reorder_rate: 0.1,
+        });
+        let mut simulation = Simulation::new(seed, 1).with_link_model(links);
+        let ids = ["A", "B", "C", "D"];
+        for id in ids {
+            simulation.add_node(id);
+        }
+        for (i, a) in ids.iter().enumerate() {
+            for b in &ids[i + 1..] {
+                simulation.connect(a, b);
+            }
+        }
+        simulation.mine_every(Duration::from_secs(30));
+        simulation.transact_every(
+            Duration::from_secs(5),
+            vec![address::from_name("Alice"), address::from_name("Bob"), address::from_name("Charlie")],
+        );
+        simulation.run_until(Duration::from_secs(600));
+
+        simulation.nodes().map(|node| (*node.blockchain.blocks).clone()).collect()
+    }
+
+    #[test]
+    fn test_same_seed_reproduces_chains() {
+        let first = run(42);
+        assert_eq!(first, run(42));
+        assert!(first.iter().any(|chain| chain.len() > 1));
+    }
+
+    #[test]
+    fn test_different_seeds_diverge() {
+        assert_ne!(run(1), run(2));
+    }
+
+    #[test]
+    fn test_transaction_reaches_every_reachable_mempool() {
+        let links = LinkModel::new(5).with_default_link(LinkConfig {
+            latency: Duration::from_millis(50),
+            jitter: Duration::from_millis(50),
+            ..LinkConfig::default()
+        });
+        let mut simulation = Simulation::new(5, 1).with_link_model(links);
+        let ids = ["A", "B", "C", "D", "E", "Isolated"];
+        for id in ids {
+            simulation.add_node(id);
+        }
+        // A ring of five with a chord; the last node has no peers.
+        for pair in [("A", "B"), ("B", "C"), ("C", "D"), ("D", "E"), ("E", "A"), ("A", "C")] {
+            simulation.connect(pair.0, pair.1);
+        }
+        let transaction = Transaction::new_at(address::from_name("Alice"), address::from_name("Bob"), 5.0, 0);
+        simulation.schedule(Duration::ZERO, Event::SubmitTransaction("A".to_string(), Box::new(transaction.clone())));
+        simulation.run();
+
+        for id in &ids[..5] {
+            let pending = &simulation.node(id).unwrap().blockchain.pending_transactions;
+            assert_eq!(pending, &vec![transaction.clone()], "{}", id);
+        }
+        assert!(simulation.node("Isolated").unwrap().blockchain.pending_transactions.is_empty());
+
+        let propagation = simulation.propagation(&transaction.hash()).unwrap();
+        assert_eq!(propagation.reached, 5);
+        assert!(propagation.last_reached_at > Duration::ZERO);
+        let metrics = simulation.gossip_metrics();
+        assert_eq!(metrics.accepted, 4);
+        assert_eq!(metrics.duplicates, metrics.transactions_received - metrics.accepted);
+    }
+
+    fn relay_network(seed: u64, size: usize, edges: &[(usize, usize)]) -> Simulation {
+        let links = LinkModel::new(seed).with_default_link(LinkConfig {
+            latency: Duration::from_millis(100),
+            jitter: Duration::from_millis(50),
+            ..LinkConfig::default()
+        });
+        let mut simulation = Simulation::new(seed, 1).with_link_model(links);
+        for i in 0..size {
+            simulation.add_node(&format!("N{}", i));
+        }
+        for &(a, b) in edges {
+            simulation.connect(&format!("N{}", a), &format!("N{}", b));
+        }
+        simulation
+    }
+
+    fn mine_at(simulation: &mut Simulation, node: usize, delay: Duration, amount: f64) {
+        let node_id = format!("N{}", node);
+        let transaction = Transaction::new_at(address::from_name("Alice"), address::from_name(&node_id), amount, 0);
+        simulation.schedule(delay, Event::SubmitTransaction(node_id.clone(), Box::new(transaction)));
+        simulation.schedule(delay, Event::Mine(node_id));
+    }
+
+    fn assert_single_tip(simulation: &Simulation, length: usize) {
+        let tip = simulation.node("N0").unwrap().blockchain.blocks.last().unwrap().hash;
+        for node in simulation.nodes() {
+            assert_eq!(node.blockchain.blocks.len(), length, "{}", node.id);
+            assert_eq!(node.blockchain.blocks.last().unwrap().hash, tip, "{}", node.id);
+        }
+    }
+
+    #[test]
+    fn test_block_relays_along_a_line() {
+        let edges: Vec<(usize, usize)> = (0..7).map(|i| (i, i + 1)).collect();
+        let mut simulation = relay_network(1, 8, &edges);
+        mine_at(&mut simulation, 0, Duration::ZERO, 1.0);
+        simulation.run();
+
+        assert_single_tip(&simulation, 2);
+        // Each of the seven edges carries the block exactly once.
+        let relay = simulation.relay_metrics();
+        assert_eq!(relay.blocks_relayed, 7);
+        assert_eq!(relay.duplicates, 0);
+    }
+
+    #[test]
+    fn test_block_relays_around_a_ring_without_loops() {
+        let edges: Vec<(usize, usize)> = (0..8).map(|i| (i, (i + 1) % 8)).collect();
+        let mut simulation = relay_network(2, 8, &edges);
+        mine_at(&mut simulation, 3, Duration::ZERO, 1.0);
+        simulation.run();
+
+        assert_single_tip(&simulation, 2);
+        // Every node forwards the block once, so the two waves meet and stop.
+        let relay = simulation.relay_metrics();
+        assert_eq!(relay.blocks_relayed, 2 + 7);
+        assert_eq!(relay.blocks_received, relay.blocks_relayed);
+        assert_eq!(relay.blocks_accepted, 7);
+        assert!(relay.duplicates >= 1);
+    }
+
+    #[test]
+    fn test_block_relays_across_a_random_topology() {
+        let mut rng = StdRng::seed_from_u64(99);
+        let size = 20;
+        // A random spanning tree keeps the graph connected; extra edges add cycles.
+        let mut edges: Vec<(usize, usize)> = (1..size).map(|i| (i, rng.random_range(0..i))).collect();
+        for _ in 0..15 {
+            let (a, b) = (rng.random_range(0..size), rng.random_range(0..size));
+            if a != b {
+                edges.push((a, b));
+            }
+        }
+        let mut simulation = relay_network(3, size, &edges);
+        mine_at(&mut simulation, 7, Duration::ZERO, 1.0);
+        simulation.run();
+
+        assert_single_tip(&simulation, 2);
+        let relay = simulation.relay_metrics();
+        assert!(relay.blocks_relayed <= 2 * edges.len() as u64);
+        assert_eq!(relay.blocks_received, relay.blocks_relayed);
+    }
+
+    #[test]
+    fn test_competing_blocks_converge_on_longest_chain() {
+        let edges: Vec<(usize, usize)> = (0..5).map(|i| (i, i + 1)).collect();
+        let mut simulation = relay_network(4, 6, &edges);
+        // Both ends mine at once, forking the line in the middle.
+        mine_at(&mut simulation, 0, Duration::ZERO, 1.0);
+        mine_at(&mut simulation, 5, Duration::ZERO, 2.0);
+        // Later the far end extends its branch, and everyone follows it.
+        mine_at(&mut simulation, 5, Duration::from_secs(10), 3.0);
+        simulation.run();
+
+        assert_single_tip(&simulation, 3);
+        let relay = simulation.relay_metrics();
+        assert!(relay.chains_requested > 0);
+        assert_eq!(relay.chains_adopted, relay.chains_requested);
+    }
+
+    #[test]
+    fn test_peers_discover_addresses_within_limits() {
+        let peer_config = PeerConfig {
+            max_outbound: 2,
+            max_inbound: 3,
+            ..PeerConfig::default()
+        };
+        let edges: Vec<(usize, usize)> = (0..5).map(|i| (i, i + 1)).collect();
+        let mut simulation = Simulation::new(6, 1).with_peer_config(peer_config);
+        for i in 0..6 {
+            simulation.add_node(&format!("N{}", i));
+        }
+        for &(a, b) in &edges {
+            simulation.connect(&format!("N{}", a), &format!("N{}", b));
+        }
+        simulation.tick_every(Duration::from_secs(5));
+        simulation.run_until(Duration::from_secs(300));
+
+        for node in simulation.nodes() {
+            assert!(node.peers.count(Direction::Outbound) <= 2, "{}", node.id);
+            assert!(node.peers.count(Direction::Inbound) <= 3, "{}", node.id);
+            for peer in node.peers.ids() {
+                // Every connection is known to both ends.
+                assert!(simulation.node(&peer).unwrap().peers.contains(&node.id), "{} -> {}", node.id, peer);
+            }
+        }
+        // N0 started with one neighbour and found a second through address exchange.
+        assert_eq!(simulation.node("N0").unwrap().peers.count(Direction::Outbound), 2);
+    }
+
+    #[test]
+    fn test_invalid_block_bans_peer_until_ban_expires() {
+        let peer_config = PeerConfig {
+            ban_duration: 60,
+            retry_interval: 10,
+            ..PeerConfig::default()
+        };
+        let mut simulation = Simulation::new(7, 1).with_peer_config(peer_config);
+        simulation.add_node("A");
+        simulation.add_node("Evil");
+        simulation.connect("Evil", "A");
+        simulation.tick_every(Duration::from_secs(5));
+
+        let genesis = simulation.node("A").unwrap().blockchain.blocks[0].hash;
+        let mut forged = Block::new_at(1, genesis, vec![], GENESIS_TIMESTAMP);
+        forged.hash = [0u8; 32];
+        simulation.schedule(
+            Duration::from_secs(1),
+            Event::Deliver(Envelope {
+                from: "Evil".to_string(),
+                to: "A".to_string(),
+                message: Message::Block(forged),
+            }),
+        );
+
+        simulation.run_until(Duration::from_secs(30));
+        let a = simulation.node("A").unwrap();
+        assert!(!a.peers.contains("Evil"));
+        assert!(a.peers.is_banned("Evil", simulation.timestamp()));
+        assert!(!simulation.node("Evil").unwrap().peers.contains("A"));
+
+        // Evil keeps redialling and gets in once the ban has expired.
+        simulation.run_until(Duration::from_secs(120));
+        assert!(simulation.node("A").unwrap().peers.contains("Evil"));
+        assert!(simulation.node("Evil").unwrap().peers.contains("A"));
+    }
+
+    #[test]
+    fn test_churned_nodes_resync_after_rejoining() {
+        let edges = [(0, 1), (1, 2), (2, 3), (3, 0), (0, 2)];
+        let mut simulation = relay_network(8, 4, &edges);
+        mine_at(&mut simulation, 0, Duration::ZERO, 1.0);
+        simulation.run_until(Duration::from_secs(5));
+
+        simulation.stop_node("N3");
+        simulation.remove_node("N1");
+        mine_at(&mut simulation, 0, Duration::ZERO, 2.0);
+        mine_at(&mut simulation, 2, Duration::from_secs(5), 3.0);
+        simulation.run_until(Duration::from_secs(20));
+        assert_eq!(simulation.node("N0").unwrap().blockchain.blocks.len(), 4);
+        assert!(simulation.node("N3").is_none());
+
+        simulation.restart_node("N3");
+        simulation.run_until(Duration::from_secs(40));
+
+        assert_single_tip(&simulation, 4);
+        assert!(simulation.node("N1").is_none());
+        assert_eq!(simulation.node("N3").unwrap().peers.ids(), vec!["N0".to_string(), "N2".to_string()]);
+        assert!(!simulation.node("N0").unwrap().peers.addresses().contains(&"N1".to_string()));
+    }
+
+    #[test]
+    fn test_virtual_clock() {
+        let mut simulation = Simulation::new(0, 1);
+        simulation.add_node("A");
+        simulation.schedule(
+            Duration::from_secs(90),
+            Event::SubmitTransaction(
+                "A".to_string(),
+                Box::new(Transaction::new_at(address::from_name("Alice"), address::from_name("Bob"), 1.0, 0)),
+            ),
+        );
+        simulation.schedule(Duration::from_secs(120), Event::Mine("A".to_string()));
+        simulation.run();
+
+        assert_eq!(simulation.now(), Duration::from_secs(120));
+        assert_eq!(simulation.events_processed(), 2);
+        let block = simulation.node("A").unwrap().blockchain.blocks[1].clone();
+        assert_eq!(block.timestamp, GENESIS_TIMESTAMP + 120);
+    }
+}
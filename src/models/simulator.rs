@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::models::message::Envelope;
+
+/// Delivery characteristics of a link between two nodes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LinkConfig {
+    pub latency: Duration,
+    /// Extra delay drawn uniformly from `0..=jitter` for each message.
+    pub jitter: Duration,
+    /// Probability in `0.0..=1.0` that a message is lost.
+    pub drop_rate: f64,
+    /// Probability in `0.0..=1.0` that a message is held back behind later ones.
+    pub reorder_rate: f64,
+}
+
+impl Default for LinkConfig {
+    /// An instant, lossless link.
+    fn default() -> Self {
+        LinkConfig {
+            latency: Duration::ZERO,
+            jitter: Duration::ZERO,
+            drop_rate: 0.0,
+            reorder_rate: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PartitionEvent {
+    /// Splits the network into groups that cannot reach each other. Nodes not
+    /// named in any group share one implicit group.
+    Partition(Vec<Vec<String>>),
+    Heal,
+}
+
+/// Counters describing what the link model did with the messages it saw.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LinkStats {
+    pub delivered: u64,
+    pub dropped: u64,
+    pub partitioned: u64,
+    pub reordered: u64,
+}
+
+/// Decides the fate of every message: how long it takes to arrive, or whether
+/// it arrives at all.
+///
+/// All randomness comes from a seeded RNG, so the same sequence of messages
+/// gets the same delays and losses on every run.
+pub struct LinkModel {
+    default_link: LinkConfig,
+    links: HashMap<(String, String), LinkConfig>,
+    groups: Option<HashMap<String, usize>>,
+    schedule: Vec<(Duration, PartitionEvent)>,
+    rng: StdRng,
+    stats: LinkStats,
+}
+
+impl LinkModel {
+    pub fn new(seed: u64) -> Self {
+        LinkModel {
+            default_link: LinkConfig::default(),
+            links: HashMap::new(),
+            groups: None,
+            schedule: Vec::new(),
+            rng: StdRng::seed_from_u64(seed),
+            stats: LinkStats::default(),
+        }
+    }
+
+    pub fn with_default_link(mut self, config: LinkConfig) -> Self {
+        self.default_link = config;
+        self
+    }
+
+    /// Overrides the configuration of the link between `a` and `b`, in both directions.
+    pub fn set_link(&mut self, a: &str, b: &str, config: LinkConfig) {
+        self.links.insert(link_key(a, b), config);
+    }
+
+    pub fn link(&self, a: &str, b: &str) -> LinkConfig {
+        self.links.get(&link_key(a, b)).copied().unwrap_or(self.default_link)
+    }
+
+    /// Applies `event` once the simulation clock reaches `at`.
+    pub fn schedule(&mut self, at: Duration, event: PartitionEvent) {
+        let position = self.schedule.partition_point(|(time, _)| *time <= at);
+        self.schedule.insert(position, (at, event));
+    }
+
+    pub fn partition(&mut self, groups: Vec<Vec<String>>) {
+        let mut membership = HashMap::new();
+        for (index, group) in groups.into_iter().enumerate() {
+            for node_id in group {
+                membership.insert(node_id, index + 1);
+            }
+        }
+        self.groups = Some(membership);
+    }
+
+    pub fn heal(&mut self) {
+        self.groups = None;
+    }
+
+    pub fn is_partitioned(&self, a: &str, b: &str) -> bool {
+        match &self.groups {
+            Some(groups) => groups.get(a).unwrap_or(&0) != groups.get(b).unwrap_or(&0),
+            None => false,
+        }
+    }
+
+    pub fn stats(&self) -> LinkStats {
+        self.stats
+    }
+
+    /// Applies every scheduled event that is due at `now`.
+    pub fn advance(&mut self, now: Duration) {
+        let due = self.schedule.partition_point(|(time, _)| *time <= now);
+        for (_, event) in self.schedule.drain(..due).collect::<Vec<_>>() {
+            match event {
+                PartitionEvent::Partition(groups) => self.partition(groups),
+                PartitionEvent::Heal => self.heal(),
+            }
+        }
+    }
+
+    /// Returns how long `envelope` takes to arrive when sent at `now`, or
+    /// `None` if it is lost.
+    pub fn route(&mut self, now: Duration, envelope: &Envelope) -> Option<Duration> {
+        self.advance(now);
+
+        if self.is_partitioned(&envelope.from, &envelope.to) {
+            self.stats.partitioned += 1;
+            return None;
+        }
+
+        let link = self.link(&envelope.from, &envelope.to);
+        if link.drop_rate > 0.0 && self.rng.random_bool(link.drop_rate.min(1.0)) {
+            self.stats.dropped += 1;
+            return None;
+        }
+
+        let mut delay = link.latency + self.sample_jitter(link.jitter);
+        if link.reorder_rate > 0.0 && self.rng.random_bool(link.reorder_rate.min(1.0)) {
+            // Hold the message back long enough for later ones to overtake it.
+            delay += link.latency + link.jitter + Duration::from_millis(1);
+            self.stats.reordered += 1;
+        }
+
+        self.stats.delivered += 1;
+        Some(delay)
+    }
+
+    fn sample_jitter(&mut self, jitter: Duration) -> Duration {
+        if jitter.is_zero() {
+            return Duration::ZERO;
+        }
+        Duration::from_micros(self.rng.random_range(0..=jitter.as_micros() as u64))
+    }
+}
+
+fn link_key(a: &str, b: &str) -> (String, String) {
+    if a <= b {
+        (a.to_string(), b.to_string())
+    } else {
+        (b.to_string(), a.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::message::Message;
+    use crate::models::Blockchain;
+
+    fn envelope(from: &str, to: &str) -> Envelope {
+        Envelope {
+            from: from.to_string(),
+            to: to.to_string(),
+            message: Message::Chain(Blockchain::new(0).blocks),
+        }
+    }
+
+    fn lossy_link() -> LinkConfig {
+        LinkConfig {
+            latency: Duration::from_millis(20),
+            jitter: Duration::from_millis(10),
+            drop_rate: 0.3,
+            reorder_rate: 0.2,
+        }
+    }
+
+    #[test]
+    fn test_same_seed_same_fates() {
+        let run = |seed| {
+            let mut model = LinkModel::new(seed).with_default_link(lossy_link());
+            (0..100)
+                .map(|_| model.route(Duration::ZERO, &envelope("A", "B")))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(run(7), run(7));
+        assert_ne!(run(7), run(8));
+    }
+
+    #[test]
+    fn test_latency_and_jitter_bounds() {
+        let link = LinkConfig {
+            latency: Duration::from_millis(20),
+            jitter: Duration::from_millis(10),
+            ..LinkConfig::default()
+        };
+        let mut model = LinkModel::new(1).with_default_link(link);
+        for _ in 0..100 {
+            let delay = model.route(Duration::ZERO, &envelope("A", "B")).unwrap();
+            assert!(delay >= Duration::from_millis(20) && delay <= Duration::from_millis(30));
+        }
+    }
+
+    #[test]
+    fn test_per_link_override() {
+        let mut model = LinkModel::new(1);
+        model.set_link("B", "A", LinkConfig { drop_rate: 1.0, ..LinkConfig::default() });
+        assert_eq!(model.route(Duration::ZERO, &envelope("A", "B")), None);
+        assert_eq!(model.route(Duration::ZERO, &envelope("A", "C")), Some(Duration::ZERO));
+        assert_eq!(model.stats().dropped, 1);
+    }
+
+    #[test]
+    fn test_scheduled_partition_and_heal() {
+        let mut model = LinkModel::new(1);
+        model.schedule(
+            Duration::from_secs(1),
+            PartitionEvent::Partition(vec![vec!["A".to_string()], vec!["B".to_string()]]),
+        );
+        model.schedule(Duration::from_secs(2), PartitionEvent::Heal);
+
+        assert!(model.route(Duration::from_millis(500), &envelope("A", "B")).is_some());
+        assert!(model.route(Duration::from_millis(1500), &envelope("A", "B")).is_none());
+        // Unlisted nodes share a group of their own.
+        assert!(model.route(Duration::from_millis(1500), &envelope("C", "D")).is_some());
+        assert!(model.route(Duration::from_millis(1500), &envelope("A", "C")).is_none());
+        assert!(model.route(Duration::from_millis(2500), &envelope("A", "B")).is_some());
+        assert_eq!(model.stats().partitioned, 2);
+    }
+}
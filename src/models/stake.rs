@@ -0,0 +1,410 @@
+//! Bonded stake, and the proof-of-stake engine it drives.
+//!
+//! Validators bond coins with a transaction to themselves; the coins leave
+//! their balance until they unbond them. Each block's proposer is drawn from
+//! the bonded validators with odds in proportion to their stake. The seed of
+//! the draw chains the previous seed with the address of each proposer in
+//! turn, so every node draws the same one, and since the draw fixes who that
+//! proposer is, nobody can steer it by grinding what goes into a block. A
+//! validator caught signing two blocks at one height loses its whole stake
+//! once anyone reports the two headers. Should the drawn validator be
+//! offline, the others may step in after it, in address order from it, each
+//! once the block is dated far enough past its parent (see `consensus`).
+//!
+//! Until anything is bonded there is nobody to draw, so any key may propose:
+//! that is how a new chain gets its first bonds mined. Such a chain should
+//! not be trusted with anything else before its validators have bonded.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use ed25519_dalek::SigningKey;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::models::block::SignedHeader;
+use crate::models::consensus::{ChainView, ConsensusEngine, ConsensusError, fallback_due, rank_weight};
+use crate::models::{Block, Transaction, address};
+
+/// What a transaction does with stake. Bonds and unbonds move the
+/// transaction's amount between the sender's balance and its stake.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StakeOp {
+    Bond,
+    Unbond,
+    /// Reports a validator that signed both headers, burning its stake.
+    Slash { first: Box<SignedHeader>, second: Box<SignedHeader> },
+}
+
+impl fmt::Display for StakeOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StakeOp::Bond => write!(f, "bonding"),
+            StakeOp::Unbond => write!(f, "unbonding"),
+            StakeOp::Slash { first, second } => write!(f, "reporting {} and {}", first, second),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StakeError {
+    /// Bonds and unbonds pay the sender itself, and nobody else.
+    NotToSelf,
+    InsufficientStake,
+    /// The headers are not two different blocks at one height signed by one key.
+    InvalidEvidence,
+    /// The reported validator has no stake left to lose.
+    NothingToSlash,
+}
+
+impl fmt::Display for StakeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StakeError::NotToSelf => write!(f, "stake is bonded and unbonded by the sender, to itself"),
+            StakeError::InsufficientStake => write!(f, "insufficient bonded stake"),
+            StakeError::InvalidEvidence => write!(f, "headers do not prove a double signature"),
+            StakeError::NothingToSlash => write!(f, "the validator has no stake to slash"),
+        }
+    }
+}
+
+/// Coins bonded on a chain, by validator.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Stakes {
+    bonded: BTreeMap<String, f64>,
+    /// Heights each validator was already slashed for, so evidence counts once.
+    slashed: BTreeSet<(String, u64)>,
+    /// What the next proposer is drawn with; see `reseed`.
+    seed: [u8; 32],
+}
+
+impl Stakes {
+    pub fn stake(&self, validator: &str) -> f64 {
+        self.bonded.get(validator).copied().unwrap_or(0.0)
+    }
+
+    pub fn total(&self) -> f64 {
+        self.bonded.values().sum()
+    }
+
+    /// The seed the proposer of the next block is drawn with.
+    pub fn seed(&self) -> &[u8; 32] {
+        &self.seed
+    }
+
+    /// Chains the seed with the proposer of `block`. While anything is bonded
+    /// the previous draw decided who that is, so the contents of the block,
+    /// its nonce included, cannot move the next draw.
+    pub fn reseed(&mut self, block: &Block) {
+        let mut hasher = Sha256::new();
+        hasher.update(self.seed);
+        hasher.update(block.signer().unwrap_or_default().as_bytes());
+        self.seed = hasher.finalize().into();
+    }
+
+    /// The validator drawn to propose a block with `seed`, or `None` while
+    /// nothing is bonded.
+    pub fn leader(&self, seed: &[u8; 32]) -> Option<&str> {
+        let total = self.total();
+        if total <= 0.0 {
+            return None;
+        }
+        let draw = u64::from_be_bytes(seed[..8].try_into().unwrap()) as f64 / (u64::MAX as f64 + 1.0) * total;
+        let mut cumulative = 0.0;
+        for (validator, stake) in &self.bonded {
+            cumulative += stake;
+            if draw < cumulative {
+                return Some(validator);
+            }
+        }
+        // Rounding can leave the draw a hair past the last validator's share.
+        self.bonded.keys().next_back().map(String::as_str)
+    }
+
+    /// The bonded validators in the order they may propose a block with
+    /// `seed`: the drawn leader, then the others in address order from it.
+    /// Empty while nothing is bonded.
+    pub fn ranked(&self, seed: &[u8; 32]) -> Vec<&str> {
+        let Some(leader) = self.leader(seed) else {
+            return Vec::new();
+        };
+        let validators: Vec<&str> = self.bonded.keys().map(String::as_str).collect();
+        let start = validators.iter().position(|validator| *validator == leader).unwrap_or(0);
+        validators[start..].iter().chain(&validators[..start]).copied().collect()
+    }
+
+    /// Bonds, unbonds or slashes as the transaction says.
+    pub fn apply(&mut self, transaction: &Transaction) -> Result<(), StakeError> {
+        self.check(transaction)?;
+        let Some(op) = &transaction.stake else {
+            return Ok(());
+        };
+        let sender = &transaction.sender;
+        match op {
+            StakeOp::Bond => *self.bonded.entry(sender.clone()).or_default() += transaction.amount,
+            StakeOp::Unbond => {
+                let stake = self.stake(sender) - transaction.amount;
+                if stake > 0.0 {
+                    self.bonded.insert(sender.clone(), stake);
+                } else {
+                    self.bonded.remove(sender);
+                }
+            }
+            StakeOp::Slash { first, .. } => {
+                let validator = first.signer().unwrap();
+                self.bonded.remove(&validator);
+                self.slashed.insert((validator, first.index));
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether `apply` would accept the transaction, without applying it.
+    pub fn check(&self, transaction: &Transaction) -> Result<(), StakeError> {
+        let Some(op) = &transaction.stake else {
+            return Ok(());
+        };
+        if !transaction.outputs.is_empty() {
+            return Err(StakeError::NotToSelf);
+        }
+        match op {
+            StakeOp::Bond | StakeOp::Unbond if transaction.receiver != transaction.sender => Err(StakeError::NotToSelf),
+            StakeOp::Bond => Ok(()),
+            StakeOp::Unbond if self.stake(&transaction.sender) < transaction.amount => {
+                Err(StakeError::InsufficientStake)
+            }
+            StakeOp::Unbond => Ok(()),
+            StakeOp::Slash { first, second } => {
+                let validator = double_signer(first, second).ok_or(StakeError::InvalidEvidence)?;
+                if self.stake(&validator) <= 0.0 || self.slashed.contains(&(validator, first.index)) {
+                    return Err(StakeError::NothingToSlash);
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Who signed both headers, if they are two different blocks at one height.
+fn double_signer(first: &SignedHeader, second: &SignedHeader) -> Option<String> {
+    if first.index != second.index || first.hash == second.hash {
+        return None;
+    }
+    let signer = first.signer()?;
+    (second.signer()? == signer).then_some(signer)
+}
+
+/// A transaction by `sender` carrying `op`, moving `amount` into or out of its stake.
+pub fn stake_at(sender: String, op: StakeOp, amount: f64, timestamp: u64) -> Transaction {
+    let mut transaction = Transaction::new_at(sender.clone(), sender, amount, timestamp);
+    transaction.stake = Some(op);
+    transaction
+}
+
+/// Proposes blocks when the stake-weighted draw picks this node's key, or
+/// when it is due to step in for those ranked ahead of it, and accepts blocks
+/// signed likewise. While nothing is bonded it proposes, and accepts, blocks
+/// signed by any key.
+#[derive(Debug, Clone)]
+pub struct ProofOfStake {
+    key: Option<SigningKey>,
+}
+
+impl ProofOfStake {
+    pub fn new(key: SigningKey) -> Self {
+        ProofOfStake { key: Some(key) }
+    }
+
+    /// An engine that verifies blocks but never proposes any.
+    pub fn observer() -> Self {
+        ProofOfStake { key: None }
+    }
+
+    fn key(&self) -> Result<&SigningKey, ConsensusError> {
+        self.key.as_ref().ok_or(ConsensusError::NoValidatorKey)
+    }
+}
+
+impl ConsensusEngine for ProofOfStake {
+    fn prepare(&self, view: &ChainView, block: &mut Block) -> Result<(), ConsensusError> {
+        let proposer = address::from_public_key(&self.key()?.verifying_key().to_bytes());
+        let ranked = view.stakes.ranked(view.stakes.seed());
+        if ranked.is_empty() {
+            return Ok(());
+        }
+        match ranked.iter().position(|validator| *validator == proposer) {
+            Some(rank) if fallback_due(rank, view, block) => Ok(()),
+            _ => Err(ConsensusError::NotProposer),
+        }
+    }
+
+    fn seal(&self, _view: &ChainView, block: &mut Block) -> Result<(), ConsensusError> {
+        block.sign(self.key()?);
+        Ok(())
+    }
+
+    fn verify(&self, view: &ChainView, block: &Block) -> Result<(), ConsensusError> {
+        let signer = block.signer().ok_or(ConsensusError::Unsigned)?;
+        let ranked = view.stakes.ranked(view.stakes.seed());
+        if ranked.is_empty() {
+            return Ok(());
+        }
+        match ranked.iter().position(|validator| *validator == signer) {
+            Some(rank) if fallback_due(rank, view, block) => Ok(()),
+            _ => Err(ConsensusError::WrongProposer {
+                expected: ranked[0].to_string(),
+            }),
+        }
+    }
+
+    fn weight(&self, view: &ChainView, block: &Block) -> u64 {
+        let ranked = view.stakes.ranked(view.stakes.seed());
+        let signer = block.signer().unwrap_or_default();
+        rank_weight(ranked.iter().position(|validator| *validator == signer).unwrap_or(0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::models::Blockchain;
+    use crate::models::blockchain::{BlockchainError, GENESIS_TIMESTAMP};
+    use crate::models::consensus::{FALLBACK_SECONDS, validator_address, validator_key};
+
+    fn signed(node_id: &str, mut transaction: Transaction) -> Transaction {
+        transaction.sign(&validator_key(node_id));
+        transaction
+    }
+
+    #[test]
+    fn test_leaders_are_drawn_by_stake() {
+        let mut stakes = Stakes::default();
+        assert_eq!(stakes.leader(&[0; 32]), None);
+        stakes.apply(&stake_at("big".to_string(), StakeOp::Bond, 90.0, 1)).unwrap();
+        stakes.apply(&stake_at("small".to_string(), StakeOp::Bond, 10.0, 1)).unwrap();
+        let drawn = (0..1_000u32)
+            .filter(|i| stakes.leader(&Sha256::digest(i.to_be_bytes()).into()) == Some("big"))
+            .count();
+        assert!((850..950).contains(&drawn), "big drawn {} times", drawn);
+    }
+
+    #[test]
+    fn test_only_the_drawn_validator_proposes_and_double_signing_is_slashed() {
+        let (alice, bob) = (validator_address("alice"), validator_address("bob"));
+        let engine = |node_id: &str| Arc::new(ProofOfStake::new(validator_key(node_id)));
+        let mut bc = Blockchain::new(0).with_consensus(engine("alice"));
+        bc.add_transaction(signed("alice", stake_at(alice.clone(), StakeOp::Bond, 30.0, 1))).unwrap();
+        bc.add_transaction(signed("bob", stake_at(bob.clone(), StakeOp::Bond, 30.0, 1))).unwrap();
+        let first = bc.mine_block_at(GENESIS_TIMESTAMP + 100).unwrap();
+        assert_eq!(first.signer(), Some(alice.clone()));
+        assert_eq!((bc.stakes.stake(&alice), bc.get_balance(&alice)), (30.0, 70.0));
+
+        // Whoever the draw skips cannot propose before the leader is overdue,
+        // and its blocks are refused until then.
+        let leader = bc.stakes.leader(bc.stakes.seed()).unwrap().to_string();
+        let (leader_id, other_id) = if leader == alice { ("alice", "bob") } else { ("bob", "alice") };
+        let transfer = signed(leader_id, Transaction::new_at(leader.clone(), alice.clone(), 1.0, 2));
+        let mut other = bc.clone().with_consensus(engine(other_id));
+        other.add_transaction(transfer.clone()).unwrap();
+        assert_eq!(
+            other.mine_block_at(GENESIS_TIMESTAMP + 101),
+            Err(BlockchainError::Consensus(ConsensusError::NotProposer))
+        );
+        let mut forged = Block::new_at(2, first.hash, vec![transfer.clone()], GENESIS_TIMESTAMP + 101);
+        forged.sign(&validator_key(other_id));
+        assert_eq!(
+            other.append_block(forged.clone()),
+            Err(BlockchainError::Consensus(ConsensusError::WrongProposer { expected: leader.clone() }))
+        );
+        let mut leading = bc.clone().with_consensus(engine(leader_id));
+        leading.add_transaction(transfer).unwrap();
+        let second = leading.mine_block_at(GENESIS_TIMESTAMP + 200).unwrap();
+        assert_eq!(second.signer(), Some(leader.clone()));
+        assert!(leading.validate_chain().is_ok());
+
+        // Once the leader is overdue the other steps in, but a chain with the
+        // leader's block outweighs one with the backup's.
+        let backup = other.mine_block_at(GENESIS_TIMESTAMP + 100 + FALLBACK_SECONDS).unwrap();
+        assert_eq!(backup.signer(), Some(validator_address(other_id)));
+        assert!(!leading.clone().replace_chain(other.blocks.clone()));
+        assert!(other.replace_chain(leading.blocks.clone()));
+
+        // Signing a competing block at the same height costs the leader its stake.
+        let mut competing = Block::new_at(2, first.hash, vec![], GENESIS_TIMESTAMP + 201);
+        competing.sign(&validator_key(leader_id));
+        let evidence = StakeOp::Slash {
+            first: Box::new(second.header().unwrap()),
+            second: Box::new(competing.header().unwrap()),
+        };
+        let reporter = if leader == alice { bob.clone() } else { alice.clone() };
+        let slash = signed(other_id, stake_at(reporter.clone(), evidence.clone(), 0.0, 3));
+        leading.add_transaction(slash).unwrap();
+        let again = signed(other_id, stake_at(reporter.clone(), evidence, 0.0, 4));
+        assert_eq!(
+            leading.add_transaction(again),
+            Err(BlockchainError::Stake(StakeError::NothingToSlash))
+        );
+        let bogus = StakeOp::Slash {
+            first: Box::new(second.header().unwrap()),
+            second: Box::new(forged.header().unwrap()),
+        };
+        assert_eq!(
+            leading.add_transaction(signed(other_id, stake_at(reporter, bogus, 0.0, 5))),
+            Err(BlockchainError::Stake(StakeError::InvalidEvidence))
+        );
+        let next = leading.stakes.leader(leading.stakes.seed()).unwrap().to_string();
+        let mut proposer = leading.with_consensus(engine(if next == alice { "alice" } else { "bob" }));
+        proposer.mine_block_at(GENESIS_TIMESTAMP + 300).unwrap();
+        assert_eq!(proposer.stakes.stake(&leader), 0.0);
+        assert_eq!(proposer.stakes.total(), 30.0);
+    }
+
+    #[test]
+    fn test_what_a_proposer_puts_in_its_block_does_not_move_the_draw() {
+        let alice = validator_address("alice");
+        let mut bc = Blockchain::new(0).with_consensus(Arc::new(ProofOfStake::new(validator_key("alice"))));
+        bc.add_transaction(signed("alice", stake_at(alice.clone(), StakeOp::Bond, 40.0, 1))).unwrap();
+        bc.mine_block_at(GENESIS_TIMESTAMP + 100).unwrap();
+        let seeds: Vec<[u8; 32]> = [(2.0, 200), (3.0, 201)]
+            .into_iter()
+            .map(|(amount, timestamp)| {
+                let mut fork = bc.clone();
+                let payment = Transaction::new_at(alice.clone(), validator_address("bob"), amount, timestamp);
+                fork.add_transaction(signed("alice", payment)).unwrap();
+                fork.mine_block_at(GENESIS_TIMESTAMP + timestamp).unwrap();
+                *fork.stakes.seed()
+            })
+            .collect();
+        assert_eq!(seeds[0], seeds[1]);
+        assert_ne!(seeds[0], *bc.stakes.seed());
+
+        let mut replica = Blockchain::new(0).with_consensus(Arc::new(ProofOfStake::observer()));
+        assert!(replica.replace_chain(bc.blocks.clone()));
+        assert_eq!(replica.stakes.seed(), bc.stakes.seed());
+    }
+
+    #[test]
+    fn test_unbonding_returns_what_was_bonded() {
+        let alice = validator_address("alice");
+        let mut bc = Blockchain::new(0).with_consensus(Arc::new(ProofOfStake::new(validator_key("alice"))));
+        let stake = |op: StakeOp, amount: f64, timestamp: u64| signed("alice", stake_at(alice.clone(), op, amount, timestamp));
+        bc.add_transaction(stake(StakeOp::Bond, 40.0, 1)).unwrap();
+        bc.mine_block_at(GENESIS_TIMESTAMP + 100).unwrap();
+        let refused = |op: StakeOp, amount: f64| bc.clone().add_transaction(stake(op, amount, 2));
+        assert_eq!(refused(StakeOp::Unbond, 41.0), Err(BlockchainError::Stake(StakeError::InsufficientStake)));
+        let mut elsewhere = stake_at(alice.clone(), StakeOp::Bond, 1.0, 2);
+        elsewhere.receiver = validator_address("bob");
+        let elsewhere = signed("alice", elsewhere);
+        assert_eq!(bc.add_transaction(elsewhere), Err(BlockchainError::Stake(StakeError::NotToSelf)));
+
+        bc.add_transaction(stake(StakeOp::Unbond, 15.0, 3)).unwrap();
+        bc.mine_block_at(GENESIS_TIMESTAMP + 200).unwrap();
+        assert_eq!((bc.stakes.stake(&alice), bc.get_balance(&alice)), (25.0, 75.0));
+        let mut replica = Blockchain::new(0).with_consensus(Arc::new(ProofOfStake::observer()));
+        assert!(replica.replace_chain(bc.blocks.clone()));
+        assert_eq!(replica.stakes, bc.stakes);
+    }
+}
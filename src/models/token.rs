@@ -0,0 +1,292 @@
+//! Fungible tokens issued on the ledger next to the native currency.
+//!
+//! A token is identified by its symbol, which the first issue claims for
+//! good. Amounts are whole base units; `decimals` only says where wallets put
+//! the point. The issuer alone can mint new units and burn units it holds.
+//! Token operations ride on ordinary transactions, so whatever native amount
+//! and fee they carry moves as usual.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::models::Transaction;
+
+/// The longest symbol a token may have.
+pub const MAX_SYMBOL_LENGTH: usize = 11;
+/// The most decimal places a token may have.
+pub const MAX_DECIMALS: u8 = 18;
+
+/// What a transaction does with a token. Units go to the transaction's receiver.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenOp {
+    /// Creates the token, with the sender as issuer, and gives the whole supply to the receiver.
+    Issue { symbol: String, decimals: u8, supply: u64 },
+    Transfer { symbol: String, amount: u64 },
+    /// Creates new units; only the issuer may.
+    Mint { symbol: String, amount: u64 },
+    /// Destroys units the issuer holds.
+    Burn { symbol: String, amount: u64 },
+}
+
+impl TokenOp {
+    pub fn symbol(&self) -> &str {
+        match self {
+            TokenOp::Issue { symbol, .. }
+            | TokenOp::Transfer { symbol, .. }
+            | TokenOp::Mint { symbol, .. }
+            | TokenOp::Burn { symbol, .. } => symbol,
+        }
+    }
+}
+
+impl fmt::Display for TokenOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenOp::Issue {
+                symbol,
+                decimals,
+                supply,
+            } => write!(f, "issuing {} {} with {} decimals", supply, symbol, decimals),
+            TokenOp::Transfer { symbol, amount } => write!(f, "transferring {} {}", amount, symbol),
+            TokenOp::Mint { symbol, amount } => write!(f, "minting {} {}", amount, symbol),
+            TokenOp::Burn { symbol, amount } => write!(f, "burning {} {}", amount, symbol),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenError {
+    /// The symbol is empty, too long or not made of uppercase letters and digits.
+    InvalidSymbol,
+    TooManyDecimals,
+    SymbolTaken,
+    UnknownToken,
+    /// Only the issuer may mint or burn.
+    NotIssuer,
+    InsufficientTokens,
+    /// Minting would take the supply past what a `u64` holds.
+    SupplyOverflow,
+    /// The token goes to more than one receiver.
+    MultipleReceivers,
+}
+
+impl fmt::Display for TokenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenError::InvalidSymbol => write!(
+                f,
+                "token symbols are 1 to {} uppercase letters and digits",
+                MAX_SYMBOL_LENGTH
+            ),
+            TokenError::TooManyDecimals => write!(f, "a token has at most {} decimals", MAX_DECIMALS),
+            TokenError::SymbolTaken => write!(f, "a token with that symbol already exists"),
+            TokenError::UnknownToken => write!(f, "no such token"),
+            TokenError::NotIssuer => write!(f, "only the token's issuer may mint or burn it"),
+            TokenError::InsufficientTokens => write!(f, "insufficient token balance"),
+            TokenError::SupplyOverflow => write!(f, "token supply would overflow"),
+            TokenError::MultipleReceivers => write!(f, "token operations have a single receiver"),
+        }
+    }
+}
+
+/// An issued token and who holds it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub issuer: String,
+    pub decimals: u8,
+    pub supply: u64,
+    balances: BTreeMap<String, u64>,
+}
+
+impl Token {
+    pub fn balance(&self, address: &str) -> u64 {
+        self.balances.get(address).copied().unwrap_or(0)
+    }
+
+    fn credit(&mut self, address: &str, amount: u64) {
+        *self.balances.entry(address.to_string()).or_default() += amount;
+    }
+
+    fn debit(&mut self, address: &str, amount: u64) {
+        let balance = self.balance(address) - amount;
+        if balance == 0 {
+            self.balances.remove(address);
+        } else {
+            self.balances.insert(address.to_string(), balance);
+        }
+    }
+}
+
+/// Every token issued on a chain, by symbol.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Tokens {
+    tokens: BTreeMap<String, Token>,
+}
+
+impl Tokens {
+    pub fn get(&self, symbol: &str) -> Option<&Token> {
+        self.tokens.get(symbol)
+    }
+
+    /// Issues, moves, mints or burns the units the transaction names.
+    pub fn apply(&mut self, transaction: &Transaction) -> Result<(), TokenError> {
+        self.check(transaction)?;
+        let Some(op) = &transaction.token else {
+            return Ok(());
+        };
+        let (sender, receiver) = (&transaction.sender, &transaction.receiver);
+        match op {
+            TokenOp::Issue {
+                symbol,
+                decimals,
+                supply,
+            } => {
+                let mut token = Token {
+                    issuer: sender.clone(),
+                    decimals: *decimals,
+                    supply: *supply,
+                    balances: BTreeMap::new(),
+                };
+                token.credit(receiver, *supply);
+                self.tokens.insert(symbol.clone(), token);
+            }
+            TokenOp::Transfer { symbol, amount } => {
+                let token = self.tokens.get_mut(symbol).unwrap();
+                token.debit(sender, *amount);
+                token.credit(receiver, *amount);
+            }
+            TokenOp::Mint { symbol, amount } => {
+                let token = self.tokens.get_mut(symbol).unwrap();
+                token.supply += amount;
+                token.credit(receiver, *amount);
+            }
+            TokenOp::Burn { symbol, amount } => {
+                let token = self.tokens.get_mut(symbol).unwrap();
+                token.supply -= amount;
+                token.debit(sender, *amount);
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether `apply` would accept the transaction, without applying it.
+    pub fn check(&self, transaction: &Transaction) -> Result<(), TokenError> {
+        let Some(op) = &transaction.token else {
+            return Ok(());
+        };
+        if !transaction.outputs.is_empty() {
+            return Err(TokenError::MultipleReceivers);
+        }
+        if let TokenOp::Issue { symbol, decimals, .. } = op {
+            if !is_valid_symbol(symbol) {
+                return Err(TokenError::InvalidSymbol);
+            }
+            if *decimals > MAX_DECIMALS {
+                return Err(TokenError::TooManyDecimals);
+            }
+            if self.tokens.contains_key(symbol) {
+                return Err(TokenError::SymbolTaken);
+            }
+            return Ok(());
+        }
+        let token = self.tokens.get(op.symbol()).ok_or(TokenError::UnknownToken)?;
+        match op {
+            TokenOp::Transfer { amount, .. } => {
+                if token.balance(&transaction.sender) < *amount {
+                    return Err(TokenError::InsufficientTokens);
+                }
+            }
+            TokenOp::Mint { amount, .. } => {
+                if transaction.sender != token.issuer {
+                    return Err(TokenError::NotIssuer);
+                }
+                if token.supply.checked_add(*amount).is_none() {
+                    return Err(TokenError::SupplyOverflow);
+                }
+            }
+            TokenOp::Burn { amount, .. } => {
+                if transaction.sender != token.issuer {
+                    return Err(TokenError::NotIssuer);
+                }
+                if token.balance(&transaction.sender) < *amount {
+                    return Err(TokenError::InsufficientTokens);
+                }
+            }
+            TokenOp::Issue { .. } => unreachable!("issues are checked above"),
+        }
+        Ok(())
+    }
+}
+
+fn is_valid_symbol(symbol: &str) -> bool {
+    (1..=MAX_SYMBOL_LENGTH).contains(&symbol.len())
+        && symbol.bytes().all(|byte| byte.is_ascii_uppercase() || byte.is_ascii_digit())
+}
+
+/// A transaction carrying `op` from `sender` to `receiver`, moving no native currency.
+pub fn token_at(sender: String, receiver: String, op: TokenOp, timestamp: u64) -> Transaction {
+    let mut transaction = Transaction::new_at(sender, receiver, 0.0, timestamp);
+    transaction.token = Some(op);
+    transaction
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::address;
+    use crate::models::blockchain::{Blockchain, BlockchainError, GENESIS_TIMESTAMP};
+
+    #[test]
+    fn test_issue_transfer_mint_and_burn() {
+        let (alice, bob) = (address::from_name("Alice"), address::from_name("Bob"));
+        let symbol = || "ACME".to_string();
+        let mut bc = Blockchain::new(0);
+        let issue = TokenOp::Issue {
+            symbol: symbol(),
+            decimals: 2,
+            supply: 1_000,
+        };
+        bc.add_transaction(token_at(alice.clone(), alice.clone(), issue.clone(), 1)).unwrap();
+        let transfer = TokenOp::Transfer { symbol: symbol(), amount: 300 };
+        bc.add_transaction(token_at(alice.clone(), bob.clone(), transfer, 2)).unwrap();
+        bc.mine_block_at(GENESIS_TIMESTAMP + 100).unwrap();
+        assert_eq!((bc.token_balance("ACME", &alice), bc.token_balance("ACME", &bob)), (700, 300));
+        assert_eq!(bc.token_supply("ACME"), Some(1_000));
+        assert_eq!(bc.get_balance(&alice), 100.0);
+
+        let refused =
+            |sender: &str, op: TokenOp| bc.clone().add_transaction(token_at(sender.to_string(), alice.clone(), op, 3));
+        let mint = |amount| TokenOp::Mint { symbol: symbol(), amount };
+        let burn = |amount| TokenOp::Burn { symbol: symbol(), amount };
+        assert_eq!(refused(&alice, issue), Err(BlockchainError::Token(TokenError::SymbolTaken)));
+        assert_eq!(refused(&bob, mint(1)), Err(BlockchainError::Token(TokenError::NotIssuer)));
+        assert_eq!(refused(&bob, burn(1)), Err(BlockchainError::Token(TokenError::NotIssuer)));
+        assert_eq!(refused(&alice, burn(701)), Err(BlockchainError::Token(TokenError::InsufficientTokens)));
+        assert_eq!(refused(&alice, mint(u64::MAX)), Err(BlockchainError::Token(TokenError::SupplyOverflow)));
+        let overdraw = TokenOp::Transfer { symbol: symbol(), amount: 301 };
+        assert_eq!(refused(&bob, overdraw), Err(BlockchainError::Token(TokenError::InsufficientTokens)));
+        let unknown = TokenOp::Transfer { symbol: "NOPE".to_string(), amount: 1 };
+        assert_eq!(refused(&alice, unknown), Err(BlockchainError::Token(TokenError::UnknownToken)));
+        let lowercase = TokenOp::Issue { symbol: "acme".to_string(), decimals: 2, supply: 1 };
+        assert_eq!(refused(&alice, lowercase), Err(BlockchainError::Token(TokenError::InvalidSymbol)));
+
+        // Transfers are checked against the pending ones too.
+        let spend = |amount| token_at(bob.clone(), alice.clone(), TokenOp::Transfer { symbol: symbol(), amount }, 4);
+        bc.add_transaction(spend(200)).unwrap();
+        assert_eq!(bc.add_transaction(spend(101)), Err(BlockchainError::Token(TokenError::InsufficientTokens)));
+        bc.add_transaction(token_at(alice.clone(), bob.clone(), mint(50), 5)).unwrap();
+        bc.add_transaction(token_at(alice.clone(), alice.clone(), burn(400), 6)).unwrap();
+        bc.mine_block_at(GENESIS_TIMESTAMP + 200).unwrap();
+        assert_eq!((bc.token_balance("ACME", &alice), bc.token_balance("ACME", &bob)), (500, 150));
+        assert_eq!(bc.token_supply("ACME"), Some(650));
+        assert_eq!(bc.tokens.get("ACME").unwrap().decimals, 2);
+
+        assert!(bc.validate_chain().is_ok());
+        let mut replica = Blockchain::new(0);
+        assert!(replica.replace_chain(bc.blocks.clone()));
+        assert_eq!(replica.tokens, bc.tokens);
+    }
+}
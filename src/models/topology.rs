@@ -0,0 +1,355 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt;
+use std::path::Path;
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+/// An undirected graph of node ids, used to wire up a simulated network.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Topology {
+    pub nodes: Vec<String>,
+    /// Each edge is stored once, with the smaller id first.
+    pub edges: BTreeSet<(String, String)>,
+}
+
+#[derive(Debug)]
+pub enum TopologyError {
+    Io(std::io::Error),
+    Parse(String),
+    /// No graph with the requested shape exists, e.g. an odd-degree regular graph on an odd node count.
+    Impossible(String),
+}
+
+impl fmt::Display for TopologyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TopologyError::Io(e) => write!(f, "could not read topology: {}", e),
+            TopologyError::Parse(e) => write!(f, "could not parse topology: {}", e),
+            TopologyError::Impossible(e) => write!(f, "impossible topology: {}", e),
+        }
+    }
+}
+
+/// Names nodes `Node_0`, `Node_1`, ... for the generated topologies.
+pub fn node_names(count: usize) -> Vec<String> {
+    (0..count).map(|i| format!("Node_{}", i)).collect()
+}
+
+impl Topology {
+    pub fn new(nodes: Vec<String>) -> Self {
+        Topology {
+            nodes,
+            edges: BTreeSet::new(),
+        }
+    }
+
+    /// Adds an undirected edge, adding either endpoint as a node if needed.
+    /// Self-loops are ignored.
+    pub fn connect(&mut self, a: &str, b: &str) {
+        if a == b {
+            return;
+        }
+        for id in [a, b] {
+            if !self.nodes.iter().any(|node| node == id) {
+                self.nodes.push(id.to_string());
+            }
+        }
+        let edge = if a < b { (a.to_string(), b.to_string()) } else { (b.to_string(), a.to_string()) };
+        self.edges.insert(edge);
+    }
+
+    pub fn full_mesh(count: usize) -> Self {
+        let mut topology = Topology::new(node_names(count));
+        for i in 0..count {
+            for j in i + 1..count {
+                topology.connect_indices(i, j);
+            }
+        }
+        topology
+    }
+
+    pub fn ring(count: usize) -> Self {
+        let mut topology = Topology::line(count);
+        if count > 2 {
+            topology.connect_indices(count - 1, 0);
+        }
+        topology
+    }
+
+    /// Every other node connects to the first one.
+    pub fn star(count: usize) -> Self {
+        let mut topology = Topology::new(node_names(count));
+        for i in 1..count {
+            topology.connect_indices(0, i);
+        }
+        topology
+    }
+
+    pub fn line(count: usize) -> Self {
+        let mut topology = Topology::new(node_names(count));
+        for i in 1..count {
+            topology.connect_indices(i - 1, i);
+        }
+        topology
+    }
+
+    /// A random graph in which every node has exactly `degree` neighbours,
+    /// built by repeatedly pairing up free connection stubs.
+    pub fn random_regular(count: usize, degree: usize, seed: u64) -> Result<Self, TopologyError> {
+        if degree >= count.max(1) || !(count * degree).is_multiple_of(2) {
+            return Err(TopologyError::Impossible(format!(
+                "no {}-regular graph on {} nodes",
+                degree, count
+            )));
+        }
+        let mut rng = StdRng::seed_from_u64(seed);
+        // Pairing can paint itself into a corner; start over when it does.
+        'attempt: for _ in 0..1_000 {
+            let mut topology = Topology::new(node_names(count));
+            let mut stubs: Vec<usize> = (0..count).flat_map(|i| std::iter::repeat_n(i, degree)).collect();
+            stubs.shuffle(&mut rng);
+            while let Some(a) = stubs.pop() {
+                let partner = (0..stubs.len()).find(|&k| {
+                    let b = stubs[k];
+                    b != a && !topology.has_edge(&topology.nodes[a], &topology.nodes[b])
+                });
+                let Some(k) = partner else { continue 'attempt };
+                let b = stubs.swap_remove(k);
+                topology.connect_indices(a, b);
+                stubs.shuffle(&mut rng);
+            }
+            return Ok(topology);
+        }
+        Err(TopologyError::Impossible(format!(
+            "could not build a {}-regular graph on {} nodes",
+            degree, count
+        )))
+    }
+
+    /// A G(n, p) random graph: each possible edge exists with probability `probability`.
+    pub fn erdos_renyi(count: usize, probability: f64, seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut topology = Topology::new(node_names(count));
+        for i in 0..count {
+            for j in i + 1..count {
+                if rng.random_bool(probability.clamp(0.0, 1.0)) {
+                    topology.connect_indices(i, j);
+                }
+            }
+        }
+        topology
+    }
+
+    /// Parses `{"nodes": [...], "edges": [["a", "b"], ...]}`. Nodes that only
+    /// appear in edges are added automatically.
+    pub fn from_json(json: &str) -> Result<Self, TopologyError> {
+        #[derive(Deserialize)]
+        struct File {
+            #[serde(default)]
+            nodes: Vec<String>,
+            #[serde(default)]
+            edges: Vec<(String, String)>,
+        }
+        let file: File = serde_json::from_str(json).map_err(|e| TopologyError::Parse(e.to_string()))?;
+        let mut topology = Topology::new(Vec::new());
+        for node in file.nodes {
+            if !topology.nodes.contains(&node) {
+                topology.nodes.push(node);
+            }
+        }
+        for (a, b) in file.edges {
+            topology.connect(&a, &b);
+        }
+        Ok(topology)
+    }
+
+    /// Parses one edge per line as two whitespace-separated ids. A line with a
+    /// single id declares an isolated node; blank lines and `#` comments are skipped.
+    pub fn from_edge_list(text: &str) -> Result<Self, TopologyError> {
+        let mut topology = Topology::new(Vec::new());
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            let ids: Vec<&str> = line.split_whitespace().collect();
+            match ids.as_slice() {
+                [] => {}
+                [id] => {
+                    if !topology.nodes.iter().any(|node| node == id) {
+                        topology.nodes.push(id.to_string());
+                    }
+                }
+                [a, b] => topology.connect(a, b),
+                _ => {
+                    return Err(TopologyError::Parse(format!(
+                        "line {}: expected one or two ids, found {}",
+                        number + 1,
+                        ids.len()
+                    )));
+                }
+            }
+        }
+        Ok(topology)
+    }
+
+    /// Loads a `.json` file as JSON and anything else as an edge list.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, TopologyError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(TopologyError::Io)?;
+        if path.extension().is_some_and(|extension| extension == "json") {
+            Topology::from_json(&text)
+        } else {
+            Topology::from_edge_list(&text)
+        }
+    }
+
+    pub fn has_edge(&self, a: &str, b: &str) -> bool {
+        let edge = if a < b { (a.to_string(), b.to_string()) } else { (b.to_string(), a.to_string()) };
+        self.edges.contains(&edge)
+    }
+
+    pub fn neighbours(&self) -> BTreeMap<&str, Vec<&str>> {
+        let mut neighbours: BTreeMap<&str, Vec<&str>> =
+            self.nodes.iter().map(|node| (node.as_str(), Vec::new())).collect();
+        for (a, b) in &self.edges {
+            neighbours.entry(a.as_str()).or_default().push(b.as_str());
+            neighbours.entry(b.as_str()).or_default().push(a.as_str());
+        }
+        neighbours
+    }
+
+    pub fn degree(&self, node: &str) -> usize {
+        self.edges.iter().filter(|(a, b)| a == node || b == node).count()
+    }
+
+    /// Groups of nodes that can reach each other, each sorted, largest first.
+    pub fn components(&self) -> Vec<Vec<String>> {
+        let neighbours = self.neighbours();
+        let mut seen = BTreeSet::new();
+        let mut components = Vec::new();
+        for start in neighbours.keys() {
+            if !seen.insert(*start) {
+                continue;
+            }
+            let mut component = vec![start.to_string()];
+            let mut queue = VecDeque::from([*start]);
+            while let Some(node) = queue.pop_front() {
+                for next in &neighbours[node] {
+                    if seen.insert(*next) {
+                        component.push(next.to_string());
+                        queue.push_back(next);
+                    }
+                }
+            }
+            component.sort();
+            components.push(component);
+        }
+        components.sort_by_key(|component| std::cmp::Reverse(component.len()));
+        components
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.components().len() <= 1
+    }
+
+    /// The longest shortest path between any two nodes, in hops, or `None`
+    /// if the graph is disconnected.
+    pub fn diameter(&self) -> Option<usize> {
+        if !self.is_connected() {
+            return None;
+        }
+        let neighbours = self.neighbours();
+        let mut diameter = 0;
+        for start in neighbours.keys() {
+            let mut distance = BTreeMap::from([(*start, 0usize)]);
+            let mut queue = VecDeque::from([*start]);
+            while let Some(node) = queue.pop_front() {
+                let hops = distance[node];
+                diameter = diameter.max(hops);
+                for next in &neighbours[node] {
+                    if !distance.contains_key(next) {
+                        distance.insert(next, hops + 1);
+                        queue.push_back(next);
+                    }
+                }
+            }
+        }
+        Some(diameter)
+    }
+
+    fn connect_indices(&mut self, i: usize, j: usize) {
+        let (a, b) = (self.nodes[i].clone(), self.nodes[j].clone());
+        self.connect(&a, &b);
+    }
+}
+
+impl fmt::Display for Topology {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let components = self.components();
+        write!(f, "{} nodes, {} edges, ", self.nodes.len(), self.edges.len())?;
+        match self.diameter() {
+            Some(diameter) => write!(f, "connected, diameter {}", diameter),
+            None => write!(f, "{} components", components.len()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_basic_shapes() {
+        let mesh = Topology::full_mesh(5);
+        assert_eq!(mesh.edges.len(), 10);
+        assert_eq!(mesh.diameter(), Some(1));
+
+        let ring = Topology::ring(6);
+        assert_eq!(ring.edges.len(), 6);
+        assert_eq!(ring.diameter(), Some(3));
+
+        let star = Topology::star(5);
+        assert_eq!(star.degree("Node_0"), 4);
+        assert_eq!(star.diameter(), Some(2));
+
+        let line = Topology::line(5);
+        assert_eq!(line.edges.len(), 4);
+        assert_eq!(line.diameter(), Some(4));
+    }
+
+    #[test]
+    fn test_random_regular() {
+        let topology = Topology::random_regular(20, 3, 11).unwrap();
+        assert!(topology.nodes.iter().all(|node| topology.degree(node) == 3));
+        assert_eq!(topology, Topology::random_regular(20, 3, 11).unwrap());
+        assert!(Topology::random_regular(5, 3, 11).is_err());
+    }
+
+    #[test]
+    fn test_erdos_renyi_is_seeded() {
+        let topology = Topology::erdos_renyi(30, 0.2, 5);
+        assert_eq!(topology, Topology::erdos_renyi(30, 0.2, 5));
+        assert_ne!(topology, Topology::erdos_renyi(30, 0.2, 6));
+        assert!(Topology::erdos_renyi(10, 0.0, 5).edges.is_empty());
+        assert_eq!(Topology::erdos_renyi(10, 0.0, 5).components().len(), 10);
+    }
+
+    #[test]
+    fn test_parse_json_and_edge_list() {
+        let json = r#"{"nodes": ["A", "B", "C", "Lonely"], "edges": [["A", "B"], ["B", "C"], ["C", "D"]]}"#;
+        let topology = Topology::from_json(json).unwrap();
+        assert_eq!(topology.nodes, vec!["A", "B", "C", "Lonely", "D"]);
+        assert_eq!(topology.edges.len(), 3);
+        assert!(!topology.is_connected());
+        assert_eq!(topology.diameter(), None);
+        assert_eq!(topology.components()[0], vec!["A", "B", "C", "D"]);
+
+        let edge_list = "# a triangle\nA B\nB C\nC A\n\nLonely\n";
+        let topology = Topology::from_edge_list(edge_list).unwrap();
+        assert_eq!(topology.edges.len(), 3);
+        assert_eq!(topology.nodes.len(), 4);
+        assert!(Topology::from_edge_list("A B C").is_err());
+        assert!(Topology::from_json("[").is_err());
+    }
+}
@@ -1,20 +1,314 @@
+use std::fmt;
+
 use chrono::Utc;
+use ed25519_dalek::{Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::models::anchor::Anchor;
+use crate::models::authority::AuthorityOp;
+use crate::models::htlc::Htlc;
+use crate::models::multisig::MultisigSpend;
+use crate::models::nft::AssetOp;
+use crate::models::script::ScriptSpend;
+use crate::models::stake::StakeOp;
+use crate::models::token::TokenOp;
+use crate::models::utxo::{OutPoint, TxOutput};
+use crate::models::wasm::Wasm;
+use crate::models::{address, hex};
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Transaction {
     pub sender: String,
     pub receiver: String,
     pub amount: f64,
     pub timestamp: u64,
+    /// Payments made alongside the one to `receiver`, debited from the sender
+    /// together with it: the transaction is accepted or refused as a whole.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub outputs: Vec<TxOutput>,
+    /// Paid by the sender on top of the payments, and credited to nobody.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub fee: f64,
+    /// The transaction cannot be mined into a block below this height.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lock_height: Option<u64>,
+    /// The transaction cannot be mined into a block stamped before this time.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lock_time: Option<u64>,
+    /// Opens, claims or refunds a hash time-locked contract; see `htlc`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub htlc: Option<Htlc>,
+    /// Deploys or calls a WASM contract at the receiver; see `wasm`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wasm: Option<Wasm>,
+    /// Issues, transfers, mints or burns a token; see `token`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<TokenOp>,
+    /// Mints or transfers a unique asset; see `nft`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub asset: Option<AssetOp>,
+    /// A document hash and memo committed to the chain; see `anchor`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub anchor: Option<Anchor>,
+    /// Bonds, unbonds or slashes validator stake; see `stake`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stake: Option<StakeOp>,
+    /// Votes to add the receiver to or remove it from the proof-of-authority validators; see `authority`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authority: Option<AuthorityOp>,
+    /// Outputs consumed by the transfer. Only the UTXO ledger model uses them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub inputs: Vec<OutPoint>,
+    /// Proof that the sender's key authorised the transfer. Required from
+    /// key-hash addresses; only named placeholder accounts spend unsigned.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<Signature>,
+    /// The keyholders' approvals when the sender is a multisig account.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub multisig: Option<MultisigSpend>,
+    /// The scripts satisfying the sender's locking script, when it has one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub script: Option<ScriptSpend>,
+}
+
+/// An ed25519 signature over `Transaction::signing_input`, with the key that made it.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Signature {
+    #[serde(with = "hex")]
+    pub public_key: [u8; 32],
+    #[serde(with = "hex::bytes")]
+    pub signature: Vec<u8>,
 }
 
 impl Transaction {
     pub fn new(sender: String, receiver: String, amount: f64) -> Self {
+        Self::new_at(sender, receiver, amount, Utc::now().timestamp() as u64)
+    }
+
+    /// Creates a transaction stamped with `timestamp` instead of the wall clock.
+    pub fn new_at(sender: String, receiver: String, amount: f64, timestamp: u64) -> Self {
         Transaction {
             sender,
             receiver,
             amount,
-            timestamp: Utc::now().timestamp() as u64,
+            timestamp,
+            outputs: vec![],
+            fee: 0.0,
+            lock_height: None,
+            lock_time: None,
+            htlc: None,
+            wasm: None,
+            token: None,
+            asset: None,
+            anchor: None,
+            stake: None,
+            authority: None,
+            inputs: vec![],
+            signature: None,
+            multisig: None,
+            script: None,
         }
     }
-}
\ No newline at end of file
+
+    /// One transaction paying every output in `payments`, in order. `None`
+    /// when there is nobody to pay.
+    pub fn batch_at(sender: String, payments: Vec<TxOutput>, timestamp: u64) -> Option<Self> {
+        let mut payments = payments.into_iter();
+        let first = payments.next()?;
+        Some(Self::new_at(sender, first.address, first.amount, timestamp).with_outputs(payments.collect()))
+    }
+
+    pub fn with_outputs(mut self, outputs: Vec<TxOutput>) -> Self {
+        self.outputs = outputs;
+        self
+    }
+
+    pub fn with_fee(mut self, fee: f64) -> Self {
+        self.fee = fee;
+        self
+    }
+
+    /// Keeps the transaction out of blocks below `height`.
+    pub fn locked_until_height(mut self, height: u64) -> Self {
+        self.lock_height = Some(height);
+        self
+    }
+
+    /// Keeps the transaction out of blocks stamped before `timestamp`.
+    pub fn locked_until(mut self, timestamp: u64) -> Self {
+        self.lock_time = Some(timestamp);
+        self
+    }
+
+    /// Whether a block at `height` stamped with `timestamp` may include the transaction.
+    pub fn is_final(&self, height: u64, timestamp: u64) -> bool {
+        self.lock_height.is_none_or(|lock| height >= lock) && self.lock_time.is_none_or(|lock| timestamp >= lock)
+    }
+
+    /// Every payment the transaction makes, starting with the one to `receiver`.
+    pub fn payments(&self) -> Vec<TxOutput> {
+        let first = TxOutput {
+            address: self.receiver.clone(),
+            amount: self.amount,
+        };
+        std::iter::once(first).chain(self.outputs.iter().cloned()).collect()
+    }
+
+    /// What the sender gives up: every payment plus the fee. Claims,
+    /// refunds and unbonds pay out of a contract or stake, so their senders
+    /// only pay the fee.
+    pub fn debit(&self) -> f64 {
+        match (&self.htlc, &self.stake) {
+            (Some(Htlc::Claim { .. } | Htlc::Refund { .. }), _) | (_, Some(StakeOp::Unbond)) => self.fee,
+            _ => self.amount + self.outputs.iter().map(|output| output.amount).sum::<f64>() + self.fee,
+        }
+    }
+
+    /// The payments that reach their receivers. A lock's payment is held by
+    /// its contract instead, and a bond's by the sender's stake.
+    pub fn credits(&self) -> Vec<TxOutput> {
+        match (&self.htlc, &self.stake) {
+            (Some(Htlc::Lock { .. }), _) | (_, Some(StakeOp::Bond)) => vec![],
+            _ => self.payments(),
+        }
+    }
+
+    /// How many of the contract, token, asset, anchor, stake and authority
+    /// operations the transaction carries. The chain accepts at most one.
+    pub fn operation_count(&self) -> usize {
+        [
+            self.htlc.is_some(),
+            self.wasm.is_some(),
+            self.token.is_some(),
+            self.asset.is_some(),
+            self.anchor.is_some(),
+            self.stake.is_some(),
+            self.authority.is_some(),
+        ]
+        .into_iter()
+        .filter(|&carried| carried)
+        .count()
+    }
+
+    /// Names the outputs the transaction consumes, for the UTXO ledger model.
+    pub fn spending(mut self, inputs: Vec<OutPoint>) -> Self {
+        self.inputs = inputs;
+        self
+    }
+
+    /// The text a signature covers: everything but the signature itself.
+    pub fn signing_input(&self) -> String {
+        let mut text = format!("{} -> {} : {} on {}", self.sender, self.receiver, self.amount, self.timestamp);
+        if !self.outputs.is_empty() {
+            let outputs: Vec<String> =
+                self.outputs.iter().map(|output| format!("{} : {}", output.address, output.amount)).collect();
+            text.push_str(&format!(" and {}", outputs.join(", ")));
+        }
+        if self.fee != 0.0 {
+            text.push_str(&format!(" with fee {}", self.fee));
+        }
+        if let Some(height) = self.lock_height {
+            text.push_str(&format!(" locked until height {}", height));
+        }
+        if let Some(timestamp) = self.lock_time {
+            text.push_str(&format!(" locked until {}", timestamp));
+        }
+        if let Some(htlc) = &self.htlc {
+            text.push_str(&format!(" {}", htlc));
+        }
+        if let Some(wasm) = &self.wasm {
+            text.push_str(&format!(" {}", wasm));
+        }
+        if let Some(token) = &self.token {
+            text.push_str(&format!(" {}", token));
+        }
+        if let Some(asset) = &self.asset {
+            text.push_str(&format!(" {}", asset));
+        }
+        if let Some(anchor) = &self.anchor {
+            text.push_str(&format!(" {}", anchor));
+        }
+        if let Some(stake) = &self.stake {
+            text.push_str(&format!(" {}", stake));
+        }
+        if let Some(authority) = &self.authority {
+            text.push_str(&format!(" {}", authority));
+        }
+        if !self.inputs.is_empty() {
+            let inputs: Vec<String> = self.inputs.iter().map(OutPoint::to_string).collect();
+            text.push_str(&format!(" spending {}", inputs.join(", ")));
+        }
+        text
+    }
+
+    /// The canonical text committed to by `Block::hash` and `Transaction::hash`.
+    pub fn digest_input(&self) -> String {
+        let mut text = self.signing_input();
+        if let Some(signature) = &self.signature {
+            text.push_str(&format!(" signed by {}", signature));
+        }
+        if let Some(multisig) = &self.multisig {
+            text.push_str(&format!(" {}", multisig));
+        }
+        if let Some(script) = &self.script {
+            text.push_str(&format!(" {}", script));
+        }
+        text
+    }
+
+    /// Identifies the transaction in gossip and mempool bookkeeping.
+    pub fn hash(&self) -> [u8; 32] {
+        Sha256::digest(self.digest_input().as_bytes()).into()
+    }
+
+    /// Identifies what the sender approved, however they authorised it, so
+    /// that the chain can refuse to include it twice.
+    pub fn signing_hash(&self) -> [u8; 32] {
+        Sha256::digest(self.signing_input().as_bytes()).into()
+    }
+
+    pub fn sign(&mut self, key: &SigningKey) {
+        self.signature = Some(Signature::new(key, self.signing_input().as_bytes()));
+    }
+
+    /// Whether the transaction is signed by the key its sender address belongs to.
+    pub fn verify_signature(&self) -> bool {
+        let Some(signature) = &self.signature else {
+            return false;
+        };
+        address::from_public_key(&signature.public_key) == self.sender
+            && signature.verifies(self.signing_input().as_bytes())
+    }
+}
+
+impl Signature {
+    /// Signs `message` with `key`.
+    pub fn new(key: &SigningKey, message: &[u8]) -> Self {
+        Signature {
+            public_key: key.verifying_key().to_bytes(),
+            signature: key.sign(message).to_bytes().to_vec(),
+        }
+    }
+
+    /// Whether this is a valid signature of `message` by `public_key`.
+    pub fn verifies(&self, message: &[u8]) -> bool {
+        let Ok(key) = VerifyingKey::from_bytes(&self.public_key) else {
+            return false;
+        };
+        let Ok(bytes) = <[u8; 64]>::try_from(self.signature.as_slice()) else {
+            return false;
+        };
+        key.verify(message, &ed25519_dalek::Signature::from_bytes(&bytes)).is_ok()
+    }
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", hex::encode(&self.public_key), hex::encode(&self.signature))
+    }
+}
+
+fn is_zero(fee: &f64) -> bool {
+    *fee == 0.0
+}
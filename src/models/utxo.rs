@@ -0,0 +1,155 @@
+//! The unspent-output ledger.
+//!
+//! In the UTXO model a transaction names the earlier outputs it consumes and
+//! creates new ones: one per payment, in `Transaction::credits` order, then,
+//! when the inputs are worth more than the payments and fee, the change back
+//! to the sender. The fee is not paid to anyone. Like the account model,
+//! every address starts with `STARTING_BALANCE`; here it is a virtual
+//! allowance output that exists until its owner spends it.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::models::blockchain::{BlockchainError, STARTING_BALANCE};
+use crate::models::{Transaction, hex};
+
+/// A reference to one output of an earlier transaction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct OutPoint {
+    #[serde(with = "hex")]
+    pub txid: [u8; 32],
+    pub index: u32,
+}
+
+impl OutPoint {
+    /// The output holding `address`'s starting balance.
+    pub fn allowance(address: &str) -> Self {
+        OutPoint {
+            txid: Sha256::digest(format!("allowance for {}", address).as_bytes()).into(),
+            index: 0,
+        }
+    }
+}
+
+impl fmt::Display for OutPoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", hex::encode(&self.txid), self.index)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TxOutput {
+    pub address: String,
+    pub amount: f64,
+}
+
+/// Outputs that can still be spent, and those that already have been.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct UtxoSet {
+    unspent: BTreeMap<OutPoint, TxOutput>,
+    spent: BTreeSet<OutPoint>,
+}
+
+impl UtxoSet {
+    /// The output at `outpoint` if `owner` can still spend it.
+    pub fn get(&self, outpoint: &OutPoint, owner: &str) -> Option<TxOutput> {
+        if self.spent.contains(outpoint) {
+            return None;
+        }
+        if let Some(output) = self.unspent.get(outpoint) {
+            return (output.address == owner).then(|| output.clone());
+        }
+        (*outpoint == OutPoint::allowance(owner)).then(|| TxOutput {
+            address: owner.to_string(),
+            amount: STARTING_BALANCE,
+        })
+    }
+
+    /// Spends the transaction's inputs and records its outputs.
+    pub fn apply(&mut self, transaction: &Transaction) -> Result<(), BlockchainError> {
+        let mut total = 0.0;
+        let mut inputs = BTreeSet::new();
+        for input in &transaction.inputs {
+            if self.spent.contains(input) || !inputs.insert(*input) {
+                return Err(BlockchainError::DoubleSpend(*input));
+            }
+            let output = self
+                .get(input, &transaction.sender)
+                .ok_or(BlockchainError::UnknownInput(*input))?;
+            total += output.amount;
+        }
+        let debit = transaction.debit();
+        if total < debit {
+            return Err(BlockchainError::InsufficientBalance);
+        }
+
+        for input in inputs {
+            self.unspent.remove(&input);
+            self.spent.insert(input);
+        }
+        let txid = transaction.hash();
+        let mut outputs = transaction.credits();
+        if total > debit {
+            outputs.push(TxOutput {
+                address: transaction.sender.clone(),
+                amount: total - debit,
+            });
+        }
+        for (index, output) in outputs.into_iter().enumerate() {
+            self.unspent.insert(OutPoint { txid, index: index as u32 }, output);
+        }
+        Ok(())
+    }
+
+    /// What `address` can spend, in outpoint order, allowance included.
+    pub fn outputs_of(&self, address: &str) -> Vec<(OutPoint, TxOutput)> {
+        let mut outputs: Vec<(OutPoint, TxOutput)> = self
+            .unspent
+            .iter()
+            .filter(|(_, output)| output.address == address)
+            .map(|(outpoint, output)| (*outpoint, output.clone()))
+            .collect();
+        let allowance = OutPoint::allowance(address);
+        if let Some(output) = self.get(&allowance, address) {
+            outputs.push((allowance, output));
+            outputs.sort_by_key(|(outpoint, _)| *outpoint);
+        }
+        outputs
+    }
+
+    pub fn balance(&self, address: &str) -> f64 {
+        self.outputs_of(address).iter().map(|(_, output)| output.amount).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::address;
+
+    #[test]
+    fn test_spending_creates_payment_and_change() {
+        let (alice, bob) = (address::from_name("Alice"), address::from_name("Bob"));
+        let mut utxos = UtxoSet::default();
+        assert_eq!(utxos.balance(&alice), STARTING_BALANCE);
+
+        let pay = Transaction::new_at(alice.clone(), bob.clone(), 30.0, 1).spending(vec![OutPoint::allowance(&alice)]);
+        utxos.apply(&pay).unwrap();
+        assert_eq!(utxos.balance(&alice), 70.0);
+        assert_eq!(utxos.balance(&bob), 130.0);
+        let change = OutPoint { txid: pay.hash(), index: 1 };
+        assert_eq!(utxos.outputs_of(&alice), vec![(change, TxOutput { address: alice.clone(), amount: 70.0 })]);
+
+        // Bob cannot spend Alice's change, and Alice cannot spend the allowance twice.
+        let theft = Transaction::new_at(bob.clone(), bob.clone(), 1.0, 2).spending(vec![change]);
+        assert_eq!(utxos.apply(&theft), Err(BlockchainError::UnknownInput(change)));
+        let again = Transaction::new_at(alice.clone(), bob.clone(), 1.0, 3).spending(vec![OutPoint::allowance(&alice)]);
+        assert_eq!(utxos.apply(&again), Err(BlockchainError::DoubleSpend(OutPoint::allowance(&alice))));
+        let twice = Transaction::new_at(alice.clone(), bob, 1.0, 4).spending(vec![change, change]);
+        assert_eq!(utxos.apply(&twice), Err(BlockchainError::DoubleSpend(change)));
+        assert_eq!(utxos.balance(&alice), 70.0);
+    }
+}
@@ -0,0 +1,382 @@
+//! Hierarchical deterministic wallets.
+//!
+//! A wallet is a BIP-39 mnemonic. Its seed derives ed25519 keypairs with
+//! SLIP-0010, where every step is hardened; the n-th address is the key at
+//! `m/44'/1'/0'/0'/n'`. Wallets are stored in a keystore file encrypted with
+//! AES-256-GCM under a key stretched from a password with PBKDF2.
+
+use std::fmt;
+use std::path::Path;
+
+use aes_gcm::aead::Aead;
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+use bip39::Mnemonic;
+use chrono::Utc;
+use ed25519_dalek::SigningKey;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Sha256, Sha512};
+
+use crate::models::{Blockchain, Transaction, address, hex};
+
+/// Added to a child index to mark it hardened.
+pub const HARDENED: u32 = 0x8000_0000;
+/// `m/44'/1'/0'/0'`, the parent of every address key.
+const ACCOUNT_PATH: [u32; 4] = [44 | HARDENED, 1 | HARDENED, HARDENED, HARDENED];
+/// PBKDF2 rounds used when saving a keystore.
+pub const KEYSTORE_ITERATIONS: u32 = 100_000;
+const KEYSTORE_VERSION: u32 = 1;
+
+#[derive(Debug)]
+pub enum WalletError {
+    InvalidMnemonic(String),
+    InvalidPath(String),
+    UnknownAddress,
+    /// The keystore could not be decrypted, most likely because of a wrong password.
+    WrongPassword,
+    Keystore(String),
+    Io(std::io::Error),
+}
+
+impl fmt::Display for WalletError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WalletError::InvalidMnemonic(e) => write!(f, "invalid mnemonic: {}", e),
+            WalletError::InvalidPath(path) => write!(f, "invalid derivation path {}", path),
+            WalletError::UnknownAddress => write!(f, "address does not belong to this wallet"),
+            WalletError::WrongPassword => write!(f, "wrong password"),
+            WalletError::Keystore(e) => write!(f, "invalid keystore: {}", e),
+            WalletError::Io(e) => write!(f, "could not access keystore: {}", e),
+        }
+    }
+}
+
+/// A private key and the chain code needed to derive its children.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExtendedKey {
+    pub key: [u8; 32],
+    pub chain_code: [u8; 32],
+}
+
+impl ExtendedKey {
+    pub fn master(seed: &[u8]) -> Self {
+        Self::from_hmac(b"ed25519 seed", &[seed])
+    }
+
+    /// Derives a hardened child; ed25519 has no non-hardened derivation, so
+    /// the hardened bit is always set.
+    pub fn child(&self, index: u32) -> Self {
+        let index = (index | HARDENED).to_be_bytes();
+        Self::from_hmac(&self.chain_code, &[&[0], &self.key, &index])
+    }
+
+    pub fn derive(seed: &[u8], path: &[u32]) -> Self {
+        path.iter().fold(Self::master(seed), |key, &index| key.child(index))
+    }
+
+    pub fn signing_key(&self) -> SigningKey {
+        SigningKey::from_bytes(&self.key)
+    }
+
+    fn from_hmac(key: &[u8], data: &[&[u8]]) -> Self {
+        let mut mac = <Hmac<Sha512> as Mac>::new_from_slice(key).expect("HMAC accepts any key length");
+        for part in data {
+            mac.update(part);
+        }
+        let output = mac.finalize().into_bytes();
+        ExtendedKey {
+            key: output[..32].try_into().unwrap(),
+            chain_code: output[32..].try_into().unwrap(),
+        }
+    }
+}
+
+/// Parses a path such as `m/44'/1'/0'`. Every step must be hardened.
+pub fn parse_path(path: &str) -> Result<Vec<u32>, WalletError> {
+    let invalid = || WalletError::InvalidPath(path.to_string());
+    let mut steps = path.split('/');
+    if steps.next() != Some("m") {
+        return Err(invalid());
+    }
+    steps
+        .map(|step| {
+            let index = step.strip_suffix('\'').or_else(|| step.strip_suffix('h')).ok_or_else(invalid)?;
+            let index: u32 = index.parse().map_err(|_| invalid())?;
+            if index >= HARDENED {
+                return Err(invalid());
+            }
+            Ok(index | HARDENED)
+        })
+        .collect()
+}
+
+/// A mnemonic seed and the addresses derived from it so far.
+pub struct Wallet {
+    mnemonic: Mnemonic,
+    passphrase: String,
+    seed: [u8; 64],
+    keys: Vec<SigningKey>,
+}
+
+impl Wallet {
+    /// Creates a wallet from a fresh 24-word mnemonic.
+    pub fn generate() -> Self {
+        let entropy: [u8; 32] = rand::random();
+        let mnemonic = Mnemonic::from_entropy(&entropy).expect("32 bytes is a valid entropy length");
+        Self::with_mnemonic(mnemonic, String::new())
+    }
+
+    /// Restores a wallet. The same phrase and passphrase always derive the same addresses.
+    pub fn from_mnemonic(phrase: &str, passphrase: &str) -> Result<Self, WalletError> {
+        let mnemonic = Mnemonic::parse(phrase).map_err(|e| WalletError::InvalidMnemonic(e.to_string()))?;
+        Ok(Self::with_mnemonic(mnemonic, passphrase.to_string()))
+    }
+
+    fn with_mnemonic(mnemonic: Mnemonic, passphrase: String) -> Self {
+        let seed = mnemonic.to_seed(&passphrase);
+        Wallet {
+            mnemonic,
+            passphrase,
+            seed,
+            keys: Vec::new(),
+        }
+    }
+
+    pub fn mnemonic(&self) -> String {
+        self.mnemonic.to_string()
+    }
+
+    /// The key at any path under this wallet's seed.
+    pub fn derive(&self, path: &str) -> Result<SigningKey, WalletError> {
+        Ok(ExtendedKey::derive(&self.seed, &parse_path(path)?).signing_key())
+    }
+
+    /// Derives the next address key and returns its address.
+    pub fn new_address(&mut self) -> String {
+        let mut path = ACCOUNT_PATH.to_vec();
+        path.push(self.keys.len() as u32 | HARDENED);
+        let key = ExtendedKey::derive(&self.seed, &path).signing_key();
+        self.keys.push(key);
+        self.addresses().pop().unwrap()
+    }
+
+    pub fn addresses(&self) -> Vec<String> {
+        self.keys
+            .iter()
+            .map(|key| address::from_public_key(&key.verifying_key().to_bytes()))
+            .collect()
+    }
+
+    pub fn signing_key(&self, address: &str) -> Option<&SigningKey> {
+        self.keys
+            .iter()
+            .find(|key| address::from_public_key(&key.verifying_key().to_bytes()) == address)
+    }
+
+    /// Builds a transaction from one of this wallet's addresses, signed with its key.
+    pub fn build_transaction(&self, sender: &str, receiver: &str, amount: f64) -> Result<Transaction, WalletError> {
+        self.build_transaction_at(sender, receiver, amount, Utc::now().timestamp() as u64)
+    }
+
+    pub fn build_transaction_at(
+        &self,
+        sender: &str,
+        receiver: &str,
+        amount: f64,
+        timestamp: u64,
+    ) -> Result<Transaction, WalletError> {
+        let key = self.signing_key(sender).ok_or(WalletError::UnknownAddress)?;
+        let mut transaction = Transaction::new_at(sender.to_string(), receiver.to_string(), amount, timestamp);
+        transaction.sign(key);
+        Ok(transaction)
+    }
+
+    /// What the wallet's addresses can still spend: their confirmed balances
+    /// less whatever they already have waiting in `blockchain`'s mempool.
+    pub fn spendable_balance(&self, blockchain: &Blockchain) -> f64 {
+        let addresses = self.addresses();
+        let confirmed: f64 = addresses.iter().map(|address| blockchain.get_balance(address)).sum();
+        let pending: f64 = blockchain
+            .pending_transactions
+            .iter()
+            .filter(|transaction| addresses.contains(&transaction.sender))
+            .map(Transaction::debit)
+            .sum();
+        confirmed - pending
+    }
+
+    pub fn encrypt(&self, password: &str, iterations: u32) -> Keystore {
+        let contents = KeystoreContents {
+            mnemonic: self.mnemonic(),
+            passphrase: self.passphrase.clone(),
+            addresses: self.keys.len(),
+        };
+        let plaintext = serde_json::to_vec(&contents).expect("keystore contents always serialize");
+        let salt: [u8; 16] = rand::random();
+        let nonce: [u8; 12] = rand::random();
+        let ciphertext = cipher(password, &salt, iterations)
+            .encrypt(Nonce::from_slice(&nonce), plaintext.as_slice())
+            .expect("encryption cannot fail for in-memory data");
+        Keystore {
+            version: KEYSTORE_VERSION,
+            iterations,
+            salt: salt.to_vec(),
+            nonce: nonce.to_vec(),
+            ciphertext,
+        }
+    }
+
+    pub fn decrypt(keystore: &Keystore, password: &str) -> Result<Self, WalletError> {
+        if keystore.version != KEYSTORE_VERSION {
+            return Err(WalletError::Keystore(format!("unsupported version {}", keystore.version)));
+        }
+        if keystore.nonce.len() != 12 {
+            return Err(WalletError::Keystore("nonce must be 12 bytes".to_string()));
+        }
+        let plaintext = cipher(password, &keystore.salt, keystore.iterations)
+            .decrypt(Nonce::from_slice(&keystore.nonce), keystore.ciphertext.as_slice())
+            .map_err(|_| WalletError::WrongPassword)?;
+        let contents: KeystoreContents =
+            serde_json::from_slice(&plaintext).map_err(|e| WalletError::Keystore(e.to_string()))?;
+        let mut wallet = Self::from_mnemonic(&contents.mnemonic, &contents.passphrase)?;
+        for _ in 0..contents.addresses {
+            wallet.new_address();
+        }
+        Ok(wallet)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>, password: &str) -> Result<(), WalletError> {
+        let keystore = serde_json::to_string_pretty(&self.encrypt(password, KEYSTORE_ITERATIONS))
+            .expect("keystores always serialize");
+        std::fs::write(path, keystore).map_err(WalletError::Io)
+    }
+
+    pub fn load(path: impl AsRef<Path>, password: &str) -> Result<Self, WalletError> {
+        let text = std::fs::read_to_string(path).map_err(WalletError::Io)?;
+        let keystore: Keystore = serde_json::from_str(&text).map_err(|e| WalletError::Keystore(e.to_string()))?;
+        Self::decrypt(&keystore, password)
+    }
+}
+
+/// The on-disk form of a wallet.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Keystore {
+    pub version: u32,
+    /// PBKDF2-HMAC-SHA256 rounds that stretch the password into the AES key.
+    pub iterations: u32,
+    #[serde(with = "hex::bytes")]
+    pub salt: Vec<u8>,
+    #[serde(with = "hex::bytes")]
+    pub nonce: Vec<u8>,
+    #[serde(with = "hex::bytes")]
+    pub ciphertext: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct KeystoreContents {
+    mnemonic: String,
+    passphrase: String,
+    addresses: usize,
+}
+
+fn cipher(password: &str, salt: &[u8], iterations: u32) -> Aes256Gcm {
+    let mut key = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, iterations, &mut key);
+    Aes256Gcm::new_from_slice(&key).expect("key is 32 bytes")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Block;
+    use crate::models::blockchain::{BlockchainError, GENESIS_TIMESTAMP};
+
+    const PHRASE: &str = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+
+    #[test]
+    fn test_slip10_vectors() {
+        let seed = hex::decode("000102030405060708090a0b0c0d0e0f").unwrap();
+        let master = ExtendedKey::master(&seed);
+        assert_eq!(
+            hex::encode(&master.key),
+            "2b4be7f19ee27bbf30c667b642d5f4aa69fd169872f8fc3059c08ebae2eb19e7"
+        );
+        assert_eq!(
+            hex::encode(&master.chain_code),
+            "90046a93de5380a72b5e45010748567d5ea02bbf6522f979e05c0d8d8ca9fffb"
+        );
+        let child = ExtendedKey::derive(&seed, &parse_path("m/0'").unwrap());
+        assert_eq!(
+            hex::encode(&child.key),
+            "68e0fe46dfb67e368c75379acec591dad19df3cde26e63b93a8e704f1dade7a3"
+        );
+        assert!(parse_path("m/0").is_err());
+        assert!(parse_path("44'/0'").is_err());
+    }
+
+    #[test]
+    fn test_same_mnemonic_derives_same_addresses() {
+        let mut first = Wallet::from_mnemonic(PHRASE, "").unwrap();
+        let mut second = Wallet::from_mnemonic(PHRASE, "").unwrap();
+        let mut other = Wallet::from_mnemonic(PHRASE, "extra words").unwrap();
+        assert_eq!(first.new_address(), second.new_address());
+        assert_ne!(first.new_address(), other.new_address());
+        assert_eq!(first.addresses().len(), 2);
+        assert_eq!(first.derive("m/44'/1'/0'/0'/1'").unwrap(), *first.signing_key(&first.addresses()[1]).unwrap());
+        assert!(Wallet::from_mnemonic("abandon abandon", "").is_err());
+    }
+
+    #[test]
+    fn test_keystore_round_trip() {
+        let mut wallet = Wallet::generate();
+        wallet.new_address();
+        wallet.new_address();
+        let keystore = wallet.encrypt("hunter2", 1_000);
+        let restored = Wallet::decrypt(&keystore, "hunter2").unwrap();
+        assert_eq!(restored.mnemonic(), wallet.mnemonic());
+        assert_eq!(restored.addresses(), wallet.addresses());
+        assert!(matches!(Wallet::decrypt(&keystore, "hunter3"), Err(WalletError::WrongPassword)));
+        assert!(!serde_json::to_string(&keystore).unwrap().contains("abandon"));
+    }
+
+    #[test]
+    fn test_signed_transactions_and_spendable_balance() {
+        let mut wallet = Wallet::from_mnemonic(PHRASE, "").unwrap();
+        let first = wallet.new_address();
+        let second = wallet.new_address();
+        let mut blockchain = Blockchain::new(1);
+        assert_eq!(wallet.spendable_balance(&blockchain), 200.0);
+
+        let transaction = wallet.build_transaction(&first, &address::from_name("Bob"), 30.0).unwrap();
+        assert!(transaction.verify_signature());
+        blockchain.add_transaction(transaction).unwrap();
+        assert_eq!(wallet.spendable_balance(&blockchain), 170.0);
+        blockchain.mine_block().unwrap();
+        assert_eq!(wallet.spendable_balance(&blockchain), 170.0);
+        assert!(blockchain.validate_chain().is_ok());
+
+        // Tampering with a signed transaction, or claiming someone else's address, is caught.
+        let mut tampered = wallet.build_transaction(&second, &address::from_name("Bob"), 5.0).unwrap();
+        tampered.amount = 50.0;
+        assert_eq!(blockchain.add_transaction(tampered), Err(BlockchainError::InvalidSignature));
+        let mut stolen = wallet.build_transaction(&second, &address::from_name("Bob"), 5.0).unwrap();
+        stolen.sender = first.clone();
+        assert!(!stolen.verify_signature());
+        assert!(matches!(wallet.build_transaction("Alice", "Bob", 1.0), Err(WalletError::UnknownAddress)));
+    }
+
+    #[test]
+    fn test_unsigned_spend_from_a_derived_address_is_rejected() {
+        let mut wallet = Wallet::from_mnemonic(PHRASE, "").unwrap();
+        let derived = wallet.new_address();
+        let mut blockchain = Blockchain::new(0);
+        let unsigned = Transaction::new_at(derived.clone(), address::from_name("Mallory"), 90.0, 1);
+        assert_eq!(blockchain.add_transaction(unsigned.clone()), Err(BlockchainError::InvalidSignature));
+
+        // Nor can it reach the chain inside someone else's block.
+        let mut block = Block::new_at(1, blockchain.blocks[0].hash, vec![unsigned], GENESIS_TIMESTAMP + 100);
+        block.hash = block.hash();
+        assert_eq!(blockchain.append_block(block), Err(BlockchainError::InvalidSignature));
+        assert_eq!(blockchain.get_balance(&derived), 100.0);
+    }
+}
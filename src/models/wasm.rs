@@ -0,0 +1,528 @@
+//! WebAssembly contract accounts.
+//!
+//! A deploy transaction stores a WASM module at a new contract address, and
+//! call transactions run one of its exported functions in a sandboxed
+//! interpreter. A function takes no arguments and returns nothing: it reads
+//! its input, caller and the block time through host functions, and keeps
+//! what it needs in the contract's key-value storage. Contracts cannot pay
+//! anything out, so no coins may be sent to them.
+//!
+//! Every instruction and host call burns gas. A call names its gas limit and
+//! pays for all of it up front in its fee, used or not, so balances follow
+//! from the transactions alone. The mempool refuses a call that runs out of
+//! gas or traps. One that only fails once mined is still included, so that
+//! its sender pays for the gas it burned, but its storage writes are
+//! discarded.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use wasmi::core::TrapCode;
+use wasmi::{Caller, Config, Engine, Extern, Linker, Module, Store, StoreLimits, StoreLimitsBuilder};
+
+use crate::models::{Transaction, address, hex};
+
+/// How much gas one coin of fee pays for.
+pub const GAS_PER_COIN: f64 = 1_000_000.0;
+/// The most gas a single call may use.
+pub const MAX_GAS: u64 = 10_000_000;
+/// Gas charged per byte of code a deploy stores.
+pub const DEPLOY_GAS_PER_BYTE: u64 = 200;
+/// Gas charged for each host function call, on top of the bytes it moves.
+pub const HOST_CALL_GAS: u64 = 100;
+/// Gas charged per byte read from or written to storage, input or memory.
+pub const HOST_BYTE_GAS: u64 = 10;
+/// The largest module that may be deployed, in bytes.
+pub const MAX_CODE_SIZE: usize = 64 * 1024;
+/// The most linear memory a contract may grow to, in bytes.
+pub const MAX_MEMORY: usize = 1024 * 1024;
+/// The largest storage key or value, in bytes.
+pub const MAX_STORAGE_ENTRY: usize = 1024;
+
+/// What a transaction does with a WASM contract.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Wasm {
+    /// Stores `code` at the transaction's receiver, which must be `contract_address` of it.
+    Deploy {
+        #[serde(with = "hex::bytes")]
+        code: Vec<u8>,
+    },
+    /// Runs the receiver's exported `function` on `input`.
+    Call {
+        function: String,
+        #[serde(default, with = "hex::bytes")]
+        input: Vec<u8>,
+        gas_limit: u64,
+    },
+}
+
+impl Wasm {
+    /// The gas the transaction's fee must cover.
+    pub fn gas(&self) -> u64 {
+        match self {
+            Wasm::Deploy { code } => code.len() as u64 * DEPLOY_GAS_PER_BYTE,
+            Wasm::Call { gas_limit, .. } => *gas_limit,
+        }
+    }
+}
+
+impl fmt::Display for Wasm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Wasm::Deploy { code } => write!(f, "deploying {}", hex::encode(&Sha256::digest(code))),
+            Wasm::Call {
+                function,
+                input,
+                gas_limit,
+            } => write!(f, "calling {}({}) with gas {}", function, hex::encode(input), gas_limit),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WasmError {
+    /// Contract accounts have no key, so nothing may be sent from them.
+    SentByContract,
+    /// A deploy's receiver is not the address derived from it.
+    WrongAddress,
+    AlreadyDeployed,
+    CodeTooLarge,
+    /// The module does not compile, or imports something the host does not provide.
+    InvalidModule(String),
+    /// No contract is deployed at the call's receiver.
+    UnknownContract,
+    /// Coins are sent to a contract, which could never pay them out.
+    ValueSent,
+    /// The contract exports no function of that name taking and returning nothing.
+    UnknownFunction(String),
+    GasLimitTooHigh,
+    /// The fee does not pay for the gas the transaction may use.
+    FeeBelowGas { required: f64 },
+    OutOfGas,
+    /// The contract trapped or misused a host function.
+    Trap(String),
+}
+
+impl fmt::Display for WasmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WasmError::SentByContract => write!(f, "contract accounts cannot send transactions"),
+            WasmError::WrongAddress => write!(f, "deploy is not addressed to the contract it creates"),
+            WasmError::AlreadyDeployed => write!(f, "a contract is already deployed at that address"),
+            WasmError::CodeTooLarge => write!(f, "contract code is larger than {} bytes", MAX_CODE_SIZE),
+            WasmError::InvalidModule(e) => write!(f, "invalid contract module: {}", e),
+            WasmError::UnknownContract => write!(f, "no contract is deployed at that address"),
+            WasmError::ValueSent => write!(f, "contracts cannot pay out, so nothing may be sent to them"),
+            WasmError::UnknownFunction(name) => write!(f, "contract exports no function {:?}", name),
+            WasmError::GasLimitTooHigh => write!(f, "a call may use at most {} gas", MAX_GAS),
+            WasmError::FeeBelowGas { required } => write!(f, "fee must be at least {} to pay for gas", required),
+            WasmError::OutOfGas => write!(f, "contract ran out of gas"),
+            WasmError::Trap(e) => write!(f, "contract trapped: {}", e),
+        }
+    }
+}
+
+/// A deployed contract and what it has stored.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub deployer: String,
+    pub code: Vec<u8>,
+    pub storage: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+/// What a deploy or call changes, held back until the rest of its transaction is accepted.
+#[derive(Debug)]
+pub struct Changes {
+    address: String,
+    program: Program,
+}
+
+/// Every contract deployed on a chain, by address.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Programs {
+    programs: BTreeMap<String, Program>,
+}
+
+impl Programs {
+    pub fn get(&self, address: &str) -> Option<&Program> {
+        self.programs.get(address)
+    }
+
+    /// Deploys or runs the contract the transaction refers to, and returns
+    /// what that would change without changing it. `timestamp` is that of
+    /// the block including the transaction, or `None` while it is pending, in
+    /// which case contracts see the transaction's own timestamp. A call that
+    /// fails in a block changes nothing, but is not refused.
+    pub fn run(&self, transaction: &Transaction, timestamp: Option<u64>) -> Result<Option<Changes>, WasmError> {
+        let to_contract = |address: &str| matches!(address::decode(address), Ok((address::CONTRACT_VERSION, _)));
+        if transaction.payments().iter().any(|payment| payment.amount != 0.0 && to_contract(&payment.address)) {
+            return Err(WasmError::ValueSent);
+        }
+        let Some(wasm) = &transaction.wasm else {
+            return Ok(None);
+        };
+        let required = gas_fee(wasm.gas());
+        if transaction.fee < required {
+            return Err(WasmError::FeeBelowGas { required });
+        }
+        let address = transaction.receiver.clone();
+        let program = match wasm {
+            Wasm::Deploy { code } => {
+                if address != contract_address(&transaction.sender, code, transaction.timestamp) {
+                    return Err(WasmError::WrongAddress);
+                }
+                if self.programs.contains_key(&address) {
+                    return Err(WasmError::AlreadyDeployed);
+                }
+                if code.len() > MAX_CODE_SIZE {
+                    return Err(WasmError::CodeTooLarge);
+                }
+                compile(&engine(), code)?;
+                Program {
+                    deployer: transaction.sender.clone(),
+                    code: code.clone(),
+                    storage: BTreeMap::new(),
+                }
+            }
+            Wasm::Call {
+                function,
+                input,
+                gas_limit,
+            } => {
+                if *gas_limit > MAX_GAS {
+                    return Err(WasmError::GasLimitTooHigh);
+                }
+                let program = self.programs.get(&address).ok_or(WasmError::UnknownContract)?;
+                let host = Host {
+                    caller: transaction.sender.clone(),
+                    timestamp: timestamp.unwrap_or(transaction.timestamp),
+                    input: input.clone(),
+                    storage: program.storage.clone(),
+                    limits: StoreLimitsBuilder::new().memory_size(MAX_MEMORY).instances(1).build(),
+                };
+                let storage = match execute(&program.code, function, *gas_limit, host) {
+                    Ok(storage) => storage,
+                    Err(WasmError::OutOfGas | WasmError::Trap(_)) if timestamp.is_some() => return Ok(None),
+                    Err(e) => return Err(e),
+                };
+                Program {
+                    storage,
+                    ..program.clone()
+                }
+            }
+        };
+        Ok(Some(Changes { address, program }))
+    }
+
+    pub fn commit(&mut self, changes: Option<Changes>) {
+        if let Some(Changes { address, program }) = changes {
+            self.programs.insert(address, program);
+        }
+    }
+}
+
+/// The address `sender` deploys `code` to with a transaction stamped `timestamp`.
+pub fn contract_address(sender: &str, code: &[u8], timestamp: u64) -> String {
+    let text = format!("{} deploys {} on {}", sender, hex::encode(&Sha256::digest(code)), timestamp);
+    let hash = Sha256::digest(text.as_bytes());
+    address::encode(address::CONTRACT_VERSION, hash[..20].try_into().unwrap())
+}
+
+/// A transaction deploying `code` to `contract_address`, with the fee its size requires.
+pub fn deploy_at(sender: String, code: Vec<u8>, timestamp: u64) -> Transaction {
+    let receiver = contract_address(&sender, &code, timestamp);
+    with_wasm(Transaction::new_at(sender, receiver, 0.0, timestamp), Wasm::Deploy { code })
+}
+
+/// A transaction running the `function` of `contract`, with the fee `gas_limit` requires.
+pub fn call_at(
+    sender: String,
+    contract: String,
+    function: &str,
+    input: Vec<u8>,
+    gas_limit: u64,
+    timestamp: u64,
+) -> Transaction {
+    let call = Wasm::Call {
+        function: function.to_string(),
+        input,
+        gas_limit,
+    };
+    with_wasm(Transaction::new_at(sender, contract, 0.0, timestamp), call)
+}
+
+/// The fee that pays for `gas`.
+pub fn gas_fee(gas: u64) -> f64 {
+    gas as f64 / GAS_PER_COIN
+}
+
+fn with_wasm(transaction: Transaction, wasm: Wasm) -> Transaction {
+    let mut transaction = transaction.with_fee(gas_fee(wasm.gas()));
+    transaction.wasm = Some(wasm);
+    transaction
+}
+
+/// What a running contract can see and change.
+struct Host {
+    caller: String,
+    timestamp: u64,
+    input: Vec<u8>,
+    storage: BTreeMap<Vec<u8>, Vec<u8>>,
+    limits: StoreLimits,
+}
+
+fn engine() -> Engine {
+    let mut config = Config::default();
+    config.consume_fuel(true);
+    Engine::new(&config)
+}
+
+fn compile(engine: &Engine, code: &[u8]) -> Result<Module, WasmError> {
+    let module = Module::new(engine, code).map_err(|e| WasmError::InvalidModule(e.to_string()))?;
+    for import in module.imports() {
+        if import.module() != "env" || !HOST_FUNCTIONS.contains(&import.name()) {
+            return Err(WasmError::InvalidModule(format!("unknown import {}.{}", import.module(), import.name())));
+        }
+    }
+    Ok(module)
+}
+
+/// The functions contracts may import from the `env` module.
+const HOST_FUNCTIONS: [&str; 5] = ["input", "caller", "timestamp", "storage_read", "storage_write"];
+
+/// Runs `function` with `gas_limit` gas and returns the storage it leaves behind.
+fn execute(
+    code: &[u8],
+    function: &str,
+    gas_limit: u64,
+    host: Host,
+) -> Result<BTreeMap<Vec<u8>, Vec<u8>>, WasmError> {
+    let engine = engine();
+    let module = compile(&engine, code)?;
+    let mut store = Store::new(&engine, host);
+    store.limiter(|host| &mut host.limits);
+    store.set_fuel(gas_limit).expect("fuel metering is enabled");
+
+    let mut linker = <Linker<Host>>::new(&engine);
+    define_host_functions(&mut linker).expect("host functions have distinct names");
+    let instance = linker
+        .instantiate(&mut store, &module)
+        .and_then(|instance| instance.start(&mut store))
+        .map_err(trap)?;
+    let entry = instance
+        .get_typed_func::<(), ()>(&store, function)
+        .map_err(|_| WasmError::UnknownFunction(function.to_string()))?;
+    entry.call(&mut store, ()).map_err(trap)?;
+    Ok(store.into_data().storage)
+}
+
+fn trap(error: impl Into<wasmi::Error>) -> WasmError {
+    let error = error.into();
+    if error.as_trap_code() == Some(TrapCode::OutOfFuel) {
+        WasmError::OutOfGas
+    } else {
+        WasmError::Trap(error.to_string())
+    }
+}
+
+/// Byte-oriented host functions copy into a buffer of `capacity` bytes at
+/// `ptr` and return the full length, so a contract can retry with a larger
+/// buffer. `storage_read` returns -1 for a missing key.
+fn define_host_functions(linker: &mut Linker<Host>) -> Result<(), wasmi::Error> {
+    linker.func_wrap("env", "input", |mut caller: Caller<'_, Host>, ptr: u32, capacity: u32| {
+        let input = caller.data().input.clone();
+        copy_out(&mut caller, &input, ptr, capacity)
+    })?;
+    linker.func_wrap("env", "caller", |mut caller: Caller<'_, Host>, ptr: u32, capacity: u32| {
+        let address = caller.data().caller.clone().into_bytes();
+        copy_out(&mut caller, &address, ptr, capacity)
+    })?;
+    linker.func_wrap("env", "timestamp", |mut caller: Caller<'_, Host>| {
+        charge(&mut caller, 0)?;
+        Ok(caller.data().timestamp)
+    })?;
+    linker.func_wrap(
+        "env",
+        "storage_read",
+        |mut caller: Caller<'_, Host>, key_ptr: u32, key_len: u32, ptr: u32, capacity: u32| {
+            let key = copy_in(&mut caller, key_ptr, key_len)?;
+            match caller.data().storage.get(&key).cloned() {
+                Some(value) => copy_out(&mut caller, &value, ptr, capacity).map(i64::from),
+                None => Ok(-1),
+            }
+        },
+    )?;
+    linker.func_wrap(
+        "env",
+        "storage_write",
+        |mut caller: Caller<'_, Host>, key_ptr: u32, key_len: u32, value_ptr: u32, value_len: u32| {
+            let key = copy_in(&mut caller, key_ptr, key_len)?;
+            let value = copy_in(&mut caller, value_ptr, value_len)?;
+            caller.data_mut().storage.insert(key, value);
+            Ok(())
+        },
+    )?;
+    Ok(())
+}
+
+/// Burns the gas for a host call moving `bytes` bytes.
+fn charge(caller: &mut Caller<'_, Host>, bytes: usize) -> Result<(), wasmi::Error> {
+    let cost = HOST_CALL_GAS + bytes as u64 * HOST_BYTE_GAS;
+    let fuel = caller.get_fuel().expect("fuel metering is enabled");
+    if fuel < cost {
+        caller.set_fuel(0).expect("fuel metering is enabled");
+        return Err(TrapCode::OutOfFuel.into());
+    }
+    caller.set_fuel(fuel - cost).expect("fuel metering is enabled");
+    Ok(())
+}
+
+fn memory(caller: &Caller<'_, Host>) -> Result<wasmi::Memory, wasmi::Error> {
+    caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| wasmi::Error::new("contract exports no memory"))
+}
+
+fn copy_in(caller: &mut Caller<'_, Host>, ptr: u32, len: u32) -> Result<Vec<u8>, wasmi::Error> {
+    if len as usize > MAX_STORAGE_ENTRY {
+        return Err(wasmi::Error::new(format!("keys and values are at most {} bytes", MAX_STORAGE_ENTRY)));
+    }
+    charge(caller, len as usize)?;
+    let mut bytes = vec![0; len as usize];
+    memory(caller)?.read(&*caller, ptr as usize, &mut bytes).map_err(wasmi::Error::from)?;
+    Ok(bytes)
+}
+
+fn copy_out(caller: &mut Caller<'_, Host>, bytes: &[u8], ptr: u32, capacity: u32) -> Result<u32, wasmi::Error> {
+    let copied = bytes.len().min(capacity as usize);
+    charge(caller, copied)?;
+    memory(caller)?.write(&mut *caller, ptr as usize, &bytes[..copied]).map_err(wasmi::Error::from)?;
+    Ok(bytes.len() as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Blockchain;
+    use crate::models::blockchain::{BlockchainError, GENESIS_TIMESTAMP};
+
+    const COUNTER: &str = r#"
+        (module
+          (import "env" "storage_read" (func $read (param i32 i32 i32 i32) (result i64)))
+          (import "env" "storage_write" (func $write (param i32 i32 i32 i32)))
+          (import "env" "caller" (func $caller (param i32 i32) (result i32)))
+          (import "env" "timestamp" (func $timestamp (result i64)))
+          (memory (export "memory") 1)
+          (data (i32.const 0) "count")
+          (data (i32.const 8) "last")
+          (func $increment (export "increment")
+            (drop (call $read (i32.const 0) (i32.const 5) (i32.const 16) (i32.const 8)))
+            (i64.store (i32.const 16) (i64.add (i64.load (i32.const 16)) (i64.const 1)))
+            (call $write (i32.const 0) (i32.const 5) (i32.const 16) (i32.const 8))
+            (call $write (i32.const 8) (i32.const 4) (i32.const 32)
+              (call $caller (i32.const 32) (i32.const 64))))
+          (func (export "increment_until_150")
+            (call $increment)
+            (if (i64.gt_u (call $timestamp) (i64.const 150)) (then unreachable)))
+          (func (export "spin") (loop $forever (br $forever)))
+          (func (export "fail") unreachable))
+    "#;
+
+    fn count(bc: &Blockchain, contract: &str) -> Option<u64> {
+        let value = bc.programs.get(contract)?.storage.get(b"count".as_slice())?;
+        Some(u64::from_le_bytes(value.as_slice().try_into().unwrap()))
+    }
+
+    #[test]
+    fn test_calls_update_storage_and_pay_for_gas() {
+        let (alice, bob) = (address::from_name("Alice"), address::from_name("Bob"));
+        let mut bc = Blockchain::new(0);
+        let deploy = deploy_at(alice.clone(), wat::parse_str(COUNTER).unwrap(), 1);
+        let contract = deploy.receiver.clone();
+        bc.add_transaction(deploy.clone()).unwrap();
+        bc.mine_block_at(GENESIS_TIMESTAMP + 100).unwrap();
+        assert_eq!(bc.programs.get(&contract).unwrap().deployer, alice);
+        assert_eq!(count(&bc, &contract), None);
+
+        let first = call_at(alice.clone(), contract.clone(), "increment", vec![], 100_000, 2);
+        let second = call_at(bob.clone(), contract.clone(), "increment", vec![], 100_000, 3);
+        bc.add_transaction(first.clone()).unwrap();
+        bc.add_transaction(second.clone()).unwrap();
+        bc.mine_block_at(GENESIS_TIMESTAMP + 200).unwrap();
+        assert_eq!(count(&bc, &contract), Some(2));
+        let last = bc.programs.get(&contract).unwrap().storage.get(b"last".as_slice()).unwrap();
+        assert_eq!(last, bob.as_bytes());
+        assert_eq!(bc.get_balance(&contract), 100.0);
+        assert_eq!(bc.get_balance(&alice), 100.0 - deploy.debit() - first.debit());
+        assert_eq!(first.fee, 0.1);
+
+        assert!(bc.validate_chain().is_ok());
+        let mut replica = Blockchain::new(0);
+        assert!(replica.replace_chain(bc.blocks.clone()));
+        assert_eq!(replica.programs, bc.programs);
+    }
+
+    #[test]
+    fn test_failed_calls_are_refused() {
+        let alice = address::from_name("Alice");
+        let mut bc = Blockchain::new(0);
+        let deploy = deploy_at(alice.clone(), wat::parse_str(COUNTER).unwrap(), 1);
+        let contract = deploy.receiver.clone();
+        bc.add_transaction(deploy).unwrap();
+        bc.mine_block_at(GENESIS_TIMESTAMP + 100).unwrap();
+
+        let call = |function: &str, gas: u64| call_at(alice.clone(), contract.clone(), function, vec![], gas, 2);
+        let refused = |transaction: Transaction| match bc.clone().add_transaction(transaction) {
+            Err(BlockchainError::Wasm(e)) => e,
+            other => panic!("expected a contract error, got {:?}", other),
+        };
+        assert_eq!(refused(call("spin", 100_000)), WasmError::OutOfGas);
+        assert!(matches!(refused(call("fail", 100_000)), WasmError::Trap(_)));
+        assert_eq!(refused(call("increment", 100)), WasmError::OutOfGas);
+        assert_eq!(refused(call("missing", 100_000)), WasmError::UnknownFunction("missing".to_string()));
+        assert_eq!(refused(call("increment", MAX_GAS + 1)), WasmError::GasLimitTooHigh);
+        assert_eq!(refused(call("increment", 100_000).with_fee(0.01)), WasmError::FeeBelowGas { required: 0.1 });
+        let nowhere = call_at(alice.clone(), alice.clone(), "increment", vec![], 100_000, 2);
+        assert_eq!(refused(nowhere), WasmError::UnknownContract);
+        assert_eq!(refused(Transaction::new_at(contract.clone(), alice.clone(), 1.0, 2)), WasmError::SentByContract);
+        let mut paying = call("increment", 100_000);
+        paying.amount = 5.0;
+        assert_eq!(refused(paying), WasmError::ValueSent);
+        assert_eq!(refused(Transaction::new_at(alice.clone(), contract.clone(), 5.0, 2)), WasmError::ValueSent);
+
+        assert!(matches!(refused(deploy_at(alice.clone(), b"not wasm".to_vec(), 3)), WasmError::InvalidModule(_)));
+        let importer = wat::parse_str(r#"(module (import "env" "clock" (func)))"#).unwrap();
+        assert!(matches!(refused(deploy_at(alice.clone(), importer, 3)), WasmError::InvalidModule(_)));
+        let mut misaddressed = deploy_at(alice.clone(), wat::parse_str("(module)").unwrap(), 3);
+        misaddressed.receiver = contract.clone();
+        assert_eq!(refused(misaddressed), WasmError::WrongAddress);
+        assert_eq!(count(&bc, &contract), None);
+    }
+
+    #[test]
+    fn test_calls_failing_once_mined_pay_for_gas_and_change_nothing() {
+        let alice = address::from_name("Alice");
+        let mut bc = Blockchain::new(0);
+        let deploy = deploy_at(alice.clone(), wat::parse_str(COUNTER).unwrap(), 1);
+        let contract = deploy.receiver.clone();
+        bc.add_transaction(deploy.clone()).unwrap();
+        bc.mine_block_at(GENESIS_TIMESTAMP + 100).unwrap();
+
+        // Pending, the call sees its own timestamp and succeeds; mined at 200 it traps.
+        let late = call_at(alice.clone(), contract.clone(), "increment_until_150", vec![], 100_000, 2);
+        bc.add_transaction(late.clone()).unwrap();
+        let block = bc.mine_block_at(GENESIS_TIMESTAMP + 200).unwrap();
+        assert_eq!((block.transactions.len(), &block.transactions[0]), (1, &late));
+        assert!(bc.pending_transactions.is_empty());
+        assert_eq!(count(&bc, &contract), None);
+        assert_eq!(bc.get_balance(&alice), 100.0 - deploy.fee - late.fee);
+
+        let mut replica = Blockchain::new(0);
+        assert!(replica.replace_chain(bc.blocks.clone()));
+        assert_eq!(replica.programs, bc.programs);
+    }
+}
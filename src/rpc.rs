@@ -0,0 +1,432 @@
+//! A JSON-RPC 2.0 API for a running node, served over plain HTTP.
+//!
+//! Every request is a `POST` whose body is a JSON-RPC request object or a
+//! batch of them. Parameters are passed by name:
+//!
+//! | method                   | params                                         | result                        |
+//! |--------------------------|------------------------------------------------|-------------------------------|
+//! | `submit_transaction`     | `sender`, `receiver`, `amount`, `timestamp`?   | `{"hash"}`                    |
+//! | `submit_raw_transaction` | `transaction`, signed by its sender            | `{"hash"}`                    |
+//! | `get_balance`            | `address`                                      | `{"address", "balance"}`      |
+//! | `get_block`              | `height` or `hash`                             | the block                     |
+//! | `get_chain_tip`          |                                                | `{"height", "hash"}`          |
+//! | `get_chain`              |                                                | every block from genesis      |
+//! | `submit_chain`           | `blocks`                                       | `{"adopted"}`                 |
+//! | `get_anchor`             | `hash` of an anchored document                 | where it was first anchored   |
+//! | `get_mempool`            |                                                | pending transactions          |
+//! | `get_peers`              |                                                | connected peer ids            |
+//! | `start_mining`           |                                                | `{"mining": true}`            |
+//! | `stop_mining`            |                                                | `{"mining": false}`           |
+//!
+//! `submit_transaction` also takes an optional `fee`, `outputs`, a list of
+//! further `{"address", "amount"}` payments checked together with the first,
+//! and `lock_height` or `lock_time` to keep it out of blocks until then.
+//! It builds an unsigned transaction, which only named placeholder accounts
+//! may send; anything else goes through `submit_raw_transaction`.
+
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+use crate::models::actor::NodeHandle;
+use crate::models::utxo::TxOutput;
+use crate::models::{Block, Transaction, hex};
+
+/// Where a node serves its API unless told otherwise.
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:8545";
+
+const MAX_HEADER_BYTES: usize = 8 * 1024;
+const MAX_BODY_BYTES: usize = 4 * 1024 * 1024;
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+/// The node refused the request, e.g. a transaction with insufficient balance.
+pub const NODE_ERROR: i64 = -32000;
+pub const NOT_FOUND: i64 = -32001;
+
+/// A JSON-RPC error object.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        RpcError {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (code {})", self.message, self.code)
+    }
+}
+
+/// Serves the API for one node until dropped.
+pub struct RpcServer {
+    address: SocketAddr,
+    task: JoinHandle<()>,
+}
+
+impl RpcServer {
+    /// Listens on `address`; pass port 0 to pick a free port.
+    pub async fn bind(address: &str, node: NodeHandle) -> io::Result<Self> {
+        let listener = TcpListener::bind(address).await?;
+        let address = listener.local_addr()?;
+        let task = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve_connection(stream, node.clone()));
+            }
+        });
+        Ok(RpcServer { address, task })
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+}
+
+impl Drop for RpcServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn serve_connection(mut stream: TcpStream, node: NodeHandle) {
+    let response = match read_request(&mut stream).await {
+        Ok(body) => match serde_json::from_slice::<Value>(&body) {
+            Ok(request) => dispatch(&node, request).await,
+            Err(e) => Some(error_response(Value::Null, RpcError::new(PARSE_ERROR, e.to_string()))),
+        },
+        Err(status) => {
+            let _ = write_response(&mut stream, status, None).await;
+            return;
+        }
+    };
+    let _ = write_response(&mut stream, "200 OK", response).await;
+}
+
+/// Reads one HTTP request and returns its body, or the status line to reply with.
+async fn read_request(stream: &mut TcpStream) -> Result<Vec<u8>, &'static str> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 4096];
+    let header_end = loop {
+        if let Some(end) = find(&buffer, b"\r\n\r\n") {
+            break end;
+        }
+        if buffer.len() > MAX_HEADER_BYTES {
+            return Err("431 Request Header Fields Too Large");
+        }
+        match stream.read(&mut chunk).await {
+            Ok(0) | Err(_) => return Err("400 Bad Request"),
+            Ok(read) => buffer.extend_from_slice(&chunk[..read]),
+        }
+    };
+
+    let head = String::from_utf8_lossy(&buffer[..header_end]).to_string();
+    let mut lines = head.lines();
+    if !lines.next().is_some_and(|line| line.starts_with("POST ")) {
+        return Err("405 Method Not Allowed");
+    }
+    let length = lines
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+        .ok_or("411 Length Required")?;
+    if length > MAX_BODY_BYTES {
+        return Err("413 Payload Too Large");
+    }
+
+    let mut body = buffer.split_off(header_end + 4);
+    while body.len() < length {
+        match stream.read(&mut chunk).await {
+            Ok(0) | Err(_) => return Err("400 Bad Request"),
+            Ok(read) => body.extend_from_slice(&chunk[..read]),
+        }
+    }
+    body.truncate(length);
+    Ok(body)
+}
+
+async fn write_response(stream: &mut TcpStream, status: &str, body: Option<Value>) -> io::Result<()> {
+    // Notifications get no JSON-RPC response at all.
+    let (status, body) = match body {
+        Some(body) => (status, body.to_string()),
+        None if status.starts_with("200") => ("204 No Content", String::new()),
+        None => (status, String::new()),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+/// Handles a request or a batch; returns `None` when nothing needs a reply.
+async fn dispatch(node: &NodeHandle, request: Value) -> Option<Value> {
+    let Value::Array(batch) = request else {
+        return handle_request(node, request).await;
+    };
+    if batch.is_empty() {
+        return Some(error_response(Value::Null, RpcError::new(INVALID_REQUEST, "empty batch")));
+    }
+    let mut responses = Vec::new();
+    for request in batch {
+        responses.extend(handle_request(node, request).await);
+    }
+    (!responses.is_empty()).then_some(Value::Array(responses))
+}
+
+async fn handle_request(node: &NodeHandle, request: Value) -> Option<Value> {
+    #[derive(Deserialize)]
+    struct Request {
+        jsonrpc: String,
+        method: String,
+        #[serde(default)]
+        params: Value,
+    }
+
+    let id = request.get("id").cloned();
+    let request = match serde_json::from_value::<Request>(request) {
+        Ok(request) if request.jsonrpc == "2.0" => request,
+        _ => {
+            return Some(error_response(
+                id.unwrap_or(Value::Null),
+                RpcError::new(INVALID_REQUEST, "invalid request"),
+            ));
+        }
+    };
+    let result = call(node, &request.method, request.params).await;
+    // A request without an id is a notification.
+    let id = id?;
+    Some(match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "result": result, "id": id }),
+        Err(error) => error_response(id, error),
+    })
+}
+
+fn error_response(id: Value, error: RpcError) -> Value {
+    json!({ "jsonrpc": "2.0", "error": error, "id": id })
+}
+
+fn params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))
+}
+
+async fn call(node: &NodeHandle, method: &str, params_value: Value) -> Result<Value, RpcError> {
+    let node_error = |e: crate::models::node::NodeError| RpcError::new(NODE_ERROR, e.to_string());
+    match method {
+        "submit_transaction" => {
+            #[derive(Deserialize)]
+            struct Params {
+                sender: String,
+                receiver: String,
+                amount: f64,
+                timestamp: Option<u64>,
+                #[serde(default)]
+                outputs: Vec<TxOutput>,
+                #[serde(default)]
+                fee: f64,
+                lock_height: Option<u64>,
+                lock_time: Option<u64>,
+            }
+            let p: Params = params(params_value)?;
+            let mut transaction = match p.timestamp {
+                Some(timestamp) => Transaction::new_at(p.sender, p.receiver, p.amount, timestamp),
+                None => Transaction::new(p.sender, p.receiver, p.amount),
+            }
+            .with_outputs(p.outputs)
+            .with_fee(p.fee);
+            transaction.lock_height = p.lock_height;
+            transaction.lock_time = p.lock_time;
+            let hash = transaction.hash();
+            node.submit_transaction(transaction).await.map_err(node_error)?;
+            Ok(json!({ "hash": hex::encode(&hash) }))
+        }
+        "submit_raw_transaction" => {
+            #[derive(Deserialize)]
+            struct Params {
+                transaction: Transaction,
+            }
+            let p: Params = params(params_value)?;
+            let hash = p.transaction.hash();
+            node.submit_transaction(p.transaction).await.map_err(node_error)?;
+            Ok(json!({ "hash": hex::encode(&hash) }))
+        }
+        "get_balance" => {
+            #[derive(Deserialize)]
+            struct Params {
+                address: String,
+            }
+            let p: Params = params(params_value)?;
+            let status = node.status().await.map_err(node_error)?;
+            let balance = status.blockchain.get_balance(&p.address);
+            Ok(json!({ "address": p.address, "balance": balance }))
+        }
+        "get_block" => {
+            #[derive(Deserialize)]
+            #[serde(untagged)]
+            enum Params {
+                Height { height: u64 },
+                Hash { hash: String },
+            }
+            let query: Params = params(params_value)?;
+            let status = node.status().await.map_err(node_error)?;
+            let block = match query {
+                Params::Height { height } => status.blockchain.blocks.get(height as usize).cloned(),
+                Params::Hash { hash } => {
+                    let hash = hex::decode_hash(&hash)
+                        .ok_or_else(|| RpcError::new(INVALID_PARAMS, "hash must be 64 hex digits"))?;
+                    status.blockchain.blocks.iter().find(|block| block.hash == hash).cloned()
+                }
+            };
+            let block = block.ok_or_else(|| RpcError::new(NOT_FOUND, "block not found"))?;
+            Ok(json!(block))
+        }
+        "get_chain_tip" => {
+            let status = node.status().await.map_err(node_error)?;
+            let tip = status.blockchain.blocks.last().expect("chain has a genesis block");
+            Ok(json!({ "height": tip.index, "hash": hex::encode(&tip.hash) }))
+        }
+        "get_chain" => {
+            let status = node.status().await.map_err(node_error)?;
+            Ok(json!(*status.blockchain.blocks))
+        }
+        "submit_chain" => {
+            #[derive(Deserialize)]
+            struct Params {
+                blocks: Vec<Block>,
+            }
+            let p: Params = params(params_value)?;
+            let adopted = node.import_chain(Arc::new(p.blocks)).await.map_err(node_error)?;
+            Ok(json!({ "adopted": adopted }))
+        }
+        "get_anchor" => {
+            #[derive(Deserialize)]
+            struct Params {
+                hash: String,
+            }
+            let p: Params = params(params_value)?;
+            let hash =
+                hex::decode_hash(&p.hash).ok_or_else(|| RpcError::new(INVALID_PARAMS, "hash must be 64 hex digits"))?;
+            let status = node.status().await.map_err(node_error)?;
+            let proof = status.blockchain.find_anchor(&hash);
+            let proof = proof.ok_or_else(|| RpcError::new(NOT_FOUND, "hash was never anchored"))?;
+            Ok(json!(proof))
+        }
+        "get_mempool" => {
+            let status = node.status().await.map_err(node_error)?;
+            Ok(json!(status.blockchain.pending_transactions))
+        }
+        "get_peers" => {
+            let status = node.status().await.map_err(node_error)?;
+            Ok(json!(status.peers))
+        }
+        "start_mining" | "stop_mining" => {
+            let enabled = method == "start_mining";
+            node.set_mining(enabled).map_err(node_error)?;
+            Ok(json!({ "mining": enabled }))
+        }
+        _ => Err(RpcError::new(METHOD_NOT_FOUND, format!("unknown method {}", method))),
+    }
+}
+
+#[derive(Debug)]
+pub enum ClientError {
+    Io(io::Error),
+    /// The server answered with something other than a JSON-RPC response.
+    InvalidResponse(String),
+    Rpc(RpcError),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Io(e) => write!(f, "could not reach node: {}", e),
+            ClientError::InvalidResponse(e) => write!(f, "invalid response: {}", e),
+            ClientError::Rpc(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Calls a node's API over HTTP, one connection per call.
+pub struct RpcClient {
+    address: String,
+    next_id: AtomicU64,
+}
+
+impl RpcClient {
+    pub fn new(address: impl Into<String>) -> Self {
+        RpcClient {
+            address: address.into(),
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    pub async fn call(&self, method: &str, params: Value) -> Result<Value, ClientError> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let request = json!({ "jsonrpc": "2.0", "method": method, "params": params, "id": id });
+        let (status, body) = self.post(&request.to_string()).await?;
+        if !status.starts_with("200") {
+            return Err(ClientError::InvalidResponse(format!("HTTP {}", status)));
+        }
+        let mut response: Value =
+            serde_json::from_str(&body).map_err(|e| ClientError::InvalidResponse(e.to_string()))?;
+        if let Some(error) = response.get_mut("error") {
+            let error = serde_json::from_value(error.take()).map_err(|e| ClientError::InvalidResponse(e.to_string()))?;
+            return Err(ClientError::Rpc(error));
+        }
+        response
+            .get_mut("result")
+            .map(Value::take)
+            .ok_or_else(|| ClientError::InvalidResponse("missing result".to_string()))
+    }
+
+    /// Posts a raw body and returns the HTTP status and response body.
+    pub async fn post(&self, body: &str) -> Result<(String, String), ClientError> {
+        let mut stream = TcpStream::connect(&self.address).await.map_err(ClientError::Io)?;
+        let request = format!(
+            "POST / HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.address,
+            body.len(),
+            body
+        );
+        stream.write_all(request.as_bytes()).await.map_err(ClientError::Io)?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await.map_err(ClientError::Io)?;
+
+        let response = String::from_utf8(response).map_err(|e| ClientError::InvalidResponse(e.to_string()))?;
+        let (head, body) = response
+            .split_once("\r\n\r\n")
+            .ok_or_else(|| ClientError::InvalidResponse("malformed HTTP response".to_string()))?;
+        let status = head
+            .lines()
+            .next()
+            .and_then(|line| line.split_once(' '))
+            .map(|(_, status)| status.to_string())
+            .ok_or_else(|| ClientError::InvalidResponse("missing status line".to_string()))?;
+        Ok((status, body.to_string()))
+    }
+}
@@ -0,0 +1,570 @@
+//! Declarative network experiments.
+//!
+//! A scenario names the nodes and links of a network, a list of timed steps to
+//! play against it, and assertions to check once every step has run and the
+//! network has settled. Scenarios are written in TOML or JSON:
+//!
+//! ```toml
+//! name = "partition heals"
+//! difficulty = 1
+//! nodes = ["A", "B", "C"]
+//!
+//! [default_link]
+//! latency_ms = 5
+//!
+//! [[links]]
+//! from = "A"
+//! to = "B"
+//!
+//! [[links]]
+//! from = "B"
+//! to = "C"
+//! latency_ms = 20
+//!
+//! [[steps]]
+//! at_ms = 0
+//! action = "transaction"
+//! node = "A"
+//! sender = "Alice"
+//! receiver = "Bob"
+//! amount = 10.0
+//!
+//! [[steps]]
+//! at_ms = 50
+//! action = "mine"
+//! node = "A"
+//!
+//! [[assertions]]
+//! kind = "balance"
+//! address = "Bob"
+//! equals = 110.0
+//!
+//! [[assertions]]
+//! kind = "tips_agree"
+//! ```
+//!
+//! Accounts may be written as names such as "Alice"; anything that is not a
+//! valid address stands for `address::from_name` of it.
+//!
+//! Nodes can also come from a `[topology]` table (`kind` is one of
+//! `full_mesh`, `ring`, `star`, `line`, `random_regular`, `erdos_renyi` or
+//! `file`), with `links` adding to it.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::models::simulator::{LinkConfig, LinkModel};
+use crate::models::topology::{Topology, TopologyError};
+use crate::models::{Block, Network, Transaction, address, hex};
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default = "default_difficulty")]
+    pub difficulty: usize,
+    /// Seeds the link model's delays and losses.
+    #[serde(default)]
+    pub seed: u64,
+    #[serde(default)]
+    pub topology: Option<TopologySpec>,
+    #[serde(default)]
+    pub nodes: Vec<String>,
+    #[serde(default)]
+    pub default_link: LinkSpec,
+    #[serde(default)]
+    pub links: Vec<Link>,
+    #[serde(default)]
+    pub steps: Vec<Step>,
+    #[serde(default)]
+    pub assertions: Vec<Assertion>,
+}
+
+fn default_difficulty() -> usize {
+    1
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum TopologySpec {
+    FullMesh { count: usize },
+    Ring { count: usize },
+    Star { count: usize },
+    Line { count: usize },
+    RandomRegular { count: usize, degree: usize, seed: u64 },
+    ErdosRenyi { count: usize, probability: f64, seed: u64 },
+    /// A JSON or edge-list file, relative to the scenario file.
+    File { path: PathBuf },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LinkSpec {
+    pub latency_ms: u64,
+    pub jitter_ms: u64,
+    pub drop_rate: f64,
+    pub reorder_rate: f64,
+}
+
+impl From<LinkSpec> for LinkConfig {
+    fn from(spec: LinkSpec) -> Self {
+        LinkConfig {
+            latency: Duration::from_millis(spec.latency_ms),
+            jitter: Duration::from_millis(spec.jitter_ms),
+            drop_rate: spec.drop_rate,
+            reorder_rate: spec.reorder_rate,
+        }
+    }
+}
+
+/// Connects two nodes. Any delivery settings given replace the default link's.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Link {
+    pub from: String,
+    pub to: String,
+    pub latency_ms: Option<u64>,
+    pub jitter_ms: Option<u64>,
+    pub drop_rate: Option<f64>,
+    pub reorder_rate: Option<f64>,
+}
+
+impl Link {
+    fn config(&self, default: LinkSpec) -> Option<LinkConfig> {
+        if self.latency_ms.is_none() && self.jitter_ms.is_none() && self.drop_rate.is_none() && self.reorder_rate.is_none() {
+            return None;
+        }
+        Some(
+            LinkSpec {
+                latency_ms: self.latency_ms.unwrap_or(default.latency_ms),
+                jitter_ms: self.jitter_ms.unwrap_or(default.jitter_ms),
+                drop_rate: self.drop_rate.unwrap_or(default.drop_rate),
+                reorder_rate: self.reorder_rate.unwrap_or(default.reorder_rate),
+            }
+            .into(),
+        )
+    }
+}
+
+/// An action played `at_ms` milliseconds after the scenario starts. Steps at
+/// the same time run in the order they are written.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Step {
+    #[serde(default)]
+    pub at_ms: u64,
+    #[serde(flatten)]
+    pub action: Action,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Action {
+    Transaction { node: String, sender: String, receiver: String, amount: f64 },
+    /// Mines the node's mempool and broadcasts the block.
+    Mine { node: String },
+    BroadcastChain { node: String },
+    Partition { groups: Vec<Vec<String>> },
+    Heal,
+    Connect { from: String, to: String },
+    Disconnect { from: String, to: String },
+    AddNode { node: String },
+    StopNode { node: String },
+    RestartNode { node: String },
+    /// Waits until every message in flight has been delivered.
+    Settle,
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Transaction { node, sender, receiver, amount } => {
+                write!(f, "transaction {} -> {} : {} on {}", sender, receiver, amount, node)
+            }
+            Action::Mine { node } => write!(f, "mine on {}", node),
+            Action::BroadcastChain { node } => write!(f, "broadcast chain from {}", node),
+            Action::Partition { groups } => write!(f, "partition {:?}", groups),
+            Action::Heal => write!(f, "heal"),
+            Action::Connect { from, to } => write!(f, "connect {} to {}", from, to),
+            Action::Disconnect { from, to } => write!(f, "disconnect {} from {}", from, to),
+            Action::AddNode { node } => write!(f, "add node {}", node),
+            Action::StopNode { node } => write!(f, "stop node {}", node),
+            Action::RestartNode { node } => write!(f, "restart node {}", node),
+            Action::Settle => write!(f, "settle"),
+        }
+    }
+}
+
+/// A check on the network's final state. Leaving out `node` checks every node.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Assertion {
+    Balance { node: Option<String>, address: String, equals: f64 },
+    Height { node: Option<String>, equals: u64 },
+    /// The named nodes, or every running node, share the same chain tip.
+    TipsAgree { nodes: Option<Vec<String>> },
+}
+
+impl fmt::Display for Assertion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let on = |node: &Option<String>| node.clone().unwrap_or_else(|| "every node".to_string());
+        match self {
+            Assertion::Balance { node, address, equals } => {
+                write!(f, "balance of {} is {} on {}", address, equals, on(node))
+            }
+            Assertion::Height { node, equals } => write!(f, "height is {} on {}", equals, on(node)),
+            Assertion::TipsAgree { nodes: Some(nodes) } => write!(f, "{} agree on the tip", nodes.join(", ")),
+            Assertion::TipsAgree { nodes: None } => write!(f, "every node agrees on the tip"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ScenarioError {
+    Io(std::io::Error),
+    Parse(String),
+    Topology(TopologyError),
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScenarioError::Io(e) => write!(f, "could not read scenario: {}", e),
+            ScenarioError::Parse(e) => write!(f, "could not parse scenario: {}", e),
+            ScenarioError::Topology(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AssertionResult {
+    pub assertion: String,
+    pub passed: bool,
+    /// What was actually found, when the assertion failed.
+    pub detail: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ScenarioReport {
+    pub name: String,
+    /// Steps the network refused, such as a transaction with insufficient balance.
+    pub step_errors: Vec<String>,
+    pub assertions: Vec<AssertionResult>,
+}
+
+impl ScenarioReport {
+    pub fn passed(&self) -> bool {
+        self.assertions.iter().all(|result| result.passed)
+    }
+}
+
+impl fmt::Display for ScenarioReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Scenario: {}", if self.name.is_empty() { "(unnamed)" } else { &self.name })?;
+        for error in &self.step_errors {
+            writeln!(f, "  step failed: {}", error)?;
+        }
+        for result in &self.assertions {
+            let mark = if result.passed { "PASS" } else { "FAIL" };
+            write!(f, "  {} {}", mark, result.assertion)?;
+            if let Some(detail) = &result.detail {
+                write!(f, " ({})", detail)?;
+            }
+            writeln!(f)?;
+        }
+        let passed = self.assertions.iter().filter(|result| result.passed).count();
+        write!(f, "{}/{} assertions passed", passed, self.assertions.len())
+    }
+}
+
+impl Scenario {
+    pub fn from_toml(text: &str) -> Result<Self, ScenarioError> {
+        toml::from_str(text).map_err(|e| ScenarioError::Parse(e.to_string()))
+    }
+
+    pub fn from_json(text: &str) -> Result<Self, ScenarioError> {
+        serde_json::from_str(text).map_err(|e| ScenarioError::Parse(e.to_string()))
+    }
+
+    /// Loads a `.json` file as JSON and anything else as TOML. A topology file
+    /// named in the scenario is resolved relative to it.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ScenarioError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(ScenarioError::Io)?;
+        let mut scenario = if path.extension().is_some_and(|extension| extension == "json") {
+            Scenario::from_json(&text)?
+        } else {
+            Scenario::from_toml(&text)?
+        };
+        if let Some(TopologySpec::File { path: topology }) = &mut scenario.topology
+            && let Some(directory) = path.parent()
+        {
+            *topology = directory.join(&*topology);
+        }
+        Ok(scenario)
+    }
+
+    /// The starting network: the topology, plus the listed nodes and links.
+    pub fn build_topology(&self) -> Result<Topology, ScenarioError> {
+        let mut topology = match &self.topology {
+            None => Topology::default(),
+            Some(TopologySpec::FullMesh { count }) => Topology::full_mesh(*count),
+            Some(TopologySpec::Ring { count }) => Topology::ring(*count),
+            Some(TopologySpec::Star { count }) => Topology::star(*count),
+            Some(TopologySpec::Line { count }) => Topology::line(*count),
+            Some(TopologySpec::RandomRegular { count, degree, seed }) => {
+                Topology::random_regular(*count, *degree, *seed).map_err(ScenarioError::Topology)?
+            }
+            Some(TopologySpec::ErdosRenyi { count, probability, seed }) => {
+                Topology::erdos_renyi(*count, *probability, *seed)
+            }
+            Some(TopologySpec::File { path }) => Topology::load(path).map_err(ScenarioError::Topology)?,
+        };
+        for node in &self.nodes {
+            if !topology.nodes.contains(node) {
+                topology.nodes.push(node.clone());
+            }
+        }
+        for link in &self.links {
+            topology.connect(&link.from, &link.to);
+        }
+        Ok(topology)
+    }
+
+    /// Builds the network, plays every step on schedule, waits for the network
+    /// to settle and checks the assertions.
+    pub async fn run(&self) -> Result<ScenarioReport, ScenarioError> {
+        let topology = self.build_topology()?;
+        let mut links = LinkModel::new(self.seed).with_default_link(self.default_link.into());
+        for link in &self.links {
+            if let Some(config) = link.config(self.default_link) {
+                links.set_link(&link.from, &link.to, config);
+            }
+        }
+        let network = Network::from_topology_with_link_model(&topology, self.difficulty, links).await;
+
+        let mut steps: Vec<&Step> = self.steps.iter().collect();
+        steps.sort_by_key(|step| step.at_ms);
+        let started = tokio::time::Instant::now();
+        let mut step_errors = Vec::new();
+        for step in steps {
+            tokio::time::sleep_until(started + Duration::from_millis(step.at_ms)).await;
+            if let Err(e) = self.play(&network, &step.action).await {
+                step_errors.push(format!("{} at {}ms: {}", step.action, step.at_ms, e));
+            }
+        }
+        network.settle().await;
+
+        let mut assertions = Vec::new();
+        for assertion in &self.assertions {
+            let failure = check(&network, assertion).await;
+            assertions.push(AssertionResult {
+                assertion: assertion.to_string(),
+                passed: failure.is_none(),
+                detail: failure,
+            });
+        }
+        Ok(ScenarioReport {
+            name: self.name.clone(),
+            step_errors,
+            assertions,
+        })
+    }
+
+    async fn play(&self, network: &Network, action: &Action) -> Result<(), String> {
+        match action {
+            Action::Transaction { node, sender, receiver, amount } => {
+                let transaction = Transaction::new(account(sender), account(receiver), *amount);
+                network.add_transaction_to_node(node, transaction).await.map_err(|e| e.to_string())
+            }
+            Action::Mine { node } => {
+                let block = network.mine_block(node).await.map_err(|e| e.to_string())?;
+                network.broadcast_block(node, block).await;
+                Ok(())
+            }
+            Action::BroadcastChain { node } => {
+                network.broadcast_chain(node).await;
+                Ok(())
+            }
+            Action::Partition { groups } => {
+                network.partition(groups.clone());
+                Ok(())
+            }
+            Action::Heal => {
+                network.heal();
+                Ok(())
+            }
+            Action::Connect { from, to } => {
+                network.connect_nodes(from, to).await;
+                Ok(())
+            }
+            Action::Disconnect { from, to } => {
+                network.disconnect_nodes(from, to).await;
+                Ok(())
+            }
+            Action::AddNode { node } => {
+                network.add_node(node.clone(), self.difficulty).await;
+                Ok(())
+            }
+            Action::StopNode { node } => network.stop_node(node).await.map_err(|e| e.to_string()),
+            Action::RestartNode { node } => network.restart_node(node).await.map_err(|e| e.to_string()),
+            Action::Settle => {
+                network.settle().await;
+                Ok(())
+            }
+        }
+    }
+}
+
+fn account(name: &str) -> String {
+    if address::is_valid(name) {
+        name.to_string()
+    } else {
+        address::from_name(name)
+    }
+}
+
+/// Returns why the assertion failed, or `None` if it holds.
+async fn check(network: &Network, assertion: &Assertion) -> Option<String> {
+    let statuses = network.statuses().await;
+    let selected = |node: &Option<String>| -> Result<Vec<_>, String> {
+        match node {
+            None => Ok(statuses.iter().collect()),
+            Some(id) => statuses
+                .iter()
+                .find(|status| &status.id == id)
+                .map(|status| vec![status])
+                .ok_or_else(|| format!("{} is not running", id)),
+        }
+    };
+    let mismatches: Vec<String> = match assertion {
+        Assertion::Balance { node, address, equals } => match selected(node) {
+            Err(e) => return Some(e),
+            Ok(statuses) => statuses
+                .into_iter()
+                .map(|status| (status, status.blockchain.get_balance(&account(address))))
+                .filter(|(_, balance)| balance != equals)
+                .map(|(status, balance)| format!("{} on {}", balance, status.id))
+                .collect(),
+        },
+        Assertion::Height { node, equals } => match selected(node) {
+            Err(e) => return Some(e),
+            Ok(statuses) => statuses
+                .into_iter()
+                .map(|status| (status, status.blockchain.blocks.len() as u64 - 1))
+                .filter(|(_, height)| height != equals)
+                .map(|(status, height)| format!("{} on {}", height, status.id))
+                .collect(),
+        },
+        Assertion::TipsAgree { nodes } => {
+            let tips: Vec<(&str, &Block)> = statuses
+                .iter()
+                .filter(|status| nodes.as_ref().is_none_or(|nodes| nodes.contains(&status.id)))
+                .map(|status| (status.id.as_str(), status.blockchain.blocks.last().unwrap()))
+                .collect();
+            if tips.windows(2).all(|pair| pair[0].1.hash == pair[1].1.hash) {
+                Vec::new()
+            } else {
+                tips.iter()
+                    .map(|(id, tip)| format!("#{} {} on {}", tip.index, hex::encode(&tip.hash[..4]), id))
+                    .collect()
+            }
+        }
+    };
+    (!mismatches.is_empty()).then(|| format!("found {}", mismatches.join(", ")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PARTITION: &str = r#"
+        name = "partition and heal"
+        nodes = ["A", "B", "C"]
+
+        [[links]]
+        from = "A"
+        to = "B"
+
+        [[links]]
+        from = "B"
+        to = "C"
+        latency_ms = 5
+
+        [[steps]]
+        action = "partition"
+        groups = [["A", "B"], ["C"]]
+
+        [[steps]]
+        at_ms = 10
+        action = "transaction"
+        node = "A"
+        sender = "Alice"
+        receiver = "Bob"
+        amount = 10.0
+
+        [[steps]]
+        at_ms = 20
+        action = "mine"
+        node = "A"
+
+        [[steps]]
+        at_ms = 40
+        action = "heal"
+
+        [[steps]]
+        at_ms = 40
+        action = "broadcast_chain"
+        node = "B"
+
+        [[assertions]]
+        kind = "balance"
+        address = "Bob"
+        equals = 110.0
+
+        [[assertions]]
+        kind = "tips_agree"
+
+        [[assertions]]
+        kind = "height"
+        node = "C"
+        equals = 2
+    "#;
+
+    #[tokio::test]
+    async fn test_run_reports_each_assertion() {
+        let scenario = Scenario::from_toml(PARTITION).unwrap();
+        let report = scenario.run().await.unwrap();
+        assert!(report.step_errors.is_empty());
+        assert_eq!(report.assertions.len(), 3);
+        assert!(report.assertions[0].passed);
+        assert!(report.assertions[1].passed);
+        assert!(!report.assertions[2].passed);
+        assert_eq!(report.assertions[2].detail.as_deref(), Some("found 1 on C"));
+        assert!(!report.passed());
+    }
+
+    #[tokio::test]
+    async fn test_json_scenario_with_generated_topology() {
+        let json = r#"{
+            "topology": { "kind": "star", "count": 4 },
+            "steps": [
+                { "action": "transaction", "node": "Node_1", "sender": "Alice", "receiver": "Bob", "amount": 500.0 },
+                { "action": "mine", "node": "Node_1" }
+            ],
+            "assertions": [{ "kind": "height", "equals": 0 }, { "kind": "tips_agree", "nodes": ["Node_0", "Node_3"] }]
+        }"#;
+        let report = Scenario::from_json(json).unwrap().run().await.unwrap();
+        assert_eq!(report.step_errors.len(), 2);
+        assert!(report.passed());
+    }
+
+    #[test]
+    fn test_rejects_unknown_fields() {
+        assert!(Scenario::from_toml("nodes = [\"A\"]\ncolour = \"blue\"").is_err());
+        assert!(Scenario::from_toml("[[steps]]\naction = \"explode\"").is_err());
+    }
+}
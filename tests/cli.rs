@@ -0,0 +1,197 @@
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::process::{Child, Command, Output, Stdio};
+use std::time::{Duration, Instant};
+
+use serde_json::{Value, json};
+
+use decentralized_ledger::models::blockchain::GENESIS_TIMESTAMP;
+use decentralized_ledger::models::{Blockchain, Transaction, address};
+use decentralized_ledger::models::wallet::Wallet;
+
+const BINARY: &str = env!("CARGO_BIN_EXE_decentralized-ledger");
+
+/// A `node run` process, killed when dropped.
+struct RunningNode {
+    child: Child,
+    rpc: String,
+}
+
+impl RunningNode {
+    fn start(id: &str, flags: &[&str]) -> Self {
+        let mut child = Command::new(BINARY)
+            .args(["--json", "--rpc", "127.0.0.1:0", "node", "run", "--difficulty", "1", "--id", id])
+            .args(flags)
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut line = String::new();
+        BufReader::new(child.stdout.take().unwrap()).read_line(&mut line).unwrap();
+        let started: Value = serde_json::from_str(&line).unwrap();
+        RunningNode {
+            child,
+            rpc: started["rpc"].as_str().unwrap().to_string(),
+        }
+    }
+
+    fn run(&self, args: &[&str]) -> Output {
+        ledger(&[&["--json", "--rpc", &self.rpc], args].concat())
+    }
+}
+
+impl Drop for RunningNode {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn ledger(args: &[&str]) -> Output {
+    Command::new(BINARY).args(args).output().unwrap()
+}
+
+fn json_output(output: &Output) -> Value {
+    serde_json::from_slice(&output.stdout).unwrap()
+}
+
+fn temp_file(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("ledger-cli-{}-{}", std::process::id(), name))
+}
+
+#[test]
+fn test_transactions_balances_and_blocks() {
+    let node = RunningNode::start("cli-a", &[]);
+    let (alice, bob) = (address::from_name("Alice"), address::from_name("Bob"));
+
+    let sent = node.run(&["tx", "send", &alice, &bob, "25"]);
+    assert_eq!(sent.status.code(), Some(0));
+    assert_eq!(json_output(&sent)["hash"].as_str().unwrap().len(), 64);
+
+    let refused = node.run(&["tx", "send", &alice, &bob, "500"]);
+    assert_eq!(refused.status.code(), Some(1));
+    assert_eq!(json_output(&refused)["error"], json!("invalid transaction: insufficient balance"));
+
+    let balance = node.run(&["balance", &bob]);
+    assert_eq!(json_output(&balance)["balance"], json!(100.0));
+
+    let genesis = node.run(&["block", "show", "0"]);
+    assert_eq!(json_output(&genesis)["index"], json!(0));
+    let hash = json_output(&genesis)["hash"].as_str().unwrap().to_string();
+    assert_eq!(json_output(&node.run(&["block", "show", &hash])), json_output(&genesis));
+    assert_eq!(node.run(&["block", "show", "7"]).status.code(), Some(1));
+
+    let text = ledger(&["--rpc", &node.rpc, "balance", &alice]);
+    assert_eq!(String::from_utf8(text.stdout).unwrap().trim(), format!("{}: 100.0", alice));
+
+    let misspelt = node.run(&["tx", "send", "Alice", &bob, "1"]);
+    assert_eq!(misspelt.status.code(), Some(1));
+}
+
+#[test]
+fn test_send_signed_with_a_keystore() {
+    let node = RunningNode::start("cli-wallet", &[]);
+    let mut wallet = Wallet::generate();
+    let sender = wallet.new_address();
+    let bob = address::from_name("Bob");
+    let keystore = temp_file("keystore.json");
+    std::fs::write(&keystore, serde_json::to_string(&wallet.encrypt("hunter2", 1)).unwrap()).unwrap();
+    let keystore = keystore.to_str().unwrap();
+    let send = [&["--json", "--rpc", &node.rpc, "tx", "send", &sender, &bob, "25"][..], &["--keystore", keystore]].concat();
+
+    let unsigned = node.run(&["tx", "send", &sender, &bob, "25"]);
+    assert_eq!(json_output(&unsigned)["error"], json!("invalid transaction: invalid signature"));
+
+    let sent = Command::new(BINARY).args(&send).env("LEDGER_PASSWORD", "hunter2").output().unwrap();
+    assert_eq!(sent.status.code(), Some(0));
+    assert_eq!(json_output(&sent)["hash"].as_str().unwrap().len(), 64);
+
+    let mut wrong = Command::new(BINARY)
+        .args(&send)
+        .env_remove("LEDGER_PASSWORD")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    writeln!(wrong.stdin.take().unwrap(), "hunter3").unwrap();
+    let wrong = wrong.wait_with_output().unwrap();
+    assert_eq!(wrong.status.code(), Some(1));
+    assert!(json_output(&wrong)["error"].as_str().unwrap().ends_with("wrong password"));
+    let _ = std::fs::remove_file(keystore);
+}
+
+#[test]
+fn test_export_validate_and_import_chain() {
+    let source = RunningNode::start("cli-source", &["--mine"]);
+    source.run(&["tx", "send", &address::from_name("Alice"), &address::from_name("Bob"), "5"]);
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while source.run(&["block", "show", "1"]).status.code() != Some(0) {
+        assert!(Instant::now() < deadline, "node never mined");
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    let file = temp_file("chain.json");
+    let file = file.to_str().unwrap();
+
+    let exported = source.run(&["chain", "export", file]);
+    assert_eq!(exported.status.code(), Some(0));
+    assert_eq!(json_output(&exported)["length"], json!(2));
+
+    let target = RunningNode::start("cli-target", &[]);
+    assert_eq!(json_output(&target.run(&["chain", "import", file])), json!({ "adopted": true, "length": 2 }));
+    assert_eq!(json_output(&target.run(&["block", "show", "1"])), json_output(&source.run(&["block", "show", "1"])));
+    // Offering the same chain again is refused: it is no longer longer.
+    assert_eq!(target.run(&["chain", "import", file]).status.code(), Some(1));
+
+    let mut chain: Vec<Value> = serde_json::from_str(&std::fs::read_to_string(file).unwrap()).unwrap();
+    chain.push(chain[0].clone());
+    std::fs::write(file, serde_json::to_string(&chain).unwrap()).unwrap();
+    let invalid = ledger(&["--json", "chain", "validate", "--file", file]);
+    assert_eq!(invalid.status.code(), Some(1));
+    assert!(json_output(&invalid)["error"].as_str().unwrap().starts_with("chain is invalid"));
+
+    let valid = source.run(&["chain", "validate", "--difficulty", "1"]);
+    assert_eq!(json_output(&valid), json!({ "valid": true, "length": 2 }));
+    let _ = std::fs::remove_file(file);
+}
+
+#[test]
+fn test_validate_checks_proof_of_work_at_the_given_difficulty() {
+    let mut blockchain = Blockchain::new(0);
+    let transfer = Transaction::new_at(address::from_name("Alice"), address::from_name("Bob"), 5.0, 1);
+    blockchain.add_transaction(transfer).unwrap();
+    let block = blockchain.mine_block_at(GENESIS_TIMESTAMP + 100).unwrap();
+    assert_ne!(block.hash[0], 0, "the block must miss difficulty 1 for this test");
+    let file = temp_file("easy-chain.json");
+    std::fs::write(&file, serde_json::to_string(&*blockchain.blocks).unwrap()).unwrap();
+    let file = file.to_str().unwrap();
+
+    let easy = ledger(&["--json", "chain", "validate", "--file", file, "--difficulty", "0"]);
+    assert_eq!(json_output(&easy), json!({ "valid": true, "length": 2 }));
+    let hard = ledger(&["--json", "chain", "validate", "--file", file, "--difficulty", "1"]);
+    assert_eq!(hard.status.code(), Some(1));
+    assert_eq!(json_output(&hard)["error"], json!("chain is invalid: block hash does not meet the difficulty"));
+    let _ = std::fs::remove_file(file);
+}
+
+#[test]
+fn test_simulate_scenario_file() {
+    let scenario = concat!(env!("CARGO_MANIFEST_DIR"), "/scenarios/partition.toml");
+    let output = ledger(&["--json", "simulate", scenario]);
+    assert_eq!(output.status.code(), Some(0));
+    let report = json_output(&output);
+    assert_eq!(report["assertions"].as_array().unwrap().len(), 4);
+    assert!(report["step_errors"].as_array().unwrap().is_empty());
+
+    let file = temp_file("failing.toml");
+    std::fs::write(&file, "nodes = [\"A\"]\n[[assertions]]\nkind = \"height\"\nequals = 3\n").unwrap();
+    let output = ledger(&["simulate", file.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stderr).unwrap().contains("FAIL height is 3 on every node (found 0 on A)"));
+    let _ = std::fs::remove_file(file);
+}
+
+#[test]
+fn test_exit_codes_for_missing_files_nodes_and_usage() {
+    assert_eq!(ledger(&["chain", "validate", "--file", "/nonexistent/chain.json"]).status.code(), Some(3));
+    assert_eq!(ledger(&["--rpc", "127.0.0.1:1", "balance", "Bob"]).status.code(), Some(3));
+    assert_eq!(ledger(&["tx", "send", "Alice"]).status.code(), Some(2));
+}
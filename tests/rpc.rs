@@ -0,0 +1,173 @@
+use std::time::Duration;
+
+use serde_json::{Value, json};
+
+use decentralized_ledger::models::wallet::Wallet;
+use decentralized_ledger::models::{Network, address, hex};
+use decentralized_ledger::rpc::{self, ClientError, RpcClient, RpcServer};
+
+async fn serve(network: &Network, node_id: &str) -> (RpcServer, RpcClient) {
+    let server = RpcServer::bind("127.0.0.1:0", network.handle(node_id).unwrap()).await.unwrap();
+    let client = RpcClient::new(server.address().to_string());
+    (server, client)
+}
+
+async fn single_node() -> Network {
+    let network = Network::new();
+    network.add_node("A".to_string(), 1).await;
+    network
+}
+
+fn rpc_code(result: Result<Value, ClientError>) -> i64 {
+    match result {
+        Err(ClientError::Rpc(error)) => error.code,
+        other => panic!("expected an RPC error, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_submit_transaction_reaches_mempool() {
+    let (alice, bob) = (address::from_name("Alice"), address::from_name("Bob"));
+    let network = single_node().await;
+    let (_server, client) = serve(&network, "A").await;
+
+    let submitted = client
+        .call("submit_transaction", json!({ "sender": alice, "receiver": bob, "amount": 10.0, "timestamp": 7 }))
+        .await
+        .unwrap();
+    let mempool = client.call("get_mempool", Value::Null).await.unwrap();
+    assert_eq!(mempool, json!([{ "sender": alice, "receiver": bob, "amount": 10.0, "timestamp": 7 }]));
+    assert_eq!(submitted["hash"].as_str().unwrap().len(), 64);
+
+    let refused = client
+        .call("submit_transaction", json!({ "sender": alice, "receiver": bob, "amount": 1000.0 }))
+        .await;
+    match refused {
+        Err(ClientError::Rpc(error)) => {
+            assert_eq!(error.code, rpc::NODE_ERROR);
+            assert_eq!(error.message, "invalid transaction: insufficient balance");
+        }
+        other => panic!("expected an RPC error, got {:?}", other),
+    }
+
+    let misspelt = client
+        .call("submit_transaction", json!({ "sender": "Alice", "receiver": bob, "amount": 1.0 }))
+        .await;
+    assert_eq!(rpc_code(misspelt), rpc::NODE_ERROR);
+}
+
+#[tokio::test]
+async fn test_submit_raw_transaction_signed_by_a_wallet() {
+    let mut wallet = Wallet::generate();
+    let (sender, bob) = (wallet.new_address(), address::from_name("Bob"));
+    let network = single_node().await;
+    let (_server, client) = serve(&network, "A").await;
+
+    let unsigned = client
+        .call("submit_transaction", json!({ "sender": sender, "receiver": bob, "amount": 25.0 }))
+        .await;
+    assert_eq!(rpc_code(unsigned), rpc::NODE_ERROR);
+
+    let transaction = wallet.build_transaction_at(&sender, &bob, 25.0, 7).unwrap();
+    let submitted = client
+        .call("submit_raw_transaction", json!({ "transaction": transaction }))
+        .await
+        .unwrap();
+    assert_eq!(submitted["hash"], json!(hex::encode(&transaction.hash())));
+    let mempool = client.call("get_mempool", Value::Null).await.unwrap();
+    assert_eq!(mempool, json!([transaction]));
+
+    let mut forged = transaction.clone();
+    forged.amount = 75.0;
+    let forged = client.call("submit_raw_transaction", json!({ "transaction": forged })).await;
+    assert_eq!(rpc_code(forged), rpc::NODE_ERROR);
+    let malformed = client.call("submit_raw_transaction", json!({ "transaction": { "sender": sender } })).await;
+    assert_eq!(rpc_code(malformed), rpc::INVALID_PARAMS);
+}
+
+#[tokio::test]
+async fn test_blocks_by_height_and_hash() {
+    let (alice, bob) = (address::from_name("Alice"), address::from_name("Bob"));
+    let network = single_node().await;
+    let (_server, client) = serve(&network, "A").await;
+    client
+        .call("submit_transaction", json!({ "sender": alice, "receiver": bob, "amount": 30.0 }))
+        .await
+        .unwrap();
+    let block = network.mine_block("A").await.unwrap();
+
+    let tip = client.call("get_chain_tip", Value::Null).await.unwrap();
+    assert_eq!(tip, json!({ "height": 1, "hash": hex::encode(&block.hash) }));
+
+    let by_height = client.call("get_block", json!({ "height": 1 })).await.unwrap();
+    let by_hash = client.call("get_block", json!({ "hash": tip["hash"] })).await.unwrap();
+    assert_eq!(by_height, by_hash);
+    assert_eq!(serde_json::from_value::<decentralized_ledger::models::Block>(by_hash).unwrap(), block);
+
+    let balance = client.call("get_balance", json!({ "address": bob })).await.unwrap();
+    assert_eq!(balance["balance"], json!(130.0));
+
+    assert_eq!(rpc_code(client.call("get_block", json!({ "height": 9 })).await), rpc::NOT_FOUND);
+    assert_eq!(rpc_code(client.call("get_block", json!({ "hash": "zz" })).await), rpc::INVALID_PARAMS);
+}
+
+#[tokio::test]
+async fn test_start_and_stop_mining() {
+    let (alice, bob) = (address::from_name("Alice"), address::from_name("Bob"));
+    let network = single_node().await;
+    network.add_node("B".to_string(), 1).await;
+    network.connect_nodes("A", "B").await;
+    let (_server, client) = serve(&network, "A").await;
+
+    assert_eq!(client.call("get_peers", Value::Null).await.unwrap(), json!(["B"]));
+    assert_eq!(client.call("start_mining", Value::Null).await.unwrap(), json!({ "mining": true }));
+    client
+        .call("submit_transaction", json!({ "sender": alice, "receiver": bob, "amount": 1.0 }))
+        .await
+        .unwrap();
+
+    let mined = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let tip = client.call("get_chain_tip", Value::Null).await.unwrap();
+            if tip["height"] == json!(1) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await;
+    assert!(mined.is_ok(), "node never mined its mempool");
+    assert_eq!(client.call("stop_mining", Value::Null).await.unwrap(), json!({ "mining": false }));
+    assert!(!network.node_status("A").await.unwrap().mining);
+
+    network.settle().await;
+    assert_eq!(network.node_status("B").await.unwrap().blockchain.blocks.len(), 2);
+}
+
+#[tokio::test]
+async fn test_protocol_errors() {
+    let network = single_node().await;
+    let (_server, client) = serve(&network, "A").await;
+
+    assert_eq!(rpc_code(client.call("mine_everything", Value::Null).await), rpc::METHOD_NOT_FOUND);
+    assert_eq!(rpc_code(client.call("get_balance", json!({ "who": "Bob" })).await), rpc::INVALID_PARAMS);
+
+    let (status, body) = client.post("{not json").await.unwrap();
+    assert!(status.starts_with("200"));
+    assert_eq!(serde_json::from_str::<Value>(&body).unwrap()["error"]["code"], json!(rpc::PARSE_ERROR));
+
+    let batch = json!([
+        { "jsonrpc": "2.0", "method": "get_peers", "id": 1 },
+        { "jsonrpc": "2.0", "method": "start_mining" },
+        { "jsonrpc": "1.0", "method": "get_peers", "id": 2 },
+    ]);
+    let (_, body) = client.post(&batch.to_string()).await.unwrap();
+    let responses: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(responses[0], json!({ "jsonrpc": "2.0", "result": [], "id": 1 }));
+    assert_eq!(responses[1]["error"]["code"], json!(rpc::INVALID_REQUEST));
+    assert_eq!(responses.as_array().unwrap().len(), 2);
+
+    let (status, body) = client.post(r#"{"jsonrpc": "2.0", "method": "stop_mining"}"#).await.unwrap();
+    assert!(status.starts_with("204"));
+    assert!(body.is_empty());
+}
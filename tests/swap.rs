@@ -0,0 +1,64 @@
+use chrono::Utc;
+
+use decentralized_ledger::models::htlc::{self, ContractState};
+use decentralized_ledger::models::{Network, address};
+
+async fn chain(first: &str, second: &str) -> Network {
+    let network = Network::new();
+    network.add_node(first.to_string(), 1).await;
+    network.add_node(second.to_string(), 1).await;
+    network.connect_nodes(first, second).await;
+    network
+}
+
+async fn mine(network: &Network, node_id: &str) {
+    let block = network.mine_block(node_id).await.unwrap();
+    network.broadcast_block(node_id, block).await;
+    network.settle().await;
+}
+
+#[tokio::test]
+async fn test_atomic_swap_across_two_chains() {
+    let (alice, bob) = (address::from_name("Alice"), address::from_name("Bob"));
+    let (chain_a, chain_b) = (chain("A1", "A2").await, chain("B1", "B2").await);
+    let now = Utc::now().timestamp() as u64;
+
+    // Alice locks 30 A-coins for Bob behind a secret only Alice knows.
+    let secret = b"correct horse battery staple".to_vec();
+    let lock_a = htlc::lock_at(alice.clone(), bob.clone(), 30.0, htlc::hash_lock(&secret), now + 7_200, now);
+    chain_a.add_transaction_to_node("A1", lock_a.clone()).await.unwrap();
+    mine(&chain_a, "A1").await;
+
+    // Bob checks Alice's contract, then locks 20 B-coins for Alice under the
+    // same hash. It times out first, so Bob can still claim on A once Alice
+    // has claimed on B.
+    let status = chain_a.node_status("A2").await.unwrap();
+    let contract = status.blockchain.contracts.get(&lock_a.hash()).unwrap();
+    assert_eq!((contract.receiver.as_str(), contract.amount), (bob.as_str(), 30.0));
+    let lock_b = htlc::lock_at(bob.clone(), alice.clone(), 20.0, contract.hash_lock, now + 3_600, now);
+    chain_b.add_transaction_to_node("B1", lock_b.clone()).await.unwrap();
+    mine(&chain_b, "B1").await;
+
+    // Alice claims on B, which reveals the secret.
+    let claim_b = htlc::claim_at(&lock_b, secret, now + 1).unwrap();
+    chain_b.add_transaction_to_node("B2", claim_b).await.unwrap();
+    mine(&chain_b, "B2").await;
+
+    // Bob reads the secret off chain B and claims on A.
+    let status = chain_b.node_status("B1").await.unwrap();
+    let ContractState::Claimed { preimage } = &status.blockchain.contracts.get(&lock_b.hash()).unwrap().state else {
+        panic!("Alice's claim did not settle the contract on chain B");
+    };
+    let claim_a = htlc::claim_at(&lock_a, preimage.clone(), now + 2).unwrap();
+    chain_a.add_transaction_to_node("A1", claim_a).await.unwrap();
+    mine(&chain_a, "A1").await;
+
+    for (network, node, alice_balance, bob_balance) in
+        [(&chain_a, "A1", 70.0, 130.0), (&chain_a, "A2", 70.0, 130.0), (&chain_b, "B1", 120.0, 80.0), (&chain_b, "B2", 120.0, 80.0)]
+    {
+        let blockchain = network.node_status(node).await.unwrap().blockchain;
+        assert_eq!(blockchain.get_balance(&alice), alice_balance, "Alice on {}", node);
+        assert_eq!(blockchain.get_balance(&bob), bob_balance, "Bob on {}", node);
+        assert!(blockchain.validate_chain().is_ok());
+    }
+}